@@ -0,0 +1,341 @@
+use crate::live;
+use chrono::Utc;
+use pinyin::ToPinyin;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub enum Lookup<'a> {
+    Found(&'a str),
+    Ambiguous(Vec<(&'a str, &'a str, &'a str)>),
+    NotFound,
+}
+
+/// Lowercased `(plain, initials, full pinyin)` spellings of an area name,
+/// non-Chinese characters are kept as they are.
+fn spellings(name: &str) -> (String, String, String) {
+    let plain = name.to_lowercase();
+    let mut initials = String::new();
+    let mut full = String::new();
+    for (c, py) in plain.chars().zip(plain.as_str().to_pinyin()) {
+        match py {
+            Some(py) => {
+                initials.push_str(py.first_letter());
+                full.push_str(py.plain());
+            }
+            None if c.is_whitespace() => {}
+            None => {
+                initials.push(c);
+                full.push(c);
+            }
+        }
+    }
+    (plain, initials, full)
+}
+
+fn is_subsequence(haystack: &str, needle: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+/// Scores how well `name` matches `query`, higher is better.
+fn score(name: &str, query: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let query_compact = query.split_whitespace().collect::<String>();
+    let (plain, initials, full) = spellings(name);
+    if plain == query {
+        Some(100)
+    } else if plain.starts_with(&query) {
+        Some(80)
+    } else if plain.contains(&query) {
+        Some(70)
+    } else if initials == query_compact || full == query_compact {
+        Some(60)
+    } else if initials.starts_with(&query_compact) || full.starts_with(&query_compact) {
+        Some(50)
+    } else if initials.contains(&query_compact) || full.contains(&query_compact) {
+        Some(40)
+    } else if is_subsequence(&plain, &query) || is_subsequence(&full, &query_compact) {
+        Some(10)
+    } else {
+        None
+    }
+}
+
+/// Areas matching `query` as `(group, name, id)`, best matches first.
+pub fn search<'a>(
+    area_list: &'a [(String, Vec<(String, String)>)],
+    query: &str,
+) -> Vec<(&'a str, &'a str, &'a str)> {
+    let mut result = area_list
+        .iter()
+        .flat_map(|(group, li)| {
+            li.iter().filter_map(move |(name, id)| {
+                let score = if id == query {
+                    Some(200)
+                } else {
+                    score(name, query)
+                };
+                score.map(|score| (score, (group.as_str(), name.as_str(), id.as_str())))
+            })
+        })
+        .collect::<Vec<_>>();
+    result.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let Some(&(best, _)) = result.first() else {
+        return vec![];
+    };
+    // weaker matches are only noise next to an exact or a substring hit
+    let floor = match best {
+        100.. => 100,
+        70.. => 70,
+        60 => 60,
+        40.. => 40,
+        _ => 0,
+    };
+    result.retain(|&(score, _)| score >= floor);
+    result.into_iter().map(|(_, area)| area).collect()
+}
+
+/// Resolves an area id, name, pinyin or fuzzy spelling to a single area id.
+pub fn lookup<'a>(area_list: &'a [(String, Vec<(String, String)>)], query: &str) -> Lookup<'a> {
+    let mut candidates = search(area_list, query);
+    match candidates.len() {
+        0 => Lookup::NotFound,
+        1 => Lookup::Found(candidates.pop().unwrap().2),
+        _ => Lookup::Ambiguous(candidates),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AreaCache {
+    fetched_at: i64,
+    etag: Option<String>,
+    list: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl AreaCache {
+    fn dump<P: AsRef<Path>>(&self, fname: P) -> Result<(), Box<dyn std::error::Error>> {
+        let file = std::fs::File::create(fname)?;
+        let writer = std::io::BufWriter::new(file);
+        serde_json::to_writer(writer, &self)?;
+        Ok(())
+    }
+
+    fn load<P: AsRef<Path>>(fname: P) -> Result<AreaCache, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(fname)?;
+        let reader = std::io::BufReader::new(file);
+        let result: AreaCache = serde_json::from_reader(reader)?;
+        Ok(result)
+    }
+}
+
+/// The live area list, served from the cache at `cache_path` while it is
+/// younger than `ttl` seconds and as a fallback when the network is down.
+pub async fn area_list<P: AsRef<Path>>(
+    cache_path: P,
+    ttl: u64,
+    refresh: bool,
+) -> Result<Vec<(String, Vec<(String, String)>)>, Box<dyn std::error::Error>> {
+    cached_area_list(cache_path, ttl, refresh, live::live_area_list).await
+}
+
+/// `area_list` with the fetching of `live::live_area_list` passed in.
+#[allow(clippy::type_complexity)]
+async fn cached_area_list<P: AsRef<Path>>(
+    cache_path: P,
+    ttl: u64,
+    refresh: bool,
+    fetch: impl AsyncFnOnce(
+        Option<&str>,
+    ) -> Result<
+        Option<(Vec<(String, Vec<(String, String)>)>, Option<String>)>,
+        Box<dyn std::error::Error>,
+    >,
+) -> Result<Vec<(String, Vec<(String, String)>)>, Box<dyn std::error::Error>> {
+    let now = Utc::now().timestamp();
+    let cache = AreaCache::load(&cache_path).ok();
+    if let Some(cache) = cache.as_ref()
+        && !refresh
+        && now - cache.fetched_at < ttl as i64
+    {
+        return Ok(cache.list.clone());
+    }
+    let etag = cache.as_ref().and_then(|cache| cache.etag.as_deref());
+    let cache = match (fetch(etag).await, cache) {
+        (Ok(Some((list, etag))), _) => AreaCache {
+            fetched_at: now,
+            etag,
+            list,
+        },
+        (Ok(None), Some(cache)) => AreaCache {
+            fetched_at: now,
+            ..cache
+        },
+        (Ok(None), None) => Err("no area list cached and fetching failed")?,
+        (Err(e), Some(cache)) => {
+            eprintln!("using cached live areas: {}", e);
+            return Ok(cache.list);
+        }
+        (Err(e), None) => return Err(e),
+    };
+    cache.dump(&cache_path)?;
+    Ok(cache.list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area_list() -> Vec<(String, Vec<(String, String)>)> {
+        let group = |group: &str, areas: &[(&str, &str)]| {
+            let areas = areas
+                .iter()
+                .map(|&(name, id)| (name.to_string(), id.to_string()))
+                .collect();
+            (group.to_string(), areas)
+        };
+        vec![
+            group(
+                "网游",
+                &[("英雄联盟", "86"), ("原神", "321"), ("永劫无间", "666")],
+            ),
+            group(
+                "手游",
+                &[("王者荣耀", "35"), ("原神", "549"), ("原神云游戏", "800")],
+            ),
+            group("单机游戏", &[("主机游戏", "236"), ("单机联机", "237")]),
+            group("体育", &[("篮球明星", "900")]),
+        ]
+    }
+
+    #[test]
+    fn score_ranks_spellings() {
+        assert_eq!(score("英雄联盟", "英雄联盟"), Some(100));
+        assert_eq!(score("英雄联盟", "英雄"), Some(80));
+        assert_eq!(score("英雄联盟", "联盟"), Some(70));
+        assert_eq!(score("英雄联盟", "yxlm"), Some(60));
+        assert_eq!(score("英雄联盟", "YXLM"), Some(60));
+        assert_eq!(score("英雄联盟", "ying xiong lian meng"), Some(60));
+        assert_eq!(score("英雄联盟", "ying xiong"), Some(50));
+        assert_eq!(score("英雄联盟", "yx"), Some(50));
+        assert_eq!(score("英雄联盟", "lm"), Some(40));
+        assert_eq!(score("英雄联盟", "ylm"), Some(10));
+        assert_eq!(score("英雄联盟", "英盟"), Some(10));
+        assert_eq!(score("英雄联盟", "zzz"), None);
+    }
+
+    #[test]
+    fn search_keeps_only_the_best_kind_of_match() {
+        let list = area_list();
+        // two exact names, the prefix match is dropped
+        assert_eq!(
+            search(&list, "原神"),
+            [("网游", "原神", "321"), ("手游", "原神", "549")]
+        );
+        // the fuzzy match of 篮球明星 is dropped next to the initials
+        assert_eq!(search(&list, "lm"), [("网游", "英雄联盟", "86")]);
+        assert_eq!(search(&list, "联").len(), 2);
+        assert!(search(&list, "qqqq").is_empty());
+    }
+
+    #[test]
+    fn lookup_resolves_one_area() {
+        let list = area_list();
+        let found = |query| match lookup(&list, query) {
+            Lookup::Found(id) => Some(id),
+            _ => None,
+        };
+        assert_eq!(found("86"), Some("86"));
+        assert_eq!(found("英雄联盟"), Some("86"));
+        assert_eq!(found("yxlm"), Some("86"));
+        assert_eq!(found("zhuji"), Some("236"));
+        assert_eq!(found("lianji"), Some("237"));
+        assert_eq!(found("云游"), Some("800"));
+        assert!(matches!(lookup(&list, "原神"), Lookup::Ambiguous(areas) if areas.len() == 2));
+        assert!(matches!(lookup(&list, "qqqq"), Lookup::NotFound));
+    }
+
+    fn cache_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "bili-live-areas-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn cache(path: &Path, age: i64, etag: Option<&str>) {
+        AreaCache {
+            fetched_at: Utc::now().timestamp() - age,
+            etag: etag.map(str::to_string),
+            list: area_list()[..1].to_vec(),
+        }
+        .dump(path)
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn area_list_serves_a_fresh_cache() {
+        let path = cache_path("fresh");
+        cache(&path, 10, None);
+        let list = cached_area_list(&path, 60, false, async |_: Option<&str>| {
+            panic!("fetched with a fresh cache")
+        })
+        .await
+        .unwrap();
+        assert_eq!(list, area_list()[..1]);
+
+        // unless a refresh is asked for
+        let list = cached_area_list(&path, 60, true, async |etag: Option<&str>| {
+            assert_eq!(etag, None);
+            Ok(Some((area_list(), Some("\"v2\"".to_string()))))
+        })
+        .await
+        .unwrap();
+        assert_eq!(list, area_list());
+        let cached = AreaCache::load(&path).unwrap();
+        assert_eq!(cached.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(cached.list, area_list());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn area_list_revalidates_with_the_etag() {
+        let path = cache_path("etag");
+        cache(&path, 120, Some("\"v1\""));
+        let list = cached_area_list(&path, 60, false, async |etag: Option<&str>| {
+            assert_eq!(etag, Some("\"v1\""));
+            Ok(None)
+        })
+        .await
+        .unwrap();
+        assert_eq!(list, area_list()[..1]);
+        // not modified, so fresh for another ttl
+        let cached = AreaCache::load(&path).unwrap();
+        assert!(Utc::now().timestamp() - cached.fetched_at < 60);
+        assert_eq!(cached.etag.as_deref(), Some("\"v1\""));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn area_list_offline() {
+        let path = cache_path("offline");
+        let offline = async |_: Option<&str>| Err("offline".into());
+        cache(&path, 3600, None);
+        let list = cached_area_list(&path, 60, false, offline).await.unwrap();
+        assert_eq!(list, area_list()[..1]);
+        // the stale cache is kept as it is
+        assert!(Utc::now().timestamp() - AreaCache::load(&path).unwrap().fetched_at >= 3600);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = cached_area_list(&path, 60, false, offline)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "offline");
+        let error = cached_area_list(&path, 60, false, async |_: Option<&str>| Ok(None))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "no area list cached and fetching failed");
+        assert!(!path.exists());
+    }
+}
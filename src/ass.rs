@@ -0,0 +1,436 @@
+use crate::{
+    event::{Event, InteractKind},
+    record::Record,
+};
+use std::fmt::Write;
+
+pub struct Options {
+    pub font: String,
+    pub font_size: u32,
+    /// 0.0 transparent to 1.0 opaque
+    pub opacity: f64,
+    /// seconds a danmaku stays on screen
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+    pub gifts: bool,
+    pub entrances: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            font: "Microsoft YaHei".to_string(),
+            font_size: 36,
+            opacity: 0.8,
+            duration: 10.0,
+            width: 1920,
+            height: 1080,
+            gifts: true,
+            entrances: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    Scroll,
+    Top,
+    Bottom,
+}
+
+/// A line to show, `time` in seconds since the stream start.
+struct Line {
+    time: f64,
+    position: Position,
+    color: u64,
+    text: String,
+}
+
+fn line(record: &Record, start_ms: i64, options: &Options) -> Option<Line> {
+    let time = (record.server_ts.unwrap_or(record.local_ts) - start_ms) as f64 / 1000.0;
+    let (position, color, text) = match &record.event {
+        Event::Danmu(danmu) => {
+            let position = match danmu.mode {
+                4 => Position::Bottom,
+                5 => Position::Top,
+                _ => Position::Scroll,
+            };
+            (position, danmu.color, danmu.text.clone())
+        }
+        Event::SuperChat(sc) => (
+            Position::Top,
+            0xffd700,
+            format!("¥{} {}: {}", sc.price, sc.user.name, sc.message),
+        ),
+        Event::Gift(gift) if options.gifts => (
+            Position::Scroll,
+            0xffd700,
+            format!(
+                "{} {} {} x{}",
+                gift.user.name, gift.action, gift.gift_name, gift.num
+            ),
+        ),
+        Event::GuardBuy(guard) if options.gifts => (
+            Position::Scroll,
+            0xff8c00,
+            format!("{} 开通了 {} x{}", guard.name, guard.gift_name, guard.num),
+        ),
+        Event::Interact(interact) if options.entrances => {
+            let action = match interact.kind {
+                InteractKind::Enter => "进入直播间",
+                InteractKind::Follow => "关注了直播间",
+                InteractKind::Share => "分享了直播间",
+                InteractKind::SpecialFollow => "特别关注了直播间",
+                InteractKind::MutualFollow => "互相关注了",
+                InteractKind::Other(_) => return None,
+            };
+            (
+                Position::Scroll,
+                0xaaaaaa,
+                format!("{} {}", interact.user.name, action),
+            )
+        }
+        _ => return None,
+    };
+    (time >= 0.0).then_some(Line {
+        time,
+        position,
+        color,
+        text,
+    })
+}
+
+/// Estimated width in pixels: full width for CJK, half for the rest.
+fn text_width(text: &str, font_size: u32) -> f64 {
+    text.chars()
+        .map(|c| if c.is_ascii() { 0.5 } else { 1.0 })
+        .sum::<f64>()
+        * font_size as f64
+}
+
+/// Formats seconds as `H:MM:SS.cc`.
+fn timestamp(seconds: f64) -> String {
+    let centis = (seconds * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+/// An `0xRRGGBB` danmaku colour in the `BBGGRR` order of ASS.
+fn bgr(color: u64) -> u64 {
+    let color = color & 0xffffff;
+    (color & 0xff) << 16 | (color & 0xff00) | (color >> 16)
+}
+
+/// Keeps the text on one line and away from override tags.
+fn escape(text: &str) -> String {
+    text.replace('\\', "＼")
+        .replace('{', "｛")
+        .replace('}', "｝")
+        .replace(['\r', '\n'], " ")
+}
+
+/// A scrolling danmaku in a lane: when it entered and how fast it moves.
+#[derive(Clone, Copy)]
+struct Scrolling {
+    time: f64,
+    width: f64,
+    speed: f64,
+}
+
+/// Picks the lane for a scrolling line at `time`: the first lane where it
+/// neither overlaps the tail of the previous line nor catches up with it,
+/// else the lane that frees up first.
+fn scroll_lane(lanes: &[Option<Scrolling>], screen: f64, time: f64, speed: f64) -> usize {
+    let free_at = |lane: &Option<Scrolling>| match lane {
+        None => f64::MIN,
+        Some(prev) => {
+            // the tail of the previous line has entered the screen
+            let tail_in = prev.time + prev.width / prev.speed;
+            // the head of this line reaches the left edge after the previous one left
+            let gone = prev.time + (screen + prev.width) / prev.speed - screen / speed;
+            tail_in.max(gone)
+        }
+    };
+    lanes
+        .iter()
+        .position(|lane| free_at(lane) <= time)
+        .unwrap_or_else(|| {
+            (0..lanes.len())
+                .min_by(|&a, &b| free_at(&lanes[a]).total_cmp(&free_at(&lanes[b])))
+                .unwrap_or(0)
+        })
+}
+
+/// Picks the lane for a fixed line, each lane holds one line at a time.
+fn fixed_lane(lanes: &[f64], time: f64) -> usize {
+    lanes
+        .iter()
+        .position(|&free| free <= time)
+        .unwrap_or_else(|| {
+            (0..lanes.len())
+                .min_by(|&a, &b| lanes[a].total_cmp(&lanes[b]))
+                .unwrap_or(0)
+        })
+}
+
+/// Renders the recorded danmaku as ASS subtitles, `start_ms` is the stream
+/// start in unix milliseconds.
+pub fn export(records: &[Record], start_ms: i64, options: &Options) -> String {
+    let mut lines: Vec<_> = records
+        .iter()
+        .filter_map(|record| line(record, start_ms, options))
+        .collect();
+    lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let alpha = ((1.0 - options.opacity.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    let (w, h) = (options.width as f64, options.height as f64);
+    let line_height = options.font_size as f64 * 1.2;
+    let lane_count = ((h / line_height) as usize).max(1);
+    let mut scroll_lanes = vec![None; lane_count];
+    let mut top_lanes = vec![f64::MIN; lane_count];
+    let mut bottom_lanes = vec![f64::MIN; lane_count];
+
+    let mut ass = String::new();
+    let _ = write!(
+        ass,
+        "[Script Info]\n\
+         ScriptType: v4.00+\n\
+         PlayResX: {w}\n\
+         PlayResY: {h}\n\
+         WrapStyle: 2\n\
+         ScaledBorderAndShadow: yes\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+         BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+         BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Danmaku,{font},{size},&H{alpha:02X}FFFFFF,&H{alpha:02X}FFFFFF,\
+         &H{alpha:02X}000000,&H{alpha:02X}000000,0,0,0,0,100,100,0,0,1,1.5,0,7,0,0,0,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        w = options.width,
+        h = options.height,
+        font = options.font,
+        size = options.font_size,
+    );
+
+    for line in lines {
+        let width = text_width(&line.text, options.font_size);
+        let end = line.time + options.duration;
+        let position = match line.position {
+            Position::Scroll => {
+                let speed = (w + width) / options.duration;
+                let lane = scroll_lane(&scroll_lanes, w, line.time, speed);
+                scroll_lanes[lane] = Some(Scrolling {
+                    time: line.time,
+                    width,
+                    speed,
+                });
+                let y = lane as f64 * line_height;
+                format!("\\move({w},{y:.0},{:.0},{y:.0})", -width)
+            }
+            Position::Top => {
+                let lane = fixed_lane(&top_lanes, line.time);
+                top_lanes[lane] = end;
+                format!(
+                    "\\an8\\pos({:.0},{:.0})",
+                    w / 2.0,
+                    lane as f64 * line_height
+                )
+            }
+            Position::Bottom => {
+                let lane = fixed_lane(&bottom_lanes, line.time);
+                bottom_lanes[lane] = end;
+                format!(
+                    "\\an2\\pos({:.0},{:.0})",
+                    w / 2.0,
+                    h - lane as f64 * line_height
+                )
+            }
+        };
+        let _ = writeln!(
+            ass,
+            "Dialogue: 0,{},{},Danmaku,,0,0,0,,{{{position}\\c&H{:06X}&}}{}",
+            timestamp(line.time),
+            timestamp(end),
+            bgr(line.color),
+            escape(&line.text),
+        );
+    }
+    ass
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Danmu, Gift, Interact, User};
+    use serde_json::Value;
+
+    fn user(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            ..User::default()
+        }
+    }
+
+    fn record(ms: i64, event: Event) -> Record {
+        Record {
+            local_ts: ms,
+            server_ts: None,
+            cmd: String::new(),
+            event,
+            raw: Value::Null,
+        }
+    }
+
+    fn danmu(ms: i64, mode: u64, text: &str) -> Record {
+        record(
+            ms,
+            Event::Danmu(Danmu {
+                user: user("user"),
+                text: text.to_string(),
+                mode,
+                font_size: 25,
+                color: 0xff0000,
+                timestamp_ms: ms,
+                emoticon: None,
+                reply_to: None,
+            }),
+        )
+    }
+
+    /// The `Dialogue` lines of an export.
+    fn dialogues(records: &[Record], options: &Options) -> Vec<String> {
+        export(records, 10_000, options)
+            .lines()
+            .filter_map(|line| line.strip_prefix("Dialogue: "))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(0.0), "0:00:00.00");
+        assert_eq!(timestamp(1.5), "0:00:01.50");
+        assert_eq!(timestamp(3661.234), "1:01:01.23");
+        // rounding carries into the seconds
+        assert_eq!(timestamp(59.999), "0:01:00.00");
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(bgr(0xff0000), 0x0000ff);
+        assert_eq!(bgr(0x123456), 0x563412);
+        assert_eq!(bgr(0xffffff), 0xffffff);
+        assert_eq!(bgr(0x1_00ff00), 0x00ff00);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape(r"{\b1}bold"), "｛＼b1｝bold");
+        assert_eq!(escape("two\r\nlines"), "two  lines");
+    }
+
+    #[test]
+    fn lanes() {
+        let screen = 1000.0;
+        // 100 px wide, crossing the screen in 10 s
+        let prev = Scrolling {
+            time: 0.0,
+            width: 100.0,
+            speed: 110.0,
+        };
+        assert_eq!(scroll_lane(&[None, None], screen, 0.0, 110.0), 0);
+        // the tail of the previous line is still off screen
+        assert_eq!(scroll_lane(&[Some(prev), None], screen, 0.5, 110.0), 1);
+        assert_eq!(scroll_lane(&[Some(prev), None], screen, 1.0, 110.0), 0);
+        // a faster line would catch up with it
+        assert_eq!(scroll_lane(&[Some(prev), None], screen, 1.0, 300.0), 1);
+        assert_eq!(scroll_lane(&[Some(prev), None], screen, 7.0, 300.0), 0);
+        // all lanes taken, the one freeing up first
+        let later = Scrolling { time: 0.5, ..prev };
+        assert_eq!(
+            scroll_lane(&[Some(later), Some(prev)], screen, 0.1, 110.0),
+            1
+        );
+
+        assert_eq!(fixed_lane(&[5.0, f64::MIN], 1.0), 1);
+        assert_eq!(fixed_lane(&[5.0, 3.0], 1.0), 1);
+        assert_eq!(fixed_lane(&[5.0, 3.0], 6.0), 0);
+    }
+
+    #[test]
+    fn no_collisions() {
+        let records = [
+            danmu(11_000, 1, "first"),
+            danmu(11_000, 1, "second"),
+            danmu(11_100, 1, "third"),
+            danmu(12_000, 5, "top"),
+            danmu(12_000, 5, "top again"),
+            danmu(12_000, 4, "bottom"),
+        ];
+        let lines = dialogues(&records, &Options::default());
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("0,0:00:01.00,0:00:11.00,Danmaku,,0,0,0,,"));
+        assert!(lines[0].contains(r"\move(1920,0,-90,0)\c&H0000FF&}first"));
+        // the same moment gets the next lane, 1.2 times the font size down
+        assert!(lines[1].contains(r"\move(1920,43,-108,43)"));
+        assert!(lines[2].contains(",86,"));
+        assert!(lines[3].contains(r"\an8\pos(960,0)"));
+        assert!(lines[4].contains(r"\an8\pos(960,43)"));
+        assert!(lines[5].contains(r"\an2\pos(960,1080)"));
+    }
+
+    #[test]
+    fn filtering() {
+        let gift = Event::Gift(Gift {
+            user: user("fan"),
+            gift_id: 1,
+            gift_name: "小花花".to_string(),
+            num: 2,
+            price: 100,
+            coin_type: "gold".to_string(),
+            action: "投喂".to_string(),
+            timestamp: 0,
+        });
+        let interact = |kind| {
+            Event::Interact(Interact {
+                user: user("fan"),
+                kind,
+                timestamp: 0,
+            })
+        };
+        let records = [
+            // before the stream start
+            danmu(9_000, 1, "early"),
+            danmu(10_000, 1, "{hi}"),
+            record(11_000, gift),
+            record(12_000, interact(InteractKind::Enter)),
+            record(12_000, interact(InteractKind::Other(9))),
+            record(13_000, Event::Preparing),
+        ];
+        let texts = |options: &Options| {
+            dialogues(&records, options)
+                .iter()
+                .map(|line| line.split_once('}').unwrap().1.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(&Options::default()),
+            ["｛hi｝", "fan 投喂 小花花 x2", "fan 进入直播间"]
+        );
+        let options = Options {
+            gifts: false,
+            entrances: false,
+            ..Options::default()
+        };
+        assert_eq!(texts(&options), ["｛hi｝"]);
+    }
+}
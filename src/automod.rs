@@ -0,0 +1,407 @@
+use crate::{
+    bot::{Action, EventHandler},
+    config::{AutomodAction, AutomodCheck, AutomodConfig},
+    event::{Danmu, Event},
+};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::Write,
+    path::Path,
+};
+use tokio::time::{Duration, Instant};
+
+const LINK_PATTERN: &str =
+    r"(?i)(https?://|www\.|\b[a-z0-9-]+\.(com|cn|net|org|top|xyz|cc|io|tv|me|vip|club)\b)";
+/// danmaku kept per user for the repeat check
+const HISTORY_LEN: usize = 20;
+
+/// A rule that matched a danmaku.
+#[derive(Serialize, Debug, Clone)]
+pub struct Verdict {
+    pub time: String,
+    pub rule: String,
+    pub uid: u64,
+    pub name: String,
+    pub text: String,
+    pub action: AutomodAction,
+    /// the action was only logged
+    pub dry_run: bool,
+}
+
+/// Checks danmaku against the configured rules, the first matching rule
+/// decides. Every verdict goes to the audit log.
+pub struct Automod {
+    config: AutomodConfig,
+    /// compiled patterns of `regex` and `links` checks, by rule index
+    patterns: HashMap<usize, Vec<Regex>>,
+    room_id: u64,
+    /// danmaku of this uid are never checked, they include what the bot sends
+    own_uid: u64,
+    dry_run: bool,
+    history: HashMap<u64, VecDeque<(Instant, String)>>,
+    audit: File,
+}
+
+impl Automod {
+    pub fn new(
+        config: AutomodConfig,
+        room_id: u64,
+        own_uid: u64,
+        dry_run: bool,
+        audit_log: &Path,
+    ) -> Result<Automod, Box<dyn std::error::Error>> {
+        let links = Regex::new(LINK_PATTERN)?;
+        let mut patterns = HashMap::new();
+        for (idx, rule) in config.rules.iter().enumerate() {
+            let compiled = match &rule.check {
+                AutomodCheck::Regex { patterns } => patterns
+                    .iter()
+                    .map(|pattern| Regex::new(pattern))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("automod rule {}: {}", rule.name, e))?,
+                AutomodCheck::Links => vec![links.clone()],
+                _ => continue,
+            };
+            patterns.insert(idx, compiled);
+        }
+        let audit = File::options().create(true).append(true).open(audit_log)?;
+        Ok(Automod {
+            config,
+            patterns,
+            room_id,
+            own_uid,
+            dry_run,
+            history: HashMap::new(),
+            audit,
+        })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.config.rules.len()
+    }
+
+    fn matches(&self, idx: usize, check: &AutomodCheck, danmu: &Danmu, now: Instant) -> bool {
+        let text = &danmu.text;
+        match check {
+            AutomodCheck::Keywords { keywords } => {
+                let lower = text.to_lowercase();
+                keywords
+                    .iter()
+                    .any(|keyword| lower.contains(&keyword.to_lowercase()))
+            }
+            AutomodCheck::Regex { .. } | AutomodCheck::Links => self
+                .patterns
+                .get(&idx)
+                .is_some_and(|patterns| patterns.iter().any(|pattern| pattern.is_match(text))),
+            AutomodCheck::Repeat { count, seconds } => {
+                let within = Duration::from_secs(*seconds);
+                self.history.get(&danmu.user.uid).is_some_and(|history| {
+                    history
+                        .iter()
+                        .filter(|(time, past)| now.duration_since(*time) <= within && past == text)
+                        .count()
+                        >= *count
+                })
+            }
+            AutomodCheck::Flood { max_run } => {
+                let mut run = 0;
+                let mut last = None;
+                for c in text.chars() {
+                    run = if last == Some(c) { run + 1 } else { 1 };
+                    last = Some(c);
+                    if run > *max_run {
+                        return true;
+                    }
+                }
+                false
+            }
+            AutomodCheck::MinUserLevel { level } => danmu.user.user_level < *level,
+            AutomodCheck::NoMedal => danmu
+                .user
+                .medal
+                .as_ref()
+                .is_none_or(|medal| medal.anchor_room_id != self.room_id),
+        }
+    }
+
+    /// Checks a live event, returning the verdict of the first matching rule.
+    pub fn check(&mut self, event: &Event, now: Instant) -> Option<Verdict> {
+        let Event::Danmu(danmu) = event else {
+            return None;
+        };
+        let user = &danmu.user;
+        if user.uid == self.own_uid
+            || user.is_admin
+            || (self.config.exempt_guards && user.guard_level > 0)
+        {
+            return None;
+        }
+        let history = self.history.entry(user.uid).or_default();
+        history.push_back((now, danmu.text.clone()));
+        if history.len() > HISTORY_LEN {
+            history.pop_front();
+        }
+
+        let rule = self
+            .config
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(idx, rule)| self.matches(*idx, &rule.check, danmu, now))
+            .map(|(_, rule)| rule)?;
+        let verdict = Verdict {
+            time: chrono::Local::now().to_rfc3339(),
+            rule: rule.name.clone(),
+            uid: user.uid,
+            name: user.name.clone(),
+            text: danmu.text.clone(),
+            action: rule.action,
+            dry_run: self.dry_run,
+        };
+        if let Err(e) = serde_json::to_writer(&mut self.audit, &verdict)
+            .map_err(std::io::Error::from)
+            .and_then(|()| self.audit.write_all(b"\n"))
+        {
+            eprintln!("automod audit log: {}", e);
+        }
+        Some(verdict)
+    }
+
+    /// The room action for a verdict, none in dry-run mode.
+    pub fn action(&self, verdict: &Verdict) -> Option<Action> {
+        match verdict.action {
+            AutomodAction::Mute { hours } if !self.dry_run => Some(Action::Mute {
+                uid: verdict.uid,
+                hours,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Verdict {
+    pub fn describe(&self) -> String {
+        let action = match self.action {
+            AutomodAction::Mute { hours: Some(hours) } => format!("禁言 {} 小时", hours),
+            AutomodAction::Mute { hours: None } => "禁言本场".to_string(),
+            AutomodAction::Log => "记录".to_string(),
+            AutomodAction::Alert => "提醒".to_string(),
+        };
+        let dry_run = if self.dry_run { " (dry run)" } else { "" };
+        format!(
+            "[automod {}] {}{} {}: {}",
+            self.rule, action, dry_run, self.name, self.text
+        )
+    }
+}
+
+impl EventHandler for Automod {
+    fn on_event(&mut self, event: &Event, now: Instant) -> Vec<Action> {
+        let Some(verdict) = self.check(event, now) else {
+            return vec![];
+        };
+        if verdict.action != AutomodAction::Log {
+            println!("{}", verdict.describe());
+        }
+        self.action(&verdict).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::AutomodRule,
+        event::{Medal, User},
+    };
+
+    fn danmu(uid: u64, text: &str) -> Danmu {
+        Danmu {
+            user: User {
+                uid,
+                name: format!("user{}", uid),
+                user_level: 10,
+                medal: Some(Medal {
+                    name: "粉丝".to_string(),
+                    level: 5,
+                    guard_level: 0,
+                    anchor_uid: 1,
+                    anchor_room_id: 1000,
+                }),
+                ..User::default()
+            },
+            text: text.to_string(),
+            mode: 1,
+            font_size: 25,
+            color: 0xffffff,
+            timestamp_ms: 0,
+            emoticon: None,
+            reply_to: None,
+        }
+    }
+
+    fn with_rules(name: &str, checks: Vec<AutomodCheck>, dry_run: bool) -> Automod {
+        let rules = checks
+            .into_iter()
+            .enumerate()
+            .map(|(idx, check)| AutomodRule {
+                name: format!("rule{}", idx),
+                check,
+                action: AutomodAction::Mute { hours: Some(1) },
+            })
+            .collect();
+        let config = AutomodConfig {
+            rules,
+            ..AutomodConfig::default()
+        };
+        let path = std::env::temp_dir().join(format!(
+            "bili-live-automod-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        Automod::new(config, 1000, 1, dry_run, &path).unwrap()
+    }
+
+    /// The rule matching `danmu` on its own.
+    fn rule(automod: &mut Automod, danmu: Danmu) -> Option<String> {
+        let verdict = automod.check(&Event::Danmu(danmu), Instant::now())?;
+        Some(verdict.rule)
+    }
+
+    #[test]
+    fn checks() {
+        let mut automod = with_rules(
+            "checks",
+            vec![
+                AutomodCheck::Keywords {
+                    keywords: vec!["Spam".to_string()],
+                },
+                AutomodCheck::Regex {
+                    patterns: vec![r"^\d{5,}$".to_string()],
+                },
+                AutomodCheck::Flood { max_run: 4 },
+                AutomodCheck::Links,
+                AutomodCheck::MinUserLevel { level: 3 },
+                AutomodCheck::NoMedal,
+            ],
+            false,
+        );
+        let cases = [
+            (danmu(2, "buy SPAM now"), Some("rule0")),
+            (danmu(2, "1234567"), Some("rule1")),
+            (danmu(2, "哈哈哈哈哈"), Some("rule2")),
+            (danmu(2, "哈哈哈哈"), None),
+            (danmu(2, "see example.com"), Some("rule3")),
+            (danmu(2, "https://t.cn/x"), Some("rule3")),
+            (danmu(2, "hello"), None),
+        ];
+        for (danmu, expected) in cases {
+            let text = danmu.text.clone();
+            assert_eq!(rule(&mut automod, danmu).as_deref(), expected, "{}", text);
+        }
+
+        let mut new_user = danmu(3, "hello");
+        new_user.user.user_level = 1;
+        assert_eq!(rule(&mut automod, new_user).as_deref(), Some("rule4"));
+        let mut other_medal = danmu(3, "hello");
+        other_medal.user.medal.as_mut().unwrap().anchor_room_id = 2000;
+        assert_eq!(rule(&mut automod, other_medal).as_deref(), Some("rule5"));
+        let mut no_medal = danmu(3, "hello");
+        no_medal.user.medal = None;
+        assert_eq!(rule(&mut automod, no_medal).as_deref(), Some("rule5"));
+    }
+
+    #[test]
+    fn repeat() {
+        let mut automod = with_rules(
+            "repeat",
+            vec![AutomodCheck::Repeat {
+                count: 3,
+                seconds: 10,
+            }],
+            false,
+        );
+        let start = Instant::now();
+        let mut check = |uid, text, secs| {
+            let event = Event::Danmu(danmu(uid, text));
+            automod
+                .check(&event, start + Duration::from_secs(secs))
+                .is_some()
+        };
+        assert!(!check(2, "666", 0));
+        assert!(!check(2, "666", 1));
+        // another user and another text do not count
+        assert!(!check(3, "666", 2));
+        assert!(!check(2, "777", 2));
+        assert!(check(2, "666", 3));
+        // the first two fell out of the window
+        assert!(!check(2, "666", 12));
+    }
+
+    #[test]
+    fn exemptions() {
+        let mut automod = with_rules(
+            "exemptions",
+            vec![AutomodCheck::Keywords {
+                keywords: vec!["spam".to_string()],
+            }],
+            false,
+        );
+        let mut guard = danmu(2, "spam");
+        guard.user.guard_level = 3;
+        assert_eq!(rule(&mut automod, guard.clone()), None);
+        let mut admin = danmu(2, "spam");
+        admin.user.is_admin = true;
+        assert_eq!(rule(&mut automod, admin), None);
+        // the streamer, and so the bot
+        assert_eq!(rule(&mut automod, danmu(1, "spam")), None);
+
+        automod.config.exempt_guards = false;
+        assert!(rule(&mut automod, guard).is_some());
+    }
+
+    #[test]
+    fn dry_run_and_audit_log() {
+        let keywords = || {
+            vec![AutomodCheck::Keywords {
+                keywords: vec!["spam".to_string()],
+            }]
+        };
+        let mut automod = with_rules("live", keywords(), false);
+        let event = Event::Danmu(danmu(2, "spam"));
+        let verdict = automod.check(&event, Instant::now()).unwrap();
+        assert_eq!(
+            automod.action(&verdict),
+            Some(Action::Mute {
+                uid: 2,
+                hours: Some(1)
+            })
+        );
+
+        let mut dry = with_rules("dry", keywords(), true);
+        for text in ["spam", "fine", "more spam"] {
+            let event = Event::Danmu(danmu(2, text));
+            if let Some(verdict) = dry.check(&event, Instant::now()) {
+                assert!(verdict.dry_run);
+                assert_eq!(dry.action(&verdict), None);
+            }
+        }
+        assert_eq!(dry.on_event(&event, Instant::now()), []);
+
+        let path = std::env::temp_dir().join(format!(
+            "bili-live-automod-dry-{}.jsonl",
+            std::process::id()
+        ));
+        let log = std::fs::read_to_string(&path).unwrap();
+        let texts = log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["text"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["spam", "more spam", "spam"]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
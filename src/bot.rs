@@ -0,0 +1,417 @@
+use crate::{
+    config::BotConfig,
+    danmaku,
+    event::{Event, InteractKind},
+    live,
+};
+use std::collections::HashMap;
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant, sleep},
+};
+
+/// What an event handler wants done in the room.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send(String),
+    /// mute for `hours`, or for the current live with `None`
+    Mute {
+        uid: u64,
+        hours: Option<u64>,
+    },
+    SetTitle(String),
+    StartLive,
+    StopLive,
+}
+
+/// Reacts to live events, implemented by the bot and by anything else that
+/// answers the chat. `now` is passed in so handlers stay deterministic.
+pub trait EventHandler {
+    fn on_event(&mut self, event: &Event, now: Instant) -> Vec<Action>;
+
+    /// Called about once a second, for timers and reloads.
+    fn tick(&mut self, _now: Instant) -> Vec<Action> {
+        vec![]
+    }
+}
+
+/// Replaces every `{key}` in `template`.
+pub fn fill(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (key, value)| {
+            text.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+fn guard_name(level: u64) -> &'static str {
+    match level {
+        1 => "总督",
+        2 => "提督",
+        _ => "舰长",
+    }
+}
+
+/// Command replies, keyword replies, guard welcomes and gift thanks.
+pub struct Bot {
+    config: BotConfig,
+    /// danmaku of this uid are ignored, so the bot never answers itself
+    own_uid: u64,
+    user_last: HashMap<u64, Instant>,
+    trigger_last: HashMap<String, Instant>,
+}
+
+impl Bot {
+    pub fn new(config: BotConfig, own_uid: u64) -> Bot {
+        Bot {
+            config,
+            own_uid,
+            user_last: HashMap::new(),
+            trigger_last: HashMap::new(),
+        }
+    }
+
+    /// Whether `uid` may trigger `trigger` now, remembering it if so. Only
+    /// commands and keywords cool down globally, thanks and welcomes are
+    /// spaced out by the send queue.
+    fn cool(&mut self, uid: u64, trigger: &str, global: bool, now: Instant) -> bool {
+        let user_cooldown = Duration::from_secs(self.config.user_cooldown);
+        let global_cooldown = Duration::from_secs(self.config.global_cooldown);
+        let hot = |last: Option<&Instant>, cooldown| {
+            last.is_some_and(|&last| now.duration_since(last) < cooldown)
+        };
+        if hot(self.user_last.get(&uid), user_cooldown)
+            || global && hot(self.trigger_last.get(trigger), global_cooldown)
+        {
+            return false;
+        }
+        self.user_last.insert(uid, now);
+        if global {
+            self.trigger_last.insert(trigger.to_string(), now);
+        }
+        true
+    }
+
+    fn reply(&self, text: &str) -> Option<(String, String, String)> {
+        let text = text.trim();
+        if let Some(command) = text.strip_prefix(['!', '！']) {
+            let (name, args) = command
+                .split_once(char::is_whitespace)
+                .unwrap_or((command, ""));
+            let trigger = format!("!{}", name);
+            let reply = self.config.commands.get(&trigger)?;
+            return Some((trigger, reply.clone(), args.trim().to_string()));
+        }
+        self.config
+            .keywords
+            .iter()
+            .find(|keyword| text.contains(&keyword.keyword))
+            .map(|keyword| {
+                (
+                    keyword.keyword.clone(),
+                    keyword.reply.clone(),
+                    String::new(),
+                )
+            })
+    }
+}
+
+impl EventHandler for Bot {
+    fn on_event(&mut self, event: &Event, now: Instant) -> Vec<Action> {
+        let text = match event {
+            Event::Danmu(danmu) if danmu.user.uid != self.own_uid => {
+                let Some((trigger, reply, args)) = self.reply(&danmu.text) else {
+                    return vec![];
+                };
+                if !self.cool(danmu.user.uid, &trigger, true, now) {
+                    return vec![];
+                }
+                fill(
+                    &reply,
+                    &[
+                        ("name", danmu.user.name.clone()),
+                        ("uid", danmu.user.uid.to_string()),
+                        ("args", args),
+                    ],
+                )
+            }
+            Event::GuardBuy(guard) => match self.config.guard_welcome.clone() {
+                Some(template) if self.cool(guard.uid, "guard_welcome", false, now) => fill(
+                    &template,
+                    &[
+                        ("name", guard.name.clone()),
+                        ("guard", guard_name(guard.guard_level).to_string()),
+                        ("num", guard.num.to_string()),
+                    ],
+                ),
+                _ => return vec![],
+            },
+            Event::Interact(interact)
+                if interact.kind == InteractKind::Enter && interact.user.guard_level > 0 =>
+            {
+                match self.config.guard_enter.clone() {
+                    Some(template) if self.cool(interact.user.uid, "guard_enter", false, now) => {
+                        fill(
+                            &template,
+                            &[
+                                ("name", interact.user.name.clone()),
+                                ("guard", guard_name(interact.user.guard_level).to_string()),
+                            ],
+                        )
+                    }
+                    _ => return vec![],
+                }
+            }
+            // a gift combo comes as one event per gift, thank it once
+            Event::Gift(gift) => match self.config.gift_thanks.clone() {
+                Some(template)
+                    if event
+                        .cny()
+                        .is_some_and(|cny| cny >= self.config.gift_threshold_cny)
+                        && self.cool(gift.user.uid, "gift_thanks", false, now) =>
+                {
+                    fill(
+                        &template,
+                        &[
+                            ("name", gift.user.name.clone()),
+                            ("gift", gift.gift_name.clone()),
+                            ("num", gift.num.to_string()),
+                        ],
+                    )
+                }
+                _ => return vec![],
+            },
+            _ => return vec![],
+        };
+        vec![Action::Send(text)]
+    }
+}
+
+/// Sends a danmaku, shortening it when too long and backing off when the
+/// server says we are too fast. Returns the text that was sent.
+pub async fn send(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    text: String,
+    interval: Duration,
+) -> Result<String, live::SendError> {
+    let mut danmaku = live::OutgoingDanmaku::new(text);
+    let mut tries = 0;
+    loop {
+        tries += 1;
+        match live::send_danmaku(cookies, room_id, &danmaku).await {
+            Ok(()) => return Ok(danmaku.text),
+            Err(live::SendError::TooLong { max, .. }) if tries < 3 => {
+                danmaku.text = danmaku.text.chars().take(max).collect();
+            }
+            Err(live::SendError::RateLimited(_)) if tries < 3 => sleep(interval * 4).await,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Sends queued danmaku one at a time, waiting `interval` in between.
+fn spawn_sender(
+    cookies: HashMap<String, String>,
+    room_id: u64,
+    interval: Duration,
+) -> mpsc::UnboundedSender<String> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Some(text) = rx.recv().await {
+            match send(&cookies, room_id, text, interval).await {
+                Ok(text) => println!("> {}", text),
+                Err(e) => eprintln!("send failed: {}", e),
+            }
+            sleep(interval).await;
+        }
+    });
+    tx
+}
+
+/// Carries out an action other than sending, reporting the outcome.
+async fn execute(
+    action: Action,
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    area: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match action {
+        Action::Send(_) => unreachable!(),
+        Action::Mute { uid, hours } => {
+            live::add_silent_user(cookies, room_id, uid, hours).await?;
+            Ok(format!("muted {}", uid))
+        }
+        Action::SetTitle(title) => {
+            live::update_room_title(cookies, &title).await?;
+            Ok(format!("title set to {}", title))
+        }
+        Action::StartLive => {
+            let area = match area {
+                Some(area) => area.to_string(),
+                None => live::get_room_info(room_id).await?.area_id.to_string(),
+            };
+            let ((addr, code), _) = live::start_live(cookies, &area).await?;
+            Ok(format!("live started, addr {} code {}", addr, code))
+        }
+        Action::StopLive => {
+            live::stop_live(cookies).await?;
+            Ok("live stopped".to_string())
+        }
+    }
+}
+
+/// Runs `handler` on the live events of `room_id` until ctrl-c, `area` is
+/// used when a handler starts the live.
+pub async fn run(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    area: Option<&str>,
+    handler: &mut dyn EventHandler,
+    send_interval: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    danmaku::spawn(tx, cookies.clone(), room_id);
+    let sender = spawn_sender(cookies.clone(), room_id, send_interval);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        let actions = tokio::select! {
+            message = rx.recv() => match message {
+                Some(danmaku::Message::Command(raw)) => {
+                    handler.on_event(&Event::parse(raw), Instant::now())
+                }
+                Some(danmaku::Message::Connected) => {
+                    println!("connected to room {}", room_id);
+                    continue;
+                }
+                Some(danmaku::Message::Disconnected(e)) => {
+                    eprintln!("disconnected: {}", e);
+                    continue;
+                }
+                Some(_) => continue,
+                None => return Ok(()),
+            },
+            _ = ticker.tick() => handler.tick(Instant::now()),
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+        for action in actions {
+            match action {
+                Action::Send(text) => sender.send(text)?,
+                action => match execute(action, cookies, room_id, area).await {
+                    Ok(message) => println!("{}", message),
+                    Err(e) => eprintln!("{}", e),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Danmu, Gift, GuardBuy, Interact, User};
+
+    fn gift(uid: u64) -> Event {
+        Event::Gift(Gift {
+            user: User {
+                uid,
+                name: format!("user{}", uid),
+                ..User::default()
+            },
+            gift_id: 1,
+            gift_name: "小花花".to_string(),
+            num: 10,
+            price: 100,
+            coin_type: "gold".to_string(),
+            action: "投喂".to_string(),
+            timestamp: 0,
+        })
+    }
+
+    #[test]
+    fn gift_thanks_cool_down() {
+        let mut bot = Bot::new(BotConfig::default(), 1);
+        let start = Instant::now();
+        let thanked = |bot: &mut Bot, event: &Event, secs| {
+            bot.on_event(event, start + Duration::from_secs(secs)).len()
+        };
+        assert_eq!(thanked(&mut bot, &gift(2), 0), 1);
+        // the rest of a combo is not thanked again, another user is
+        assert_eq!(thanked(&mut bot, &gift(2), 1), 0);
+        assert_eq!(thanked(&mut bot, &gift(3), 2), 1);
+        // the first user is still within the user cooldown
+        assert_eq!(thanked(&mut bot, &gift(2), 12), 0);
+        assert_eq!(thanked(&mut bot, &gift(2), 31), 1);
+
+        let guard = Event::GuardBuy(GuardBuy {
+            uid: 4,
+            name: "user4".to_string(),
+            guard_level: 3,
+            num: 1,
+            price: 198000,
+            gift_name: "舰长".to_string(),
+            start_time: 0,
+        });
+        assert_eq!(thanked(&mut bot, &guard, 31), 1);
+        assert_eq!(thanked(&mut bot, &guard, 32), 0);
+    }
+
+    #[test]
+    fn guard_enter() {
+        let mut bot = Bot::new(BotConfig::default(), 1);
+        let now = Instant::now();
+        let enter = |uid, guard_level| {
+            Event::Interact(Interact {
+                user: User {
+                    uid,
+                    name: format!("user{}", uid),
+                    guard_level,
+                    ..User::default()
+                },
+                kind: InteractKind::Enter,
+                timestamp: 0,
+            })
+        };
+        assert_eq!(
+            bot.on_event(&enter(2, 2), now),
+            [Action::Send("欢迎提督 user2 进入直播间".to_string())]
+        );
+        assert_eq!(bot.on_event(&enter(3, 0), now), []);
+    }
+
+    #[test]
+    fn commands() {
+        let mut config = BotConfig::default();
+        config
+            .commands
+            .insert("!时间".to_string(), "{name}: 每晚八点 {args}".to_string());
+        let mut bot = Bot::new(config, 1);
+        let now = Instant::now();
+        let danmu = |uid, text: &str| {
+            Event::Danmu(Danmu {
+                user: User {
+                    uid,
+                    name: format!("user{}", uid),
+                    ..User::default()
+                },
+                text: text.to_string(),
+                mode: 1,
+                font_size: 25,
+                color: 0xffffff,
+                timestamp_ms: 0,
+                emoticon: None,
+                reply_to: None,
+            })
+        };
+        // with the full-width space of Chinese input methods
+        assert_eq!(
+            bot.on_event(&danmu(2, "！时间\u{3000}周末"), now),
+            [Action::Send("user2: 每晚八点 周末".to_string())]
+        );
+        // within the global cooldown of the command
+        assert_eq!(bot.on_event(&danmu(3, "!时间"), now), []);
+        // never answers itself
+        let later = now + Duration::from_secs(60);
+        assert_eq!(bot.on_event(&danmu(1, "!时间"), later), []);
+    }
+}
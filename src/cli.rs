@@ -1,18 +1,562 @@
-use clap::{ArgAction, Command, arg, command, value_parser};
-use std::fmt::Debug;
+use crate::live::DanmakuMode;
+use crate::record::Format;
+use clap::{Arg, ArgAction, Command, arg, command, value_parser};
+use std::{fmt::Debug, path::PathBuf};
 use viuer::{Config, print};
 
 pub fn build_commands() -> Command {
     command!() // requires `cargo` feature
         .subcommand(Command::new("status").about("check live room status"))
         .subcommand(
-            Command::new("start").about("start live").arg(
-                arg!(-a --area <AREA> "the live area")
-                    .required(false)
-                    .value_parser(value_parser!(String)),
-            ),
+            Command::new("start")
+                .about("start live")
+                .arg(
+                    arg!(-a --area <AREA> "the live area id or name")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(obs_arg("then set the stream server and key in OBS and start streaming"))
+                .arg(obs_password_arg())
+                .arg(
+                    arg!(--"write-obs-profile" <PROFILE> "write the stream server and key into the service.json of an OBS profile, by name or path")
+                        .required(false),
+                )
+                .arg(
+                    arg!(--force "write the OBS profile even while OBS uses it")
+                        .action(ArgAction::SetTrue)
+                        .required(false)
+                        .requires("write-obs-profile"),
+                ),
+        )
+        .subcommand(
+            Command::new("stop")
+                .about("stop live")
+                .arg(
+                    arg!(-l --log <PATH> "the JSONL log of `chat record` to summarize the session from, the room info otherwise")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--export <PATH> "write the summary as .md, .html or .json")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(obs_arg("stop streaming in OBS first"))
+                .arg(obs_password_arg()),
+        )
+        .subcommand(
+            Command::new("test-stream")
+                .about("push a local FLV file in real time to check that streaming works")
+                .arg(
+                    arg!(-f --file <FLV> "the FLV file to push")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-a --area <AREA> "the live area id or name, defaults to the last one")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--addr <ADDR> "push to this rtmp:// address instead of starting live")
+                        .required(false)
+                        .requires("code"),
+                )
+                .arg(
+                    arg!(--code <CODE> "the stream key for --addr")
+                        .required(false)
+                        .requires("addr"),
+                )
+                .arg(
+                    arg!(--"keep-live" "do not stop live after the file ends")
+                        .action(ArgAction::SetTrue)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("check that streaming can work")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("ingest")
+                        .about("check dns, tcp latency and the rtmp handshake of each ingest line and recommend the best one")
+                        .arg(
+                            arg!(-a --area <AREA> "the live area id or name, defaults to the last one")
+                                .required(false)
+                                .value_parser(value_parser!(String)),
+                        )
+                        .arg(
+                            arg!(--addr <ADDR> "check these rtmp:// addresses instead of starting live")
+                                .required(false)
+                                .num_args(1..),
+                        )
+                        .arg(
+                            arg!(-n --tries <N> "connections per line")
+                                .required(false)
+                                .default_value("3")
+                                .value_parser(value_parser!(u64).range(1..)),
+                        )
+                        .arg(
+                            arg!(-t --timeout <DURATION> "time limit of each dns lookup, connect and handshake")
+                                .required(false)
+                                .default_value("5s")
+                                .value_parser(parse_duration),
+                        )
+                        .arg(
+                            arg!(--json "print the results as json")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(--"keep-live" "do not stop live after the check")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("room")
+                .about("manage live room settings")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("area")
+                        .about("manage the live area of the room")
+                        .subcommand_required(true)
+                        .subcommand(
+                            Command::new("set")
+                                .about("change the live area, also while living")
+                                .arg(
+                                    arg!([AREA] "the live area id or name")
+                                        .value_parser(value_parser!(String)),
+                                ),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("areas")
+                .about("list live areas")
+                .arg(
+                    arg!(-s --search <QUERY> "filter areas by name, pinyin or id")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--refresh "fetch the live areas instead of using the cache")
+                        .action(ArgAction::SetTrue)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("chat")
+                .about("show the live chat of a room")
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .global(true)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--automod "check danmaku against the automod rules")
+                        .action(ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    arg!(--"dry-run" "with --automod, only alert and log instead of muting")
+                        .action(ArgAction::SetTrue)
+                        .required(false),
+                )
+                .subcommand(
+                    Command::new("send")
+                        .about("send a danmaku")
+                        .arg(arg!(<TEXT> "the danmaku text or emoticon code"))
+                        .arg(
+                            arg!(-c --color <RRGGBB> "the danmaku color")
+                                .required(false)
+                                .default_value("ffffff")
+                                .value_parser(|color: &str| {
+                                    u32::from_str_radix(color.trim_start_matches('#'), 16)
+                                }),
+                        )
+                        .arg(
+                            arg!(-m --mode <MODE> "where the danmaku is shown")
+                                .required(false)
+                                .default_value("scroll")
+                                .value_parser(|mode: &str| match mode {
+                                    "scroll" => Ok(DanmakuMode::Scroll),
+                                    "top" => Ok(DanmakuMode::Top),
+                                    "bottom" => Ok(DanmakuMode::Bottom),
+                                    _ => Err("expected scroll, top or bottom"),
+                                }),
+                        )
+                        .arg(
+                            arg!(-e --emoticon "send TEXT as an emoticon code")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(--"reply-to" <UID> "the uid of the user to reply to")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("record")
+                        .about("record danmaku and room events, one file per live session")
+                        .arg(
+                            arg!(-f --format <FORMAT> "the file format")
+                                .required(false)
+                                .default_value("jsonl")
+                                .value_parser(|format: &str| match format {
+                                    "jsonl" => Ok(Format::Jsonl),
+                                    "csv" => Ok(Format::Csv),
+                                    "sqlite" => Ok(Format::Sqlite),
+                                    "xml" => Ok(Format::Xml),
+                                    _ => Err("expected jsonl, csv, sqlite or xml"),
+                                }),
+                        )
+                        .arg(
+                            arg!(-o --out <PATH> "the file path, the session start time is appended to its name, defaults to ~/bili-live-danmaku")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("export-ass")
+                        .about("convert a recorded JSONL log to ASS subtitles")
+                        .arg(
+                            arg!(<LOG> "the JSONL log written by `chat record`")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(-o --out <PATH> "the subtitle file, defaults to LOG with .ass")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(--start <UNIX_TIME> "the stream start, defaults to the live time in the log or of your room")
+                                .required(false)
+                                .value_parser(value_parser!(i64)),
+                        )
+                        .arg(
+                            arg!(--font <FONT> "the font name")
+                                .required(false)
+                                .default_value("Microsoft YaHei"),
+                        )
+                        .arg(
+                            arg!(--"font-size" <SIZE> "the font size in pixels")
+                                .required(false)
+                                .default_value("36")
+                                .value_parser(value_parser!(u32).range(1..)),
+                        )
+                        .arg(
+                            arg!(--opacity <OPACITY> "from 0 transparent to 1 opaque")
+                                .required(false)
+                                .default_value("0.8")
+                                .value_parser(value_parser!(f64)),
+                        )
+                        .arg(
+                            arg!(--duration <SECONDS> "how long a danmaku stays on screen")
+                                .required(false)
+                                .default_value("10")
+                                .value_parser(value_parser!(f64)),
+                        )
+                        .arg(
+                            arg!(--resolution <WxH> "the video resolution")
+                                .required(false)
+                                .default_value("1920x1080")
+                                .value_parser(|resolution: &str| {
+                                    resolution
+                                        .split_once('x')
+                                        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                                        .ok_or("expected WIDTHxHEIGHT, e.g. 1920x1080")
+                                }),
+                        )
+                        .arg(
+                            arg!(--"no-gifts" "leave out gifts and guards")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(--"no-entrances" "leave out entrances, follows and shares")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("overlay")
+                .about("serve browser source overlays for OBS")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("serve")
+                        .about("serve the overlay pages and push live events to them")
+                        .arg(
+                            arg!(-p --port <PORT> "the local port to listen on")
+                                .required(false)
+                                .default_value("8090")
+                                .value_parser(value_parser!(u16)),
+                        )
+                        .arg(
+                            arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        )
+                        .arg(
+                            arg!(--css <PATH> "a stylesheet loaded after the built-in styles")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(--test "push fake events instead of connecting to the room")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("widgets")
+                .about("keep text files with live info up to date for OBS text sources")
+                .arg(
+                    arg!(-d --dir <DIR> "the directory of the text files")
+                        .required(false)
+                        .default_value("bili-live-widgets")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(-i --interval <SECONDS> "seconds between room status polls")
+                        .required(false)
+                        .default_value("30")
+                        .value_parser(value_parser!(u64).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("bot")
+                .about("answer commands and keywords and thank gifts in the chat")
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("automod")
+                .about("mute or report danmaku that break the automod rules")
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"dry-run" "only report and log what would be done")
+                        .action(ArgAction::SetTrue)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("raffle")
+                .about("run a danmaku raffle with verifiable draws")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("collect everyone who sends the keyword, then draw and announce the winners")
+                        .arg(
+                            arg!(-k --keyword <KEYWORD> "the danmaku to send to take part")
+                                .required(true),
+                        )
+                        .arg(
+                            arg!(-d --duration <DURATION> "how long to collect participants, ctrl-c draws early")
+                                .required(false)
+                                .default_value("5m")
+                                .value_parser(parse_duration),
+                        )
+                        .arg(
+                            arg!(-n --winners <N> "the number of winners")
+                                .required(false)
+                                .default_value("1")
+                                .value_parser(value_parser!(u64).range(1..)),
+                        )
+                        .arg(
+                            arg!(--"min-medal-level" <N> "require the fan medal of the room at this level")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        )
+                        .arg(
+                            arg!(--"guards-only" "only guards may take part")
+                                .action(ArgAction::SetTrue)
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(-o --out <PATH> "where to save participants, seed and winners, defaults to raffle-<time>.json")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("check the winners of a saved raffle against its seed")
+                        .arg(
+                            arg!(<FILE> "the file saved by `raffle start`")
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("poll")
+                .about("run a chat poll")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("count votes from danmaku, showing the results live and announcing them at the end")
+                        .arg(arg!(<QUESTION> "the question"))
+                        .arg(
+                            arg!(-o --options <OPTIONS> "the comma separated options, voted for by text or number")
+                                .required(true)
+                                .value_delimiter(',')
+                                .num_args(1..),
+                        )
+                        .arg(
+                            arg!(-d --duration <DURATION> "how long the poll is open, q closes it early")
+                                .required(false)
+                                .default_value("2m")
+                                .value_parser(parse_duration),
+                        )
+                        .arg(
+                            arg!(-p --port <PORT> "the overlay server port, the results page is /poll")
+                                .required(false)
+                                .default_value("8090")
+                                .value_parser(value_parser!(u16)),
+                        )
+                        .arg(
+                            arg!(-f --file <PATH> "the text file with the results for OBS")
+                                .required(false)
+                                .default_value("bili-live-poll.txt")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("songs")
+                .about("take song requests from `!点歌 <name>` danmaku")
+                .arg(
+                    arg!(-p --port <PORT> "the overlay server port, the queue page is /songs")
+                        .required(false)
+                        .default_value("8090")
+                        .value_parser(value_parser!(u16)),
+                )
+                .arg(
+                    arg!(-f --file <PATH> "the text file with the queue for OBS")
+                        .required(false)
+                        .default_value("bili-live-songs.txt")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("script")
+                .about("run Rhai scripts on live events")
+                .subcommand_required(true)
+                .arg(
+                    arg!(-d --dir <DIR> "the scripts directory, defaults to ~/bili-live-scripts")
+                        .required(false)
+                        .global(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .subcommand(
+                    Command::new("run")
+                        .about("run the scripts on the room, reloading them when they change")
+                        .arg(
+                            arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                                .required(false)
+                                .value_parser(value_parser!(u64)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("replay")
+                        .about("feed a recorded JSONL log to the scripts and print what they do")
+                        .arg(
+                            arg!(<LOG> "the JSONL log written by `chat record`")
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("mod")
+                .about("moderate a live room")
+                .subcommand_required(true)
+                .arg(
+                    arg!(-r --room <ROOM_ID> "the live room id, defaults to your own room")
+                        .required(false)
+                        .global(true)
+                        .value_parser(value_parser!(u64)),
+                )
+                .subcommand(
+                    Command::new("mute")
+                        .about("mute a user")
+                        .arg(arg!(<USER> "the uid or exact user name"))
+                        .arg(
+                            arg!(--hours <N> "mute for N hours instead of the current live")
+                                .required(false)
+                                .value_parser(value_parser!(u64).range(1..)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unmute")
+                        .about("unmute a user")
+                        .arg(arg!(<USER> "the uid or user name")),
+                )
+                .subcommand(Command::new("list-muted").about("list muted users"))
+                .subcommand(
+                    Command::new("words")
+                        .about("manage blocked keywords")
+                        .subcommand_required(true)
+                        .subcommand(
+                            Command::new("add")
+                                .about("block a keyword")
+                                .arg(arg!(<WORD> "the keyword")),
+                        )
+                        .subcommand(
+                            Command::new("remove")
+                                .about("unblock a keyword")
+                                .arg(arg!(<WORD> "the keyword")),
+                        )
+                        .subcommand(Command::new("list").about("list blocked keywords")),
+                )
+                .subcommand(
+                    Command::new("admin")
+                        .about("manage the room admins of your room")
+                        .subcommand_required(true)
+                        .subcommand(
+                            Command::new("add")
+                                .about("appoint a room admin")
+                                .arg(arg!(<USER> "the uid or exact user name")),
+                        )
+                        .subcommand(
+                            Command::new("remove")
+                                .about("dismiss a room admin")
+                                .arg(arg!(<USER> "the uid or exact user name")),
+                        )
+                        .subcommand(Command::new("list").about("list room admins")),
+                ),
         )
-        .subcommand(Command::new("stop").about("stop live"))
         .subcommand(
             Command::new("clean").about("clean login data").arg(
                 arg!(--area "just clean the live area data")
@@ -22,6 +566,20 @@ pub fn build_commands() -> Command {
         )
 }
 
+fn obs_arg(help: &'static str) -> Arg {
+    arg!(--obs <URL>)
+        .help(format!("{}, via obs-websocket at URL", help))
+        .required(false)
+        .num_args(0..=1)
+        .default_missing_value("ws://localhost:4455")
+}
+
+fn obs_password_arg() -> Arg {
+    arg!(--"obs-password" <PASSWORD> "the obs-websocket password, defaults to obs_password in the config")
+        .required(false)
+        .requires("obs")
+}
+
 pub fn print_pairs(head: &dyn Debug, pairs: &[(String, String)]) {
     println!("{:?}:", head);
     for (k, v) in pairs {
@@ -29,6 +587,56 @@ pub fn print_pairs(head: &dyn Debug, pairs: &[(String, String)]) {
     }
 }
 
+/// Formats seconds as `hour:min:sec`.
+pub fn format_duration(mut delta: i64) -> String {
+    let sec = delta % 60;
+    delta /= 60;
+    let min = delta % 60;
+    delta /= 60;
+    let hour = delta;
+    format!("{}:{}:{}", hour, min, sec)
+}
+
+/// Parses durations such as `90`, `90s`, `5m` or `1h30m` into seconds.
+pub fn parse_duration(text: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration {}, expected e.g. 90s, 5m or 1h30m", text);
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        secs += number.parse::<u64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        secs += number.parse::<u64>().map_err(|_| invalid())?;
+    }
+    match secs {
+        0 => Err(invalid()),
+        secs => Ok(secs),
+    }
+}
+
+pub fn print_areas(head: &dyn Debug, areas: &[(&str, &str, &str)]) {
+    println!("{:?}:", head);
+    let mut last_group = None;
+    for &(group, name, id) in areas {
+        if last_group != Some(group) {
+            println!("- {}:", group);
+            last_group = Some(group);
+        }
+        println!("  - {}[{}]", name, id);
+    }
+}
+
 pub async fn print_image(img_url: &str) -> Result<(), Box<dyn std::error::Error>> {
     let binding = reqwest::get(img_url).await?.bytes().await?;
     let img = image::load_from_memory(&binding)?;
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// seconds before the cached live area list is fetched again
+    pub area_cache_ttl: u64,
+    /// live area ids marked as favorite in the area picker
+    pub favorite_areas: Vec<String>,
+    /// live area ids of past `start` calls, the latest first
+    pub recent_areas: Vec<String>,
+    /// `widgets` templates by widget name, overriding the default ones
+    pub widget_templates: BTreeMap<String, String>,
+    pub bot: BotConfig,
+    pub automod: AutomodConfig,
+    pub songs: SongConfig,
+    /// obs-websocket password used when `--obs-password` is not given
+    pub obs_password: Option<String>,
+}
+
+/// Replies of the chat bot, `{name}` and other placeholders are filled in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BotConfig {
+    /// replies to `!command`, e.g. `"!schedule": "每晚八点开播"`; `{args}` is the rest of the line
+    pub commands: BTreeMap<String, String>,
+    /// replies to danmaku containing a keyword
+    pub keywords: Vec<KeywordReply>,
+    /// sent when someone buys a guard, `{name}`, `{guard}`, `{num}`
+    pub guard_welcome: Option<String>,
+    /// sent when a guard enters the room, `{name}`, `{guard}`
+    pub guard_enter: Option<String>,
+    /// sent for gifts worth at least `gift_threshold_cny`, `{name}`, `{gift}`, `{num}`
+    pub gift_thanks: Option<String>,
+    pub gift_threshold_cny: f64,
+    /// seconds before the same user can trigger a reply again
+    pub user_cooldown: u64,
+    /// seconds before the same command or keyword replies again
+    pub global_cooldown: u64,
+    /// milliseconds between two sent danmaku
+    pub send_interval: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeywordReply {
+    pub keyword: String,
+    pub reply: String,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            commands: BTreeMap::new(),
+            keywords: vec![],
+            guard_welcome: Some("感谢 {name} 开通{guard}!".to_string()),
+            guard_enter: Some("欢迎{guard} {name} 进入直播间".to_string()),
+            gift_thanks: Some("谢谢 {name} 的 {gift} x{num}".to_string()),
+            gift_threshold_cny: 1.0,
+            user_cooldown: 30,
+            global_cooldown: 5,
+            send_interval: 1500,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            area_cache_ttl: 24 * 60 * 60,
+            favorite_areas: vec![],
+            recent_areas: vec![],
+            widget_templates: BTreeMap::new(),
+            bot: BotConfig::default(),
+            automod: AutomodConfig::default(),
+            songs: SongConfig::default(),
+            obs_password: None,
+        }
+    }
+}
+
+/// Chat moderation rules, checked in order against every danmaku.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AutomodConfig {
+    pub rules: Vec<AutomodRule>,
+    /// guards are not checked, room admins never are
+    pub exempt_guards: bool,
+    /// JSONL audit log, defaults to `~/bili-live-automod.jsonl`
+    pub audit_log: Option<PathBuf>,
+}
+
+impl Default for AutomodConfig {
+    fn default() -> Self {
+        AutomodConfig {
+            rules: vec![],
+            exempt_guards: true,
+            audit_log: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AutomodRule {
+    pub name: String,
+    pub check: AutomodCheck,
+    pub action: AutomodAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AutomodCheck {
+    /// the danmaku contains one of the keywords
+    Keywords { keywords: Vec<String> },
+    /// the danmaku matches one of the regular expressions
+    Regex { patterns: Vec<String> },
+    /// the same user sent the same text `count` times within `seconds`
+    Repeat { count: usize, seconds: u64 },
+    /// one character repeated more than `max_run` times in a row
+    Flood { max_run: usize },
+    /// the danmaku contains a URL or domain
+    Links,
+    /// the user level is below `level`, as for new accounts
+    MinUserLevel { level: u64 },
+    /// the user wears no fan medal of this room
+    NoMedal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AutomodAction {
+    /// mute for `hours`, or for the current live without
+    Mute { hours: Option<u64> },
+    /// only write the audit log
+    Log,
+    /// show an alert in the chat view
+    Alert,
+}
+
+impl Config {
+    pub fn dump<P: AsRef<Path>>(&self, fname: P) -> Result<(), Box<dyn std::error::Error>> {
+        let file = std::fs::File::create(fname)?;
+        let writer = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, &self)?;
+        Ok(())
+    }
+
+    /// Remembers `area` as the latest one used by `start`.
+    pub fn push_recent_area(&mut self, area: &str) {
+        self.recent_areas.retain(|recent| recent != area);
+        self.recent_areas.insert(0, area.to_string());
+        self.recent_areas.truncate(10);
+    }
+
+    /// Loads the config, a missing file gives the default one.
+    pub fn load<P: AsRef<Path>>(fname: P) -> Result<Config, Box<dyn std::error::Error>> {
+        let file = match std::fs::File::open(fname) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => Err(e)?,
+        };
+        let reader = std::io::BufReader::new(file);
+        let result: Config = serde_json::from_reader(reader)?;
+        Ok(result)
+    }
+}
+
+/// Limits of the song request queue.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SongConfig {
+    /// songs one user may have waiting at a time
+    pub per_user_limit: usize,
+    /// songs waiting at most
+    pub max_queue: usize,
+    /// characters of a song name at most
+    pub max_name_len: usize,
+}
+
+impl Default for SongConfig {
+    fn default() -> Self {
+        SongConfig {
+            per_user_limit: 2,
+            max_queue: 50,
+            max_name_len: 40,
+        }
+    }
+}
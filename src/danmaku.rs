@@ -0,0 +1,431 @@
+use crate::live;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::{collections::HashMap, io::Read};
+use tokio::{
+    sync::mpsc,
+    time::{Duration, Instant, interval, sleep},
+};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub const HEADER_LEN: usize = 16;
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+pub const OP_HEARTBEAT: u32 = 2;
+pub const OP_HEARTBEAT_REPLY: u32 = 3;
+pub const OP_MESSAGE: u32 = 5;
+pub const OP_AUTH: u32 = 7;
+pub const OP_AUTH_REPLY: u32 = 8;
+
+pub const PROTOVER_INT: u16 = 1;
+pub const PROTOVER_ZLIB: u16 = 2;
+pub const PROTOVER_BROTLI: u16 = 3;
+
+/// One packet of the live message protocol: a 16-byte big endian header
+/// (total length, header length, protocol version, operation, sequence)
+/// followed by the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub protover: u16,
+    pub operation: u32,
+    pub sequence: u32,
+    pub body: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(operation: u32, body: Vec<u8>) -> Packet {
+        Packet {
+            protover: PROTOVER_INT,
+            operation,
+            sequence: 1,
+            body,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.body.len());
+        buf.extend(((HEADER_LEN + self.body.len()) as u32).to_be_bytes());
+        buf.extend((HEADER_LEN as u16).to_be_bytes());
+        buf.extend(self.protover.to_be_bytes());
+        buf.extend(self.operation.to_be_bytes());
+        buf.extend(self.sequence.to_be_bytes());
+        buf.extend(&self.body);
+        buf
+    }
+
+    /// Splits `data` into packets, unpacking compressed bodies into the
+    /// packets they contain.
+    pub fn decode(mut data: &[u8]) -> Result<Vec<Packet>, Error> {
+        let mut packets = vec![];
+        while !data.is_empty() {
+            if data.len() < HEADER_LEN {
+                Err(format!("truncated packet header: {} bytes", data.len()))?;
+            }
+            let total_len = u32::from_be_bytes(data[0..4].try_into()?) as usize;
+            let header_len = u16::from_be_bytes(data[4..6].try_into()?) as usize;
+            if header_len < HEADER_LEN || total_len < header_len || total_len > data.len() {
+                Err(format!(
+                    "invalid packet length: total {}, header {}, available {}",
+                    total_len,
+                    header_len,
+                    data.len()
+                ))?;
+            }
+            let packet = Packet {
+                protover: u16::from_be_bytes(data[6..8].try_into()?),
+                operation: u32::from_be_bytes(data[8..12].try_into()?),
+                sequence: u32::from_be_bytes(data[12..16].try_into()?),
+                body: data[header_len..total_len].to_vec(),
+            };
+            data = &data[total_len..];
+
+            let mut inflated = vec![];
+            match (packet.operation, packet.protover) {
+                (OP_MESSAGE, PROTOVER_ZLIB) => {
+                    flate2::read::ZlibDecoder::new(packet.body.as_slice())
+                        .read_to_end(&mut inflated)?;
+                }
+                (OP_MESSAGE, PROTOVER_BROTLI) => {
+                    brotli_decompressor::Decompressor::new(packet.body.as_slice(), 4096)
+                        .read_to_end(&mut inflated)?;
+                }
+                _ => {
+                    packets.push(packet);
+                    continue;
+                }
+            }
+            packets.extend(Packet::decode(&inflated)?);
+        }
+        Ok(packets)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// the server accepted the auth packet
+    Connected,
+    /// the connection was lost, the client reconnects on its own
+    Disconnected(String),
+    /// the popularity value from the heartbeat reply
+    Popularity(u32),
+    /// a JSON command such as `DANMU_MSG`
+    Command(Value),
+    /// a local notice, e.g. the result of sending a danmaku
+    Notice(String),
+}
+
+/// Credentials sent in the auth packet.
+#[derive(Debug, Clone)]
+pub struct Auth {
+    pub uid: u64,
+    pub room_id: u64,
+    pub buvid: String,
+    pub token: String,
+}
+
+impl Auth {
+    fn packet(&self) -> Packet {
+        let body = json!({
+            "uid": self.uid,
+            "roomid": self.room_id,
+            "protover": PROTOVER_BROTLI,
+            "buvid": self.buvid,
+            "platform": "web",
+            "type": 2,
+            "key": self.token,
+        });
+        Packet::new(OP_AUTH, body.to_string().into_bytes())
+    }
+}
+
+fn handle_packet(packet: Packet, tx: &mpsc::UnboundedSender<Message>) -> Result<(), Error> {
+    let message = match packet.operation {
+        OP_AUTH_REPLY => {
+            let reply: Value = serde_json::from_slice(&packet.body)?;
+            if reply["code"].as_i64() != Some(0) {
+                Err(format!("auth rejected: {}", reply))?;
+            }
+            Message::Connected
+        }
+        OP_HEARTBEAT_REPLY => Message::Popularity(u32::from_be_bytes(
+            packet
+                .body
+                .get(0..4)
+                .ok_or("truncated heartbeat reply")?
+                .try_into()?,
+        )),
+        OP_MESSAGE => Message::Command(serde_json::from_slice(&packet.body)?),
+        _ => return Ok(()),
+    };
+    tx.send(message).map_err(|_| "message receiver dropped")?;
+    Ok(())
+}
+
+/// Runs one websocket session against `url` until it fails or closes.
+pub async fn session(
+    url: &str,
+    auth: &Auth,
+    tx: &mpsc::UnboundedSender<Message>,
+) -> Result<(), Error> {
+    let mut request = url.into_client_request()?;
+    request
+        .headers_mut()
+        .insert("Origin", "https://live.bilibili.com".parse()?);
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await?;
+    ws.send(tungstenite::Message::binary(auth.packet().encode()))
+        .await?;
+
+    let heartbeat = Packet::new(OP_HEARTBEAT, b"[object Object]".to_vec()).encode();
+    let mut ticker = interval(HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                ws.send(tungstenite::Message::binary(heartbeat.clone())).await?;
+            }
+            message = ws.next() => match message {
+                Some(Ok(tungstenite::Message::Binary(data))) => {
+                    for packet in Packet::decode(&data)? {
+                        handle_packet(packet, tx)?;
+                    }
+                }
+                Some(Ok(tungstenite::Message::Close(_))) | None => {
+                    return Err("connection closed".into());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+            _ = tx.closed() => return Ok(()),
+        }
+    }
+}
+
+/// Fetches a fresh token and host list, falling back to the default host.
+async fn connect_info(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+) -> Result<(Auth, Vec<String>), String> {
+    let uid = cookies
+        .get("DedeUserID")
+        .and_then(|uid| uid.parse().ok())
+        .unwrap_or(0);
+    let buvid = live::get_buvid(cookies).await.map_err(|e| e.to_string())?;
+    let (token, mut hosts) = live::get_danmu_info(cookies, room_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    if hosts.is_empty() {
+        hosts.push("wss://broadcastlv.chat.bilibili.com/sub".to_string());
+    }
+    let auth = Auth {
+        uid,
+        room_id,
+        buvid,
+        token,
+    };
+    Ok((auth, hosts))
+}
+
+/// Connects to the live messages of `room_id` in the background, delivering
+/// into `tx` and reconnecting with backoff until the receiver is dropped.
+pub fn spawn(tx: mpsc::UnboundedSender<Message>, cookies: HashMap<String, String>, room_id: u64) {
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 0;
+        while !tx.is_closed() {
+            let error = match connect_info(&cookies, room_id).await {
+                Ok((auth, hosts)) => {
+                    let url = &hosts[attempt % hosts.len()];
+                    let started = Instant::now();
+                    let result = session(url, &auth, &tx).await.map_err(|e| e.to_string());
+                    if started.elapsed() > Duration::from_secs(60) {
+                        backoff = Duration::from_secs(1);
+                    }
+                    match result {
+                        Ok(()) => return,
+                        Err(e) => e,
+                    }
+                }
+                Err(e) => e,
+            };
+            if tx.send(Message::Disconnected(error)).is_err() {
+                return;
+            }
+            attempt += 1;
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::net::TcpListener;
+
+    fn command(cmd: &str) -> Packet {
+        Packet::new(OP_MESSAGE, json!({"cmd": cmd}).to_string().into_bytes())
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        brotli::BrotliCompress(&mut &data[..], &mut out, &Default::default()).unwrap();
+        out
+    }
+
+    /// A message packet carrying `packets` compressed with `protover`.
+    fn wrapped(protover: u16, packets: &[Packet]) -> Packet {
+        let inner = packets.iter().flat_map(Packet::encode).collect::<Vec<_>>();
+        let body = match protover {
+            PROTOVER_ZLIB => zlib(&inner),
+            PROTOVER_BROTLI => brotli(&inner),
+            _ => unreachable!(),
+        };
+        Packet {
+            protover,
+            operation: OP_MESSAGE,
+            sequence: 0,
+            body,
+        }
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = Packet::new(OP_HEARTBEAT, b"[object Object]".to_vec());
+        let data = packet.encode();
+        assert_eq!(
+            data[..HEADER_LEN],
+            [0, 0, 0, 31, 0, 16, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            Packet::decode(&data).unwrap(),
+            std::slice::from_ref(&packet)
+        );
+
+        let mut data = command("LIVE").encode();
+        data.extend(packet.encode());
+        assert_eq!(Packet::decode(&data).unwrap(), [command("LIVE"), packet]);
+        assert_eq!(Packet::decode(&[]).unwrap(), []);
+    }
+
+    #[test]
+    fn packet_compressed() {
+        for protover in [PROTOVER_ZLIB, PROTOVER_BROTLI] {
+            let data = wrapped(protover, &[command("LIVE"), command("PREPARING")]).encode();
+            assert_eq!(
+                Packet::decode(&data).unwrap(),
+                [command("LIVE"), command("PREPARING")]
+            );
+        }
+        // only message bodies are compressed
+        let packet = Packet {
+            operation: OP_HEARTBEAT_REPLY,
+            ..wrapped(PROTOVER_ZLIB, &[command("LIVE")])
+        };
+        assert_eq!(Packet::decode(&packet.encode()).unwrap(), [packet]);
+    }
+
+    #[test]
+    fn packet_invalid() {
+        let data = command("LIVE").encode();
+        let error = Packet::decode(&data[..10]).unwrap_err().to_string();
+        assert!(error.contains("truncated packet header"), "{}", error);
+        // body cut short
+        let error = Packet::decode(&data[..data.len() - 1]).unwrap_err();
+        assert!(error.to_string().contains("invalid packet length"));
+        // a second packet with half a header
+        let mut twice = data.clone();
+        twice.extend(&data[..4]);
+        assert!(Packet::decode(&twice).is_err());
+
+        let mut short_header = data.clone();
+        short_header[4..6].copy_from_slice(&8u16.to_be_bytes());
+        assert!(Packet::decode(&short_header).is_err());
+        let mut short_total = data.clone();
+        short_total[0..4].copy_from_slice(&8u32.to_be_bytes());
+        assert!(Packet::decode(&short_total).is_err());
+        let mut bad_zlib = wrapped(PROTOVER_ZLIB, &[command("LIVE")]);
+        bad_zlib.body.truncate(4);
+        assert!(Packet::decode(&bad_zlib.encode()).is_err());
+    }
+
+    #[tokio::test]
+    async fn session_with_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/sub", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut next_packet = async || match ws.next().await {
+                Some(Ok(tungstenite::Message::Binary(data))) => {
+                    Packet::decode(&data).unwrap().remove(0)
+                }
+                other => panic!("{:?}", other),
+            };
+            let auth = next_packet().await;
+            // the heartbeat goes out right after the auth packet
+            let heartbeat = next_packet().await;
+
+            let replies = [
+                Packet::new(OP_AUTH_REPLY, br#"{"code":0}"#.to_vec()),
+                Packet::new(OP_HEARTBEAT_REPLY, 1234u32.to_be_bytes().to_vec()),
+                wrapped(PROTOVER_ZLIB, &[command("DANMU_MSG"), command("SEND_GIFT")]),
+                wrapped(PROTOVER_BROTLI, &[command("LIVE")]),
+            ];
+            for reply in replies {
+                ws.send(tungstenite::Message::binary(reply.encode()))
+                    .await
+                    .unwrap();
+            }
+            ws.close(None).await.unwrap();
+            (auth, heartbeat)
+        });
+
+        let auth = Auth {
+            uid: 42,
+            room_id: 1000,
+            buvid: "buvid".to_string(),
+            token: "token".to_string(),
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let error = session(&url, &auth, &tx).await.unwrap_err();
+        assert_eq!(error.to_string(), "connection closed");
+
+        let (auth_packet, heartbeat) = server.await.unwrap();
+        assert_eq!(
+            (auth_packet.operation, auth_packet.protover),
+            (OP_AUTH, PROTOVER_INT)
+        );
+        let body: Value = serde_json::from_slice(&auth_packet.body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "uid": 42,
+                "roomid": 1000,
+                "protover": PROTOVER_BROTLI,
+                "buvid": "buvid",
+                "platform": "web",
+                "type": 2,
+                "key": "token",
+            })
+        );
+        assert_eq!(heartbeat.operation, OP_HEARTBEAT);
+
+        assert!(matches!(rx.try_recv(), Ok(Message::Connected)));
+        assert!(matches!(rx.try_recv(), Ok(Message::Popularity(1234))));
+        for cmd in ["DANMU_MSG", "SEND_GIFT", "LIVE"] {
+            match rx.try_recv() {
+                Ok(Message::Command(value)) => assert_eq!(value["cmd"], cmd),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::rtmp;
+use serde::Serialize;
+use tokio::{
+    net::{TcpStream, lookup_host},
+    time::{Duration, Instant, timeout},
+};
+
+/// What checking one ingest line found, times in milliseconds.
+#[derive(Serialize, Debug, Clone)]
+pub struct LineReport {
+    pub addr: String,
+    pub provider: String,
+    pub host: String,
+    pub port: u16,
+    pub ips: Vec<String>,
+    pub dns_ms: Option<f64>,
+    /// median over the successful tries
+    pub connect_ms: Option<f64>,
+    /// median over the successful tries
+    pub handshake_ms: Option<f64>,
+    pub tries: usize,
+    pub failures: usize,
+    /// the last error, if any try failed
+    pub error: Option<String>,
+}
+
+impl LineReport {
+    /// Lower is better, `None` when the line never completed a handshake.
+    /// Failed tries count as a full timeout each.
+    pub fn score(&self, timeout: Duration) -> Option<f64> {
+        let time = self.connect_ms? + self.handshake_ms?;
+        let failed = self.failures as f64 / self.tries as f64;
+        Some(time + failed * timeout.as_secs_f64() * 1000.0)
+    }
+}
+
+fn millis(since: Instant) -> f64 {
+    (since.elapsed().as_secs_f64() * 1000.0 * 10.0).round() / 10.0
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+async fn with_timeout<T>(
+    limit: Duration,
+    what: &str,
+    future: impl Future<Output = Result<T, rtmp::Error>>,
+) -> Result<T, rtmp::Error> {
+    timeout(limit, future)
+        .await
+        .map_err(|_| format!("{} timed out after {:?}", what, limit))?
+}
+
+/// Resolves the host of `addr`, then connects to it and does an RTMP
+/// handshake `tries` times, each on a new connection.
+pub async fn check_line(addr: &str, provider: &str, tries: usize, limit: Duration) -> LineReport {
+    let mut report = LineReport {
+        addr: addr.to_string(),
+        provider: provider.to_string(),
+        host: String::new(),
+        port: rtmp::DEFAULT_PORT,
+        ips: vec![],
+        dns_ms: None,
+        connect_ms: None,
+        handshake_ms: None,
+        tries,
+        failures: tries,
+        error: None,
+    };
+    let target = match rtmp::Target::parse(addr) {
+        Ok(target) => target,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.host = target.host.clone();
+    report.port = target.port;
+
+    let begin = Instant::now();
+    let resolved = with_timeout(limit, "dns lookup", async {
+        Ok(lookup_host((target.host.as_str(), target.port))
+            .await?
+            .collect::<Vec<_>>())
+    })
+    .await;
+    let socket_addr = match resolved {
+        Ok(addrs) if !addrs.is_empty() => {
+            report.dns_ms = Some(millis(begin));
+            report.ips = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+            addrs[0]
+        }
+        Ok(_) => {
+            report.error = Some(format!("{} has no address", target.host));
+            return report;
+        }
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+
+    let mut connects = vec![];
+    let mut handshakes = vec![];
+    for _ in 0..tries {
+        let begin = Instant::now();
+        let connected = with_timeout(limit, "connect", async {
+            Ok(TcpStream::connect(socket_addr).await?)
+        })
+        .await;
+        let mut stream = match connected {
+            Ok(stream) => stream,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                continue;
+            }
+        };
+        let connect_ms = millis(begin);
+        let begin = Instant::now();
+        match with_timeout(limit, "handshake", rtmp::handshake(&mut stream)).await {
+            Ok(()) => {
+                connects.push(connect_ms);
+                handshakes.push(millis(begin));
+            }
+            Err(e) => report.error = Some(e.to_string()),
+        }
+    }
+    report.failures = tries - handshakes.len();
+    report.connect_ms = median(connects);
+    report.handshake_ms = median(handshakes);
+    report
+}
+
+/// The index of the line to use, if any line works.
+pub fn best(reports: &[LineReport], timeout: Duration) -> Option<usize> {
+    reports
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, report)| Some((idx, report.score(timeout)?)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(idx, _)| idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// The size of C1, S1 and so on.
+    const HANDSHAKE_SIZE: usize = 1536;
+
+    /// How a stand-in ingest answers one connection.
+    #[derive(Clone, Copy)]
+    enum Answer {
+        /// a handshake after the delay
+        Handshake(Duration),
+        Close,
+        Silent,
+    }
+
+    /// Serves one connection per answer on a local port, returning the
+    /// address of the line.
+    async fn stand_in(answers: Vec<Answer>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("rtmp://{}/live-bvc/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = vec![];
+            for answer in answers {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                match answer {
+                    Answer::Handshake(delay) => {
+                        let mut c0c1 = vec![0; 1 + HANDSHAKE_SIZE];
+                        stream.read_exact(&mut c0c1).await.unwrap();
+                        tokio::time::sleep(delay).await;
+                        let mut s0s1s2 = vec![0x03];
+                        s0s1s2.extend_from_slice(&[0; HANDSHAKE_SIZE]);
+                        s0s1s2.extend_from_slice(&c0c1[1..]);
+                        stream.write_all(&s0s1s2).await.unwrap();
+                        let mut c2 = vec![0; HANDSHAKE_SIZE];
+                        stream.read_exact(&mut c2).await.unwrap();
+                    }
+                    Answer::Close => drop(stream),
+                    Answer::Silent => held.push(stream),
+                }
+            }
+            // keep silent connections open until the test is over
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn check_line_times_the_handshakes() {
+        let delay = Duration::from_millis(50);
+        let addr = stand_in(vec![Answer::Handshake(delay); 3]).await;
+        let report = check_line(&addr, "local", 3, Duration::from_secs(2)).await;
+        assert_eq!(report.host, "127.0.0.1");
+        assert_eq!(report.ips, ["127.0.0.1"]);
+        assert!(report.dns_ms.is_some());
+        assert!(report.connect_ms.is_some());
+        assert!(report.handshake_ms.unwrap() >= delay.as_millis() as f64);
+        assert_eq!((report.tries, report.failures), (3, 0));
+        assert_eq!(report.error, None);
+        assert!(report.score(Duration::from_secs(2)).is_some());
+    }
+
+    #[tokio::test]
+    async fn check_line_counts_failures() {
+        let answers = vec![
+            Answer::Handshake(Duration::ZERO),
+            Answer::Close,
+            Answer::Handshake(Duration::ZERO),
+        ];
+        let addr = stand_in(answers).await;
+        let report = check_line(&addr, "local", 3, Duration::from_secs(2)).await;
+        assert_eq!((report.tries, report.failures), (3, 1));
+        assert!(report.handshake_ms.is_some());
+        assert!(report.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn check_line_times_out() {
+        let addr = stand_in(vec![Answer::Silent; 2]).await;
+        let limit = Duration::from_millis(200);
+        let report = check_line(&addr, "local", 2, limit).await;
+        assert_eq!((report.tries, report.failures), (2, 2));
+        assert_eq!(report.handshake_ms, None);
+        assert_eq!(
+            report.error.as_deref(),
+            Some("handshake timed out after 200ms")
+        );
+        assert_eq!(report.score(limit), None);
+    }
+
+    #[tokio::test]
+    async fn check_line_refused_or_invalid() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("rtmp://{}/live-bvc/", listener.local_addr().unwrap());
+        drop(listener);
+        let report = check_line(&addr, "local", 2, Duration::from_secs(2)).await;
+        assert_eq!(report.failures, 2);
+        assert!(report.error.is_some());
+
+        let report = check_line("http://127.0.0.1/", "local", 2, Duration::from_secs(2)).await;
+        assert_eq!(report.failures, 2);
+        assert_eq!(report.dns_ms, None);
+        assert_eq!(
+            report.error.as_deref(),
+            Some("not an rtmp:// address: http://127.0.0.1/")
+        );
+    }
+
+    fn report(times: Option<(f64, f64)>, failures: usize) -> LineReport {
+        LineReport {
+            addr: String::new(),
+            provider: String::new(),
+            host: String::new(),
+            port: rtmp::DEFAULT_PORT,
+            ips: vec![],
+            dns_ms: None,
+            connect_ms: times.map(|(connect, _)| connect),
+            handshake_ms: times.map(|(_, handshake)| handshake),
+            tries: 4,
+            failures,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn best_line() {
+        let limit = Duration::from_secs(2);
+        // a quarter of the tries failing costs half a second
+        assert_eq!(report(Some((10.0, 20.0)), 1).score(limit), Some(530.0));
+        let reports = [
+            report(None, 4),
+            report(Some((10.0, 20.0)), 1),
+            report(Some((100.0, 200.0)), 0),
+            report(Some((50.0, 60.0)), 0),
+        ];
+        assert_eq!(best(&reports, limit), Some(3));
+        // the flaky line wins when failures are cheap
+        assert_eq!(best(&reports, Duration::from_millis(100)), Some(1));
+        assert_eq!(best(&reports[..1], limit), None);
+        assert_eq!(best(&[], limit), None);
+    }
+}
@@ -0,0 +1,697 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A fan medal, `guard_level` is 0 for none, 1 for 总督, 2 for 提督 and 3 for 舰长.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Medal {
+    pub name: String,
+    pub level: u64,
+    pub guard_level: u64,
+    pub anchor_uid: u64,
+    pub anchor_room_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct User {
+    pub uid: u64,
+    pub name: String,
+    pub face: Option<String>,
+    pub medal: Option<Medal>,
+    pub guard_level: u64,
+    pub user_level: u64,
+    pub is_admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Emoticon {
+    pub unique: String,
+    pub url: String,
+    pub width: u64,
+    pub height: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Danmu {
+    pub user: User,
+    pub text: String,
+    /// 1 scroll, 4 bottom, 5 top
+    pub mode: u64,
+    pub font_size: u64,
+    pub color: u64,
+    pub timestamp_ms: i64,
+    pub emoticon: Option<Emoticon>,
+    pub reply_to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Gift {
+    pub user: User,
+    pub gift_id: u64,
+    pub gift_name: String,
+    pub num: u64,
+    /// price of one gift in gold coins (1000 per CNY) or silver coins
+    pub price: u64,
+    pub coin_type: String,
+    pub action: String,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Combo {
+    pub user: User,
+    pub gift_id: u64,
+    pub gift_name: String,
+    pub combo_num: u64,
+    pub total_num: u64,
+    /// gold coins of the whole combo
+    pub combo_total_coin: u64,
+    pub action: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuperChat {
+    pub id: u64,
+    pub user: User,
+    pub message: String,
+    /// price in CNY
+    pub price: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub background_color: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GuardBuy {
+    pub uid: u64,
+    pub name: String,
+    pub guard_level: u64,
+    pub num: u64,
+    /// gold coins (1000 per CNY)
+    pub price: u64,
+    pub gift_name: String,
+    pub start_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractKind {
+    Enter,
+    Follow,
+    Share,
+    SpecialFollow,
+    MutualFollow,
+    Other(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interact {
+    pub user: User,
+    pub kind: InteractKind,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomChange {
+    pub title: String,
+    pub area_id: u64,
+    pub area_name: String,
+    pub parent_area_id: u64,
+    pub parent_area_name: String,
+}
+
+/// A live room message, commands without a typed variant are kept as `Unknown`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Danmu(Danmu),
+    Gift(Gift),
+    Combo(Combo),
+    SuperChat(SuperChat),
+    GuardBuy(GuardBuy),
+    Interact(Interact),
+    OnlineRankCount {
+        count: u64,
+        online_count: Option<u64>,
+    },
+    WatchedChange {
+        num: u64,
+        text: String,
+    },
+    Live {
+        live_time: Option<i64>,
+    },
+    Preparing,
+    RoomChange(RoomChange),
+    Warning {
+        message: String,
+    },
+    CutOff {
+        message: String,
+    },
+    Unknown {
+        raw: Value,
+    },
+}
+
+/// Numbers come as either JSON numbers or strings depending on the command.
+fn as_u64(v: &Value) -> Option<u64> {
+    match v {
+        Value::Number(n) => n.as_u64().or_else(|| n.as_f64().map(|f| f as u64)),
+        Value::String(s) => s.parse().ok(),
+        Value::Bool(b) => Some(*b as u64),
+        _ => None,
+    }
+}
+
+fn as_i64(v: &Value) -> Option<i64> {
+    match v {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_string(v: &Value) -> Option<String> {
+    v.as_str().map(str::to_string)
+}
+
+/// The `medal_info` / `fans_medal` object of gift, super chat and interact commands.
+fn parse_medal(v: &Value) -> Option<Medal> {
+    let name = as_string(&v["medal_name"]).filter(|name| !name.is_empty())?;
+    Some(Medal {
+        name,
+        level: as_u64(&v["medal_level"]).unwrap_or(0),
+        guard_level: as_u64(&v["guard_level"]).unwrap_or(0),
+        anchor_uid: as_u64(&v["target_id"]).unwrap_or(0),
+        anchor_room_id: as_u64(&v["anchor_roomid"]).unwrap_or(0),
+    })
+}
+
+fn parse_danmu(info: &Value) -> Option<Danmu> {
+    let meta = &info[0];
+    let medal = match info[3].as_array() {
+        Some(medal) if !medal.is_empty() => Some(Medal {
+            level: as_u64(&medal[0])?,
+            name: as_string(&medal[1])?,
+            anchor_room_id: as_u64(&medal[3]).unwrap_or(0),
+            guard_level: medal.get(10).and_then(as_u64).unwrap_or(0),
+            anchor_uid: medal.get(12).and_then(as_u64).unwrap_or(0),
+        }),
+        _ => None,
+    };
+    let mut uid = as_u64(&info[2][0])?;
+    if uid == 0 {
+        // the uid is hidden from anonymous connections, newer payloads repeat it here
+        uid = as_u64(&meta[15]["user"]["uid"]).unwrap_or(0);
+    }
+    let user = User {
+        uid,
+        name: as_string(&info[2][1])?,
+        face: as_string(&meta[15]["user"]["base"]["face"]),
+        medal,
+        guard_level: as_u64(&info[7]).unwrap_or(0),
+        user_level: as_u64(&info[4][0]).unwrap_or(0),
+        is_admin: as_u64(&info[2][2]).unwrap_or(0) != 0,
+    };
+    let emoticon = meta[13]["emoticon_unique"].as_str().map(|unique| Emoticon {
+        unique: unique.to_string(),
+        url: as_string(&meta[13]["url"]).unwrap_or_default(),
+        width: as_u64(&meta[13]["width"]).unwrap_or(0),
+        height: as_u64(&meta[13]["height"]).unwrap_or(0),
+    });
+    let extra = meta[15]["extra"]
+        .as_str()
+        .and_then(|extra| serde_json::from_str::<Value>(extra).ok())
+        .unwrap_or(Value::Null);
+    Some(Danmu {
+        user,
+        text: as_string(&info[1])?,
+        mode: as_u64(&meta[1]).unwrap_or(1),
+        font_size: as_u64(&meta[2]).unwrap_or(25),
+        color: as_u64(&meta[3]).unwrap_or(0xffffff),
+        timestamp_ms: as_i64(&meta[4]).unwrap_or(0),
+        emoticon,
+        reply_to: as_string(&extra["reply_uname"]).filter(|name| !name.is_empty()),
+    })
+}
+
+fn parse_gift(data: &Value) -> Option<Gift> {
+    Some(Gift {
+        user: User {
+            uid: as_u64(&data["uid"])?,
+            name: as_string(&data["uname"])?,
+            face: as_string(&data["face"]),
+            medal: parse_medal(&data["medal_info"]),
+            guard_level: as_u64(&data["guard_level"]).unwrap_or(0),
+            ..User::default()
+        },
+        gift_id: as_u64(&data["giftId"])?,
+        gift_name: as_string(&data["giftName"])?,
+        num: as_u64(&data["num"])?,
+        price: as_u64(&data["price"]).unwrap_or(0),
+        coin_type: as_string(&data["coin_type"]).unwrap_or_default(),
+        action: as_string(&data["action"]).unwrap_or_default(),
+        timestamp: as_i64(&data["timestamp"]).unwrap_or(0),
+    })
+}
+
+fn parse_combo(data: &Value) -> Option<Combo> {
+    Some(Combo {
+        user: User {
+            uid: as_u64(&data["uid"])?,
+            name: as_string(&data["uname"])?,
+            medal: parse_medal(&data["medal_info"]),
+            ..User::default()
+        },
+        gift_id: as_u64(&data["gift_id"])?,
+        gift_name: as_string(&data["gift_name"])?,
+        combo_num: as_u64(&data["combo_num"]).unwrap_or(0),
+        total_num: as_u64(&data["total_num"]).unwrap_or(0),
+        combo_total_coin: as_u64(&data["combo_total_coin"]).unwrap_or(0),
+        action: as_string(&data["action"]).unwrap_or_default(),
+    })
+}
+
+fn parse_super_chat(data: &Value) -> Option<SuperChat> {
+    let user_info = &data["user_info"];
+    Some(SuperChat {
+        id: as_u64(&data["id"])?,
+        user: User {
+            uid: as_u64(&data["uid"])?,
+            name: as_string(&user_info["uname"])?,
+            face: as_string(&user_info["face"]),
+            medal: parse_medal(&data["medal_info"]),
+            guard_level: as_u64(&user_info["guard_level"]).unwrap_or(0),
+            user_level: as_u64(&user_info["user_level"]).unwrap_or(0),
+            is_admin: as_u64(&user_info["manager"]).unwrap_or(0) != 0,
+        },
+        message: as_string(&data["message"])?,
+        price: as_u64(&data["price"])?,
+        start_time: as_i64(&data["start_time"]).unwrap_or(0),
+        end_time: as_i64(&data["end_time"]).unwrap_or(0),
+        background_color: as_string(&data["background_color"]).unwrap_or_default(),
+    })
+}
+
+fn parse_guard_buy(data: &Value) -> Option<GuardBuy> {
+    Some(GuardBuy {
+        uid: as_u64(&data["uid"])?,
+        name: as_string(&data["username"])?,
+        guard_level: as_u64(&data["guard_level"])?,
+        num: as_u64(&data["num"]).unwrap_or(1),
+        price: as_u64(&data["price"]).unwrap_or(0),
+        gift_name: as_string(&data["gift_name"]).unwrap_or_default(),
+        start_time: as_i64(&data["start_time"]).unwrap_or(0),
+    })
+}
+
+fn parse_interact(data: &Value) -> Option<Interact> {
+    let medal = parse_medal(&data["fans_medal"]);
+    Some(Interact {
+        user: User {
+            uid: as_u64(&data["uid"])?,
+            name: as_string(&data["uname"])?,
+            guard_level: medal.as_ref().map_or(0, |medal| medal.guard_level),
+            medal,
+            ..User::default()
+        },
+        kind: match as_u64(&data["msg_type"])? {
+            1 => InteractKind::Enter,
+            2 => InteractKind::Follow,
+            3 => InteractKind::Share,
+            4 => InteractKind::SpecialFollow,
+            5 => InteractKind::MutualFollow,
+            other => InteractKind::Other(other),
+        },
+        timestamp: as_i64(&data["timestamp"]).unwrap_or(0),
+    })
+}
+
+fn parse_room_change(data: &Value) -> Option<RoomChange> {
+    Some(RoomChange {
+        title: as_string(&data["title"])?,
+        area_id: as_u64(&data["area_id"]).unwrap_or(0),
+        area_name: as_string(&data["area_name"]).unwrap_or_default(),
+        parent_area_id: as_u64(&data["parent_area_id"]).unwrap_or(0),
+        parent_area_name: as_string(&data["parent_area_name"]).unwrap_or_default(),
+    })
+}
+
+impl Event {
+    /// Parses a command from the danmaku websocket, malformed known
+    /// commands are kept as `Unknown` as well.
+    pub fn parse(raw: Value) -> Event {
+        let data = &raw["data"];
+        // the cmd may carry suffixes such as `DANMU_MSG:4:0:2:2:2:0`
+        let cmd = raw["cmd"].as_str().unwrap_or_default();
+        let event = match cmd.split(':').next().unwrap_or_default() {
+            "DANMU_MSG" => parse_danmu(&raw["info"]).map(Event::Danmu),
+            "SEND_GIFT" => parse_gift(data).map(Event::Gift),
+            "COMBO_SEND" => parse_combo(data).map(Event::Combo),
+            "SUPER_CHAT_MESSAGE" => parse_super_chat(data).map(Event::SuperChat),
+            "GUARD_BUY" => parse_guard_buy(data).map(Event::GuardBuy),
+            "INTERACT_WORD" => parse_interact(data).map(Event::Interact),
+            "ONLINE_RANK_COUNT" => as_u64(&data["count"]).map(|count| Event::OnlineRankCount {
+                count,
+                online_count: as_u64(&data["online_count"]),
+            }),
+            "WATCHED_CHANGE" => as_u64(&data["num"]).map(|num| Event::WatchedChange {
+                num,
+                text: as_string(&data["text_small"]).unwrap_or_default(),
+            }),
+            "LIVE" => Some(Event::Live {
+                live_time: as_i64(&raw["live_time"]),
+            }),
+            "PREPARING" => Some(Event::Preparing),
+            "ROOM_CHANGE" => parse_room_change(data).map(Event::RoomChange),
+            "WARNING" => as_string(&raw["msg"]).map(|message| Event::Warning { message }),
+            "CUT_OFF" => as_string(&raw["msg"]).map(|message| Event::CutOff { message }),
+            _ => None,
+        };
+        event.unwrap_or(Event::Unknown { raw })
+    }
+
+    /// The serialized `type` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Danmu(_) => "danmu",
+            Event::Gift(_) => "gift",
+            Event::Combo(_) => "combo",
+            Event::SuperChat(_) => "super_chat",
+            Event::GuardBuy(_) => "guard_buy",
+            Event::Interact(_) => "interact",
+            Event::OnlineRankCount { .. } => "online_rank_count",
+            Event::WatchedChange { .. } => "watched_change",
+            Event::Live { .. } => "live",
+            Event::Preparing => "preparing",
+            Event::RoomChange(_) => "room_change",
+            Event::Warning { .. } => "warning",
+            Event::CutOff { .. } => "cut_off",
+            Event::Unknown { .. } => "unknown",
+        }
+    }
+
+    /// Server time of the event in milliseconds, when the command carries one.
+    pub fn timestamp_ms(&self) -> Option<i64> {
+        let seconds = match self {
+            Event::Danmu(danmu) => return Some(danmu.timestamp_ms).filter(|&ts| ts > 0),
+            Event::Gift(gift) => gift.timestamp,
+            Event::SuperChat(sc) => sc.start_time,
+            Event::GuardBuy(guard) => guard.start_time,
+            Event::Interact(interact) => interact.timestamp,
+            Event::Live { live_time } => (*live_time)?,
+            Event::Unknown { raw } => return as_i64(&raw["send_time"]),
+            _ => return None,
+        };
+        Some(seconds * 1000).filter(|&ts| ts > 0)
+    }
+
+    pub fn user(&self) -> Option<&User> {
+        match self {
+            Event::Danmu(danmu) => Some(&danmu.user),
+            Event::Gift(gift) => Some(&gift.user),
+            Event::Combo(combo) => Some(&combo.user),
+            Event::SuperChat(sc) => Some(&sc.user),
+            Event::Interact(interact) => Some(&interact.user),
+            _ => None,
+        }
+    }
+
+    pub fn uid(&self) -> Option<u64> {
+        match self {
+            Event::GuardBuy(guard) => Some(guard.uid),
+            _ => self.user().map(|user| user.uid),
+        }
+    }
+
+    pub fn user_name(&self) -> Option<&str> {
+        match self {
+            Event::GuardBuy(guard) => Some(&guard.name),
+            _ => self.user().map(|user| user.name.as_str()),
+        }
+    }
+
+    /// Money spent in CNY, for paid gifts, guards and super chats.
+    pub fn cny(&self) -> Option<f64> {
+        match self {
+            Event::Gift(gift) if gift.coin_type == "gold" => {
+                Some((gift.price * gift.num) as f64 / 1000.0)
+            }
+            Event::GuardBuy(guard) => Some((guard.price * guard.num) as f64 / 1000.0),
+            Event::SuperChat(sc) => Some(sc.price as f64),
+            _ => None,
+        }
+    }
+
+    /// The text written by the user, for danmaku and super chats.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Event::Danmu(danmu) => Some(&danmu.text),
+            Event::SuperChat(sc) => Some(&sc.message),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `tests/fixtures/events/<name>.json`.
+    fn fixture(name: &str) -> Event {
+        let path = format!(
+            "{}/tests/fixtures/events/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        Event::parse(serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap())
+    }
+
+    fn medal(level: u64, guard_level: u64) -> Option<Medal> {
+        Some(Medal {
+            name: "粉丝牌".to_string(),
+            level,
+            guard_level,
+            anchor_uid: 67890,
+            anchor_room_id: 1000,
+        })
+    }
+
+    #[test]
+    fn danmu() {
+        let Event::Danmu(danmu) = fixture("DANMU_MSG") else {
+            panic!()
+        };
+        assert_eq!(
+            danmu,
+            Danmu {
+                user: User {
+                    uid: 12345,
+                    name: "观众A".to_string(),
+                    face: Some("https://i0.hdslb.com/bfs/face/a.jpg".to_string()),
+                    medal: medal(21, 3),
+                    guard_level: 3,
+                    user_level: 12,
+                    is_admin: true,
+                },
+                text: "好耶".to_string(),
+                mode: 1,
+                font_size: 25,
+                color: 0xffffff,
+                timestamp_ms: 1700000000123,
+                emoticon: Some(Emoticon {
+                    unique: "upower_[主播_好耶]".to_string(),
+                    url: "https://i0.hdslb.com/bfs/emote/haoye.png".to_string(),
+                    width: 60,
+                    height: 60,
+                }),
+                reply_to: Some("观众B".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn gift() {
+        let event = fixture("SEND_GIFT");
+        assert_eq!(event.cny(), Some(0.5));
+        let Event::Gift(gift) = event else { panic!() };
+        assert_eq!(
+            gift,
+            Gift {
+                user: User {
+                    uid: 23456,
+                    name: "观众B".to_string(),
+                    face: Some("https://i0.hdslb.com/bfs/face/b.jpg".to_string()),
+                    medal: medal(21, 3),
+                    ..User::default()
+                },
+                gift_id: 31036,
+                gift_name: "小花花".to_string(),
+                num: 5,
+                price: 100,
+                coin_type: "gold".to_string(),
+                action: "投喂".to_string(),
+                timestamp: 1700000100,
+            }
+        );
+    }
+
+    #[test]
+    fn combo() {
+        let Event::Combo(combo) = fixture("COMBO_SEND") else {
+            panic!()
+        };
+        assert_eq!(
+            combo,
+            Combo {
+                user: User {
+                    uid: 23456,
+                    name: "观众B".to_string(),
+                    medal: medal(21, 3),
+                    ..User::default()
+                },
+                gift_id: 31036,
+                gift_name: "小花花".to_string(),
+                combo_num: 10,
+                total_num: 10,
+                combo_total_coin: 1000,
+                action: "投喂".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn super_chat() {
+        let event = fixture("SUPER_CHAT_MESSAGE");
+        assert_eq!(event.timestamp_ms(), Some(1700000200000));
+        let Event::SuperChat(sc) = event else {
+            panic!()
+        };
+        assert_eq!(
+            sc,
+            SuperChat {
+                id: 8123456,
+                user: User {
+                    uid: 34567,
+                    name: "观众C".to_string(),
+                    face: Some("https://i0.hdslb.com/bfs/face/c.jpg".to_string()),
+                    medal: medal(21, 3),
+                    guard_level: 3,
+                    user_level: 20,
+                    is_admin: true,
+                },
+                message: "主播加油".to_string(),
+                price: 30,
+                start_time: 1700000200,
+                end_time: 1700000260,
+                background_color: "#EDF5FF".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn guard_buy() {
+        let event = fixture("GUARD_BUY");
+        assert_eq!(
+            (event.uid(), event.user_name()),
+            (Some(45678), Some("观众D"))
+        );
+        assert_eq!(
+            event,
+            Event::GuardBuy(GuardBuy {
+                uid: 45678,
+                name: "观众D".to_string(),
+                guard_level: 3,
+                num: 1,
+                price: 198000,
+                gift_name: "舰长".to_string(),
+                start_time: 1700000300,
+            })
+        );
+    }
+
+    #[test]
+    fn interact() {
+        assert_eq!(
+            fixture("INTERACT_WORD"),
+            Event::Interact(Interact {
+                user: User {
+                    uid: 56789,
+                    name: "观众E".to_string(),
+                    medal: medal(25, 2),
+                    guard_level: 2,
+                    ..User::default()
+                },
+                kind: InteractKind::Follow,
+                timestamp: 1700000400,
+            })
+        );
+    }
+
+    #[test]
+    fn counters() {
+        assert_eq!(
+            fixture("ONLINE_RANK_COUNT"),
+            Event::OnlineRankCount {
+                count: 1234,
+                online_count: Some(5678),
+            }
+        );
+        assert_eq!(
+            fixture("WATCHED_CHANGE"),
+            Event::WatchedChange {
+                num: 98765,
+                text: "9.8万".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn room_state() {
+        assert_eq!(
+            fixture("LIVE"),
+            Event::Live {
+                live_time: Some(1700000500),
+            }
+        );
+        assert_eq!(fixture("PREPARING"), Event::Preparing);
+        assert_eq!(
+            fixture("ROOM_CHANGE"),
+            Event::RoomChange(RoomChange {
+                title: "新标题".to_string(),
+                area_id: 236,
+                area_name: "主机游戏".to_string(),
+                parent_area_id: 6,
+                parent_area_name: "单机游戏".to_string(),
+            })
+        );
+        assert_eq!(
+            fixture("WARNING"),
+            Event::Warning {
+                message: "直播间标题违规，请立即修改".to_string(),
+            }
+        );
+        assert_eq!(
+            fixture("CUT_OFF"),
+            Event::CutOff {
+                message: "禁播游戏".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        let event = fixture("unknown");
+        assert_eq!(event.name(), "unknown");
+        assert_eq!(event.timestamp_ms(), Some(1700000600123));
+        let Event::Unknown { raw } = event else {
+            panic!()
+        };
+        assert_eq!(raw["cmd"], "STOP_LIVE_ROOM_LIST");
+        // a known command missing its fields is kept as well
+        assert!(matches!(
+            Event::parse(serde_json::json!({"cmd": "SEND_GIFT", "data": {}})),
+            Event::Unknown { .. }
+        ));
+    }
+}
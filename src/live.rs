@@ -1,6 +1,154 @@
 use serde_json::Value;
 use std::collections::HashMap;
 
+fn cookie_headers(cookies: &HashMap<String, String>) -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::COOKIE,
+        (cookies
+            .iter()
+            .map(|(k, v)| format!("{k}={v};"))
+            .collect::<Vec<_>>()
+            .join(" "))
+        .parse()
+        .unwrap(),
+    );
+    headers.insert(
+        reqwest::header::USER_AGENT,
+        "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36".parse().unwrap(),
+    );
+    headers
+}
+
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+/// Signs query `params` with the WBI keys from the nav endpoint, as some
+/// web APIs reject unsigned requests.
+async fn wbi_sign(
+    cookies: &HashMap<String, String>,
+    params: &[(&str, String)],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let res: Value = serde_json::from_slice(
+        reqwest::Client::new()
+            .get("https://api.bilibili.com/x/web-interface/nav")
+            .headers(cookie_headers(cookies))
+            .send()
+            .await?
+            .bytes()
+            .await?
+            .as_ref(),
+    )?;
+    let key = |url: &Value| -> Result<String, Box<dyn std::error::Error>> {
+        let url = url.as_str().ok_or("wbi_img is not a String")?;
+        let name = url.rsplit('/').next().unwrap_or(url);
+        Ok(name.split('.').next().unwrap_or(name).to_string())
+    };
+    let raw_key = key(&res["data"]["wbi_img"]["img_url"])?
+        + key(&res["data"]["wbi_img"]["sub_url"])?.as_str();
+    let raw_key = raw_key.as_bytes();
+    let mixin_key = MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|&i| raw_key.get(i).map(|&c| c as char))
+        .take(32)
+        .collect::<String>();
+
+    let mut params = params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect::<Vec<_>>();
+    params.push((
+        "wts".to_string(),
+        chrono::Utc::now().timestamp().to_string(),
+    ));
+    params.sort();
+    let query = params
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .chars()
+                .filter(|c| !"!'()*".contains(*c))
+                .collect::<String>();
+            format!("{}={}", urlencoding::encode(k), urlencoding::encode(&v))
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let w_rid = md5::compute(format!("{}{}", query, mixin_key));
+    Ok(format!("{}&w_rid={:x}", query, w_rid))
+}
+
+/// The `buvid3` device id, taken from the cookies when present.
+pub async fn get_buvid(
+    cookies: &HashMap<String, String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(buvid) = cookies.get("buvid3") {
+        return Ok(buvid.clone());
+    }
+    let mut res: Value = serde_json::from_slice(
+        reqwest::get("https://api.bilibili.com/x/frontend/finger/spi")
+            .await?
+            .bytes()
+            .await?
+            .as_ref(),
+    )?;
+    match res["data"]["b_3"].take() {
+        Value::String(buvid) => Ok(buvid),
+        _ => Err(format!("unexpected buvid reply: {}", res))?,
+    }
+}
+
+/// The danmaku websocket token and `wss://` host urls of a room.
+pub async fn get_danmu_info(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+) -> Result<(String, Vec<String>), Box<dyn std::error::Error>> {
+    let query = wbi_sign(
+        cookies,
+        &[
+            ("id", room_id.to_string()),
+            ("type", "0".to_string()),
+            ("web_location", "444.8".to_string()),
+        ],
+    )
+    .await?;
+    let mut res: Value = serde_json::from_slice(
+        reqwest::Client::new()
+            .get(format!(
+                "https://api.live.bilibili.com/xlive/web-room/v1/index/getDanmuInfo?{query}"
+            ))
+            .headers(cookie_headers(cookies))
+            .send()
+            .await?
+            .bytes()
+            .await?
+            .as_ref(),
+    )?;
+    if res["code"].as_i64() != Some(0) {
+        Err(format!("getDanmuInfo: {}", res["message"]))?;
+    }
+    let token = match res["data"]["token"].take() {
+        Value::String(token) => token,
+        _ => Err(format!("unexpected danmaku info: {}", res))?,
+    };
+    let hosts = match res["data"]["host_list"].take() {
+        Value::Array(hosts) => hosts
+            .into_iter()
+            .filter_map(|host| {
+                Some(format!(
+                    "wss://{}:{}/sub",
+                    host["host"].as_str()?,
+                    host["wss_port"].as_u64()?
+                ))
+            })
+            .collect(),
+        _ => Err(format!("unexpected danmaku info: {}", res))?,
+    };
+    Ok((token, hosts))
+}
+
 pub async fn get_room_id(uid: &str) -> Result<String, Box<dyn std::error::Error>> {
     let mut res: Value = serde_json::from_slice(
         reqwest::get(format!(
@@ -17,6 +165,23 @@ pub async fn get_room_id(uid: &str) -> Result<String, Box<dyn std::error::Error>
     }
 }
 
+/// Resolves a short room id to the real one.
+pub async fn get_real_room_id(room_id: u64) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut res: Value = serde_json::from_slice(
+        reqwest::get(format!(
+            "https://api.live.bilibili.com/room/v1/Room/room_init?id={room_id}"
+        ))
+        .await?
+        .bytes()
+        .await?
+        .as_ref(),
+    )?;
+    match res["data"]["room_id"].take() {
+        Value::Number(room_id) => Ok(room_id.as_u64().unwrap()),
+        _ => Err(format!("room {} not found: {}", room_id, res["message"]))?,
+    }
+}
+
 pub async fn get_live_status(
     uid: &str,
 ) -> Result<((bool, u64), (i64, String, String)), Box<dyn std::error::Error>> {
@@ -52,20 +217,106 @@ pub async fn get_live_status(
     Ok(((live_status, live_time), (area_id, area_name, cover_url)))
 }
 
-pub async fn live_area_list()
--> Result<Vec<(String, Vec<(String, String)>)>, Box<dyn std::error::Error>> {
+/// Public state of a live room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomInfo {
+    pub uid: u64,
+    pub title: String,
+    pub living: bool,
+    /// unix seconds, when living
+    pub live_time: Option<i64>,
+    pub area_id: u64,
+    pub area_name: String,
+    pub parent_area_name: String,
+    pub online: u64,
+    /// followers of the streamer
+    pub attention: u64,
+}
+
+pub async fn get_room_info(room_id: u64) -> Result<RoomInfo, Box<dyn std::error::Error>> {
     let mut res: Value = serde_json::from_slice(
-        reqwest::get("https://api.live.bilibili.com/room/v1/Area/getList")
-            .await?
-            .bytes()
-            .await?
-            .as_ref(),
+        reqwest::get(format!(
+            "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={room_id}"
+        ))
+        .await?
+        .bytes()
+        .await?
+        .as_ref(),
     )?;
+    if res["code"].as_i64() != Some(0) {
+        Err(res["message"]
+            .as_str()
+            .unwrap_or("room info failed")
+            .to_owned())?;
+    }
+    let data = &mut res["data"];
+    let uid = data["uid"].as_u64();
+    let living = data["live_status"].as_u64().map(|status| status == 1);
+    // "2024-07-20 19:30:00" in China time, zeros when not living
+    let live_time = data["live_time"].as_str().and_then(|time| {
+        let time = chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok()?;
+        let offset = chrono::FixedOffset::east_opt(8 * 3600)?;
+        Some(time.and_local_timezone(offset).single()?.timestamp())
+    });
+    let online = data["online"].as_u64().unwrap_or(0);
+    let attention = data["attention"].as_u64().unwrap_or(0);
+    let area_id = data["area_id"].as_u64().unwrap_or(0);
+    match (
+        uid,
+        living,
+        data["title"].take(),
+        data["area_name"].take(),
+        data["parent_area_name"].take(),
+    ) {
+        (
+            Some(uid),
+            Some(living),
+            Value::String(title),
+            Value::String(area_name),
+            Value::String(parent_area_name),
+        ) => Ok(RoomInfo {
+            uid,
+            title,
+            living,
+            live_time: live_time.filter(|_| living),
+            area_id,
+            area_name,
+            parent_area_name,
+            online,
+            attention,
+        }),
+        _ => Err(format!("unexpected room info: {}", res))?,
+    }
+}
+
+/// Fetches the live area list, `None` if it is unchanged since `etag`.
+#[allow(clippy::type_complexity)]
+pub async fn live_area_list(
+    etag: Option<&str>,
+) -> Result<
+    Option<(Vec<(String, Vec<(String, String)>)>, Option<String>)>,
+    Box<dyn std::error::Error>,
+> {
+    let mut request =
+        reqwest::Client::new().get("https://api.live.bilibili.com/room/v1/Area/getList");
+    if let Some(etag) = etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    let resp = request.send().await?;
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let etag = resp
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let mut res: Value = serde_json::from_slice(resp.bytes().await?.as_ref())?;
     let data = match res["data"].take() {
         Value::Array(data) => data,
         _ => panic!("{:?}", res),
     };
-    Ok(data
+    let list = data
         .into_iter()
         .map(|mut e| {
             let name = match e["name"].take() {
@@ -92,64 +343,65 @@ pub async fn live_area_list()
             };
             (name, list)
         })
-        .collect())
+        .collect();
+    Ok(Some((list, etag)))
 }
 
-async fn post_live(
+/// Posts a form with the login cookies and the csrf token from `bili_jct`.
+async fn post_form(
     cookies: &HashMap<String, String>,
-    url: &'static str,
-    area: Option<&str>,
-) -> Result<bytes::Bytes, Box<dyn std::error::Error>> {
+    url: &str,
+    extra: &[(&str, &str)],
+) -> Result<bytes::Bytes, reqwest::Error> {
     let client = reqwest::Client::new();
-    let csrf = cookies["bili_jct"].clone();
-    let uid = cookies["DedeUserID"].as_str();
-    let room_id = get_room_id(uid).await?;
+    let csrf = cookies["bili_jct"].as_str();
 
     let mut data = HashMap::new();
-    data.insert("room_id", room_id.as_str());
-    data.insert("platform", "pc_link");
-    data.insert("csrf", &csrf);
-    if let Some(area) = area {
-        data.insert("csrf_token", &csrf);
-        data.insert("area_v2", area);
-        data.insert("version", "1.0.0");
-        data.insert("build", "1234");
-    }
-
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::COOKIE,
-        (cookies
-            .into_iter()
-            .map(|(k, v)| format!("{k}={v};"))
-            .collect::<Vec<_>>()
-            .join(" "))
-        .parse()
-        .unwrap(),
-    );
+    data.insert("csrf", csrf);
+    data.insert("csrf_token", csrf);
+    data.extend(extra.iter().copied());
 
-    let resp = client
+    client
         .post(url)
-        .headers(headers)
+        .headers(cookie_headers(cookies))
         .form(&data)
         .send()
         .await?
         .bytes()
-        .await?;
-    Ok(resp)
+        .await
 }
 
-pub async fn start_live(
+async fn post_live(
+    cookies: &HashMap<String, String>,
+    url: &'static str,
+    extra: &[(&'static str, &str)],
+) -> Result<bytes::Bytes, Box<dyn std::error::Error>> {
+    let uid = cookies["DedeUserID"].as_str();
+    let room_id = get_room_id(uid).await?;
+
+    let mut data = vec![("room_id", room_id.as_str()), ("platform", "pc_link")];
+    data.extend(extra.iter().copied());
+    Ok(post_form(cookies, url, &data).await?)
+}
+
+async fn post_start_live(
     cookies: &HashMap<String, String>,
     area: &str,
-) -> Result<((String, String), String), Box<dyn std::error::Error>> {
+) -> Result<Value, Box<dyn std::error::Error>> {
     let resp = post_live(
         cookies,
         "https://api.live.bilibili.com/room/v1/Room/startLive",
-        Some(area),
+        &[("area_v2", area), ("version", "1.0.0"), ("build", "1234")],
     )
     .await?;
-    let mut val: Value = serde_json::from_slice(resp.as_ref())?;
+    Ok(serde_json::from_slice(resp.as_ref())?)
+}
+
+pub async fn start_live(
+    cookies: &HashMap<String, String>,
+    area: &str,
+) -> Result<((String, String), String), Box<dyn std::error::Error>> {
+    let mut val = post_start_live(cookies, area).await?;
     let addr = match val["data"]["rtmp"]["addr"].take() {
         Value::String(addr) => addr,
         _ => panic!("{:?}", val),
@@ -165,13 +417,53 @@ pub async fn start_live(
     Ok(((addr, code), message))
 }
 
+/// An ingest line offered by startLive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ingest {
+    pub addr: String,
+    pub code: String,
+    pub provider: String,
+}
+
+/// Starts live like [`start_live`] and returns every line in the
+/// `protocols` list, the main `rtmp` one first.
+pub async fn start_live_lines(
+    cookies: &HashMap<String, String>,
+    area: &str,
+) -> Result<(Vec<Ingest>, String), Box<dyn std::error::Error>> {
+    let val = post_start_live(cookies, area).await?;
+    if val["code"].as_i64() != Some(0) {
+        Err(val["message"]
+            .as_str()
+            .unwrap_or("start live failed")
+            .to_owned())?;
+    }
+    let ingest = |line: &Value| {
+        Some(Ingest {
+            addr: line["addr"].as_str()?.to_owned(),
+            code: line["code"].as_str()?.to_owned(),
+            provider: line["provider"].as_str().unwrap_or_default().to_owned(),
+        })
+    };
+    let mut lines: Vec<Ingest> = ingest(&val["data"]["rtmp"]).into_iter().collect();
+    for line in val["data"]["protocols"].as_array().into_iter().flatten() {
+        if let Some(line) = ingest(line)
+            && !lines.iter().any(|known| known.addr == line.addr)
+        {
+            lines.push(line);
+        }
+    }
+    let message = val["message"].as_str().unwrap_or_default().to_owned();
+    Ok((lines, message))
+}
+
 pub async fn stop_live(
     cookies: &HashMap<String, String>,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let resp = post_live(
         cookies,
         "https://api.live.bilibili.com/room/v1/Room/stopLive",
-        None,
+        &[],
     )
     .await?;
     let mut val: Value = serde_json::from_slice(resp.as_ref())?;
@@ -180,3 +472,460 @@ pub async fn stop_live(
         _ => panic!("{:?}", val),
     }
 }
+
+async fn update_room(
+    cookies: &HashMap<String, String>,
+    extra: &[(&'static str, &str)],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let resp = post_live(
+        cookies,
+        "https://api.live.bilibili.com/room/v1/Room/update",
+        extra,
+    )
+    .await?;
+    let mut val: Value = serde_json::from_slice(resp.as_ref())?;
+    match val["code"].as_i64() {
+        Some(0) => {}
+        Some(_) => Err(val["message"]
+            .as_str()
+            .unwrap_or("room update failed")
+            .to_owned())?,
+        None => Err(format!("unexpected room update reply: {}", val))?,
+    }
+    match val["message"].take() {
+        Value::String(message) => Ok(message),
+        _ => Err(format!("unexpected room update reply: {}", val))?,
+    }
+}
+
+pub async fn update_room_area(
+    cookies: &HashMap<String, String>,
+    area: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    update_room(cookies, &[("area_id", area)]).await
+}
+
+pub async fn update_room_title(
+    cookies: &HashMap<String, String>,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    update_room(cookies, &[("title", title)]).await
+}
+
+/// Mutes `uid` in the room for `hours`, or for the current live with `None`.
+pub async fn add_silent_user(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    uid: u64,
+    hours: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let room_id = room_id.to_string();
+    let uid = uid.to_string();
+    let hour = hours.unwrap_or(0).to_string();
+    let kind = if hours.is_some() { "1" } else { "2" };
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/AddSilentUser",
+        &[
+            ("room_id", room_id.as_str()),
+            ("tuid", uid.as_str()),
+            ("mobile_app", "web"),
+            ("type", kind),
+            ("hour", hour.as_str()),
+        ],
+    )
+    .await?;
+    check_code(&serde_json::from_slice(resp.as_ref())?, "mute failed")
+}
+
+/// Turns a non-zero `code` of an API reply into an error.
+fn check_code(val: &Value, failed: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match val["code"].as_i64() {
+        Some(0) => Ok(()),
+        Some(_) => Err(val["message"].as_str().unwrap_or(failed).to_owned())?,
+        None => Err(format!("{}: unexpected reply {}", failed, val))?,
+    }
+}
+
+async fn get_with_cookies(
+    cookies: &HashMap<String, String>,
+    url: &str,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let resp = reqwest::Client::new()
+        .get(url)
+        .headers(cookie_headers(cookies))
+        .send()
+        .await?
+        .bytes()
+        .await?;
+    Ok(serde_json::from_slice(resp.as_ref())?)
+}
+
+/// A muted user of a room, `id` identifies the mute record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SilentUser {
+    pub id: u64,
+    pub uid: u64,
+    pub name: String,
+    pub end_time: String,
+}
+
+pub async fn get_silent_users(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+) -> Result<Vec<SilentUser>, Box<dyn std::error::Error>> {
+    let room_id = room_id.to_string();
+    let mut users = vec![];
+    let mut page = 1;
+    loop {
+        let page_str = page.to_string();
+        let resp = post_form(
+            cookies,
+            "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/GetSilentUserList",
+            &[("room_id", room_id.as_str()), ("ps", page_str.as_str())],
+        )
+        .await?;
+        let mut val: Value = serde_json::from_slice(resp.as_ref())?;
+        check_code(&val, "listing muted users failed")?;
+        let total_page = val["data"]["total_page"].as_u64().unwrap_or(1);
+        let list = match val["data"]["data"].take() {
+            Value::Array(list) => list,
+            Value::Null => vec![],
+            _ => Err(format!("unexpected list of muted users: {}", val))?,
+        };
+        for item in list {
+            users.push(SilentUser {
+                id: item["id"].as_u64().unwrap_or_default(),
+                uid: item["tuid"].as_u64().unwrap_or_default(),
+                name: item["tname"].as_str().unwrap_or_default().to_string(),
+                end_time: item["block_end_time"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+        }
+        if page >= total_page {
+            return Ok(users);
+        }
+        page += 1;
+    }
+}
+
+pub async fn del_silent_user(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    id: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let room_id = room_id.to_string();
+    let id = id.to_string();
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/DelSilentUser",
+        &[("roomid", room_id.as_str()), ("id", id.as_str())],
+    )
+    .await?;
+    check_code(&serde_json::from_slice(resp.as_ref())?, "unmute failed")
+}
+
+/// The blocked keywords of a room.
+pub async fn get_shield_keywords(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut val = get_with_cookies(
+        cookies,
+        &format!(
+            "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/GetShieldKeywordList?room_id={room_id}"
+        ),
+    )
+    .await?;
+    check_code(&val, "listing blocked keywords failed")?;
+    let list = match val["data"]["keyword_list"].take() {
+        Value::Array(list) => list,
+        Value::Null => vec![],
+        _ => Err(format!("unexpected list of blocked keywords: {}", val))?,
+    };
+    // plain strings or objects with a `keyword` field, depending on the room
+    Ok(list
+        .into_iter()
+        .filter_map(|item| match item {
+            Value::String(keyword) => Some(keyword),
+            item => item["keyword"].as_str().map(str::to_string),
+        })
+        .collect())
+}
+
+pub async fn add_shield_keyword(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    keyword: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let room_id = room_id.to_string();
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/AddShieldKeyword",
+        &[("room_id", room_id.as_str()), ("keyword", keyword)],
+    )
+    .await?;
+    check_code(
+        &serde_json::from_slice(resp.as_ref())?,
+        "adding keyword failed",
+    )
+}
+
+pub async fn del_shield_keyword(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    keyword: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let room_id = room_id.to_string();
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/banned/DelShieldKeyword",
+        &[("room_id", room_id.as_str()), ("keyword", keyword)],
+    )
+    .await?;
+    check_code(
+        &serde_json::from_slice(resp.as_ref())?,
+        "removing keyword failed",
+    )
+}
+
+/// The room admins of your own room as `(uid, name)`.
+pub async fn get_room_admins(
+    cookies: &HashMap<String, String>,
+) -> Result<Vec<(u64, String)>, Box<dyn std::error::Error>> {
+    let mut admins = vec![];
+    let mut page = 1;
+    loop {
+        let mut val = get_with_cookies(
+            cookies,
+            &format!(
+                "https://api.live.bilibili.com/xlive/web-ucenter/v1/roomAdmin/get_by_anchor?page={page}"
+            ),
+        )
+        .await?;
+        check_code(&val, "listing room admins failed")?;
+        let total_page = val["data"]["page"]["total_page"].as_u64().unwrap_or(1);
+        let list = match val["data"]["data"].take() {
+            Value::Array(list) => list,
+            Value::Null => vec![],
+            _ => Err(format!("unexpected list of room admins: {}", val))?,
+        };
+        admins.extend(list.into_iter().map(|item| {
+            (
+                item["uid"].as_u64().unwrap_or_default(),
+                item["uname"].as_str().unwrap_or_default().to_string(),
+            )
+        }));
+        if page >= total_page {
+            return Ok(admins);
+        }
+        page += 1;
+    }
+}
+
+pub async fn appoint_admin(
+    cookies: &HashMap<String, String>,
+    uid: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let uid = uid.to_string();
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/roomAdmin/appoint",
+        &[("admin", uid.as_str()), ("admin_level", "1")],
+    )
+    .await?;
+    check_code(
+        &serde_json::from_slice(resp.as_ref())?,
+        "appointing admin failed",
+    )
+}
+
+pub async fn dismiss_admin(
+    cookies: &HashMap<String, String>,
+    uid: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let uid = uid.to_string();
+    let resp = post_form(
+        cookies,
+        "https://api.live.bilibili.com/xlive/web-ucenter/v1/roomAdmin/dismiss",
+        &[("uid", uid.as_str())],
+    )
+    .await?;
+    check_code(
+        &serde_json::from_slice(resp.as_ref())?,
+        "dismissing admin failed",
+    )
+}
+
+/// Looks up the uid of the user named exactly `name`.
+pub async fn find_user(
+    cookies: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let query = wbi_sign(
+        cookies,
+        &[
+            ("search_type", "bili_user".to_string()),
+            ("keyword", name.to_string()),
+        ],
+    )
+    .await?;
+    let val = get_with_cookies(
+        cookies,
+        &format!("https://api.bilibili.com/x/web-interface/wbi/search/type?{query}"),
+    )
+    .await?;
+    check_code(&val, "user search failed")?;
+    Ok(val["data"]["result"].as_array().and_then(|users| {
+        users
+            .iter()
+            .find(|user| user["uname"].as_str() == Some(name))
+            .and_then(|user| user["mid"].as_u64())
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DanmakuMode {
+    #[default]
+    Scroll = 1,
+    Bottom = 4,
+    Top = 5,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingDanmaku {
+    pub text: String,
+    /// 0xRRGGBB
+    pub color: u32,
+    pub mode: DanmakuMode,
+    /// send `text` as an emoticon code such as `official_147`
+    pub emoticon: bool,
+    /// uid of the user replied to
+    pub reply_to: Option<u64>,
+}
+
+impl OutgoingDanmaku {
+    pub fn new(text: String) -> Self {
+        OutgoingDanmaku {
+            text,
+            color: 0xffffff,
+            ..OutgoingDanmaku::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Empty,
+    TooLong {
+        len: usize,
+        max: usize,
+    },
+    RateLimited(String),
+    /// dropped by the keyword filter of the platform or the room
+    Filtered,
+    Rejected {
+        code: i64,
+        message: String,
+    },
+    Request(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Empty => write!(f, "danmaku is empty"),
+            SendError::TooLong { len, max } => {
+                write!(f, "danmaku too long: {} characters, at most {}", len, max)
+            }
+            SendError::RateLimited(message) => write!(f, "rate limited: {}", message),
+            SendError::Filtered => write!(f, "danmaku was filtered"),
+            SendError::Rejected { code, message } => write!(f, "rejected [{}]: {}", code, message),
+            SendError::Request(e) => write!(f, "request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        SendError::Request(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for SendError {
+    fn from(e: serde_json::Error) -> Self {
+        SendError::Request(e.to_string())
+    }
+}
+
+/// The danmaku length limit of the logged in user in `room_id`.
+pub async fn get_danmaku_length(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+) -> Result<usize, reqwest::Error> {
+    let res: Value = reqwest::Client::new()
+        .get(format!(
+            "https://api.live.bilibili.com/xlive/web-room/v1/index/getInfoByUser?room_id={room_id}"
+        ))
+        .headers(cookie_headers(cookies))
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(res["data"]["property"]["danmu"]["length"]
+        .as_u64()
+        .unwrap_or(20) as usize)
+}
+
+pub async fn send_danmaku(
+    cookies: &HashMap<String, String>,
+    room_id: u64,
+    danmaku: &OutgoingDanmaku,
+) -> Result<(), SendError> {
+    let len = danmaku.text.chars().count();
+    if danmaku.text.trim().is_empty() {
+        return Err(SendError::Empty);
+    }
+    if !danmaku.emoticon {
+        let max = get_danmaku_length(cookies, room_id).await?;
+        if len > max {
+            return Err(SendError::TooLong { len, max });
+        }
+    }
+
+    let room_id = room_id.to_string();
+    let color = danmaku.color.to_string();
+    let mode = (danmaku.mode as u8).to_string();
+    let rnd = chrono::Utc::now().timestamp().to_string();
+    let reply_to = danmaku.reply_to.map(|uid| uid.to_string());
+    let mut data = vec![
+        ("roomid", room_id.as_str()),
+        ("msg", danmaku.text.as_str()),
+        ("color", color.as_str()),
+        ("mode", mode.as_str()),
+        ("fontsize", "25"),
+        ("bubble", "0"),
+        ("rnd", rnd.as_str()),
+    ];
+    if danmaku.emoticon {
+        data.push(("dm_type", "1"));
+        data.push(("emoticonOptions", "[object Object]"));
+    }
+    if let Some(reply_to) = &reply_to {
+        data.push(("reply_mid", reply_to));
+    }
+    let resp = post_form(cookies, "https://api.live.bilibili.com/msg/send", &data).await?;
+    let val: Value = serde_json::from_slice(resp.as_ref())?;
+    let message = val["message"].as_str().unwrap_or_default().to_string();
+    match val["code"].as_i64() {
+        Some(0) if message == "f" || message == "k" => Err(SendError::Filtered),
+        Some(0) => Ok(()),
+        Some(10030 | 10031) => Err(SendError::RateLimited(message)),
+        Some(code) => Err(SendError::Rejected { code, message }),
+        None => Err(SendError::Request(format!("unexpected response: {}", val))),
+    }
+}
@@ -1,11 +1,33 @@
+mod area;
+mod ass;
+mod automod;
+mod bot;
 mod cli;
+mod config;
+mod danmaku;
+mod doctor;
+mod event;
 mod live;
 mod login;
+mod obs;
+mod overlay;
+mod poll;
+mod raffle;
+mod record;
+mod rtmp;
+mod script;
+mod songs;
+mod stats;
 mod tui;
+mod widgets;
 
 use chrono::{DateTime, Datelike, Utc};
 use login::LoginData;
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc;
 
 async fn login<P: AsRef<Path>>(
     data_path: P,
@@ -43,20 +65,102 @@ async fn login<P: AsRef<Path>>(
     Ok((login_data, now))
 }
 
-async fn valid_area(area: &str) -> Result<bool, Box<dyn std::error::Error>> {
-    Ok(live::live_area_list()
-        .await?
-        .into_iter()
-        .any(|(_, li)| li.into_iter().any(|(_, id)| &id == area)))
+/// Resolves `area` to an area id, ambiguous names are reported as an error.
+fn resolve_area(
+    area_list: &[(String, Vec<(String, String)>)],
+    area: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match area::lookup(area_list, area) {
+        area::Lookup::Found(id) => Ok(Some(id.to_string())),
+        area::Lookup::Ambiguous(candidates) => {
+            cli::print_areas(&"candidates", &candidates);
+            Err(format!("ambiguous live area: {}", area))?
+        }
+        area::Lookup::NotFound => Ok(None),
+    }
+}
+
+/// Resolves `area` like [`resolve_area`], an unknown area is an error.
+fn known_area(
+    area_list: &[(String, Vec<(String, String)>)],
+    area: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(resolve_area(area_list, area)?.ok_or_else(|| format!("unknown live area: {}", area))?)
+}
+
+/// The area of `--area`, or the last one used, for commands that go live
+/// without asking.
+async fn area_arg(
+    arg_match: &clap::ArgMatches,
+    login_data: &LoginData,
+    cache_path: &Path,
+    config: &config::Config,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match arg_match.get_one::<String>("area") {
+        Some(area) => {
+            let area_list = area::area_list(cache_path, config.area_cache_ttl, false).await?;
+            known_area(&area_list, area)
+        }
+        None => Ok(login_data
+            .area
+            .clone()
+            .ok_or("no live area yet, pass --area or run start once")?),
+    }
+}
+
+/// The real id of `--room`, or of the logged in user's own room.
+async fn resolve_room(
+    arg_match: &clap::ArgMatches,
+    cookies: &HashMap<String, String>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    match arg_match.get_one::<u64>("room") {
+        Some(room_id) => live::get_real_room_id(*room_id).await,
+        None => Ok(live::get_room_id(&cookies["DedeUserID"]).await?.parse()?),
+    }
+}
+
+/// Resolves a uid or exact user name to a uid.
+async fn resolve_user(
+    cookies: &HashMap<String, String>,
+    user: &str,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    match user.parse() {
+        Ok(uid) => Ok(uid),
+        Err(_) => Ok(live::find_user(cookies, user)
+            .await?
+            .ok_or_else(|| format!("user not found: {}", user))?),
+    }
+}
+
+fn data_file(name: &str) -> PathBuf {
+    let mut data_path = dirs::home_dir().unwrap();
+    data_path.push(name);
+    data_path
+}
+
+fn obs_password<'a>(
+    arg_match: &'a clap::ArgMatches,
+    config: &'a config::Config,
+) -> Option<&'a str> {
+    arg_match
+        .get_one::<String>("obs-password")
+        .or(config.obs_password.as_ref())
+        .map(String::as_str)
+}
+
+fn automod_log(config: &config::Config) -> PathBuf {
+    match &config.automod.audit_log {
+        Some(path) => path.clone(),
+        None => data_file("bili-live-automod.jsonl"),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let data_path = {
-        let mut data_path = dirs::home_dir().unwrap();
-        data_path.push("bili-live-cookies.json");
-        data_path
-    };
+    let data_path = data_file("bili-live-cookies.json");
+    let area_cache_path = data_file("bili-live-areas.json");
+    let config_path = data_file("bili-live-config.json");
+    let mut config = config::Config::load(&config_path)?;
     let cmds = cli::build_commands();
     match cmds.get_matches().subcommand() {
         Some(("status", _)) => {
@@ -69,15 +173,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let mut pairs = vec![("is living".to_string(), living.to_string())];
             if living {
                 pairs.push(("start time".to_string(), start_time.to_string()));
-                pairs.push(("live duration".to_string(), {
-                    let mut delta = (now - start_time).num_seconds();
-                    let sec = delta % 60;
-                    delta /= 60;
-                    let min = delta % 60;
-                    delta /= 60;
-                    let hour = delta;
-                    format!("{}:{}:{}", hour, min, sec)
-                }));
+                pairs.push((
+                    "live duration".to_string(),
+                    cli::format_duration((now - start_time).num_seconds()),
+                ));
                 pairs.push(("area".to_string(), format!("{}[{}]", area_name, area_id)))
             }
             cli::print_image(&cover_url).await?;
@@ -87,35 +186,1016 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let (mut login_data, _) = login(&data_path).await?;
             let area = arg_match.get_one::<String>("area");
             let area = match (area, login_data.area) {
-                (Some(area), _) if valid_area(area).await? => {
-                    login_data.area = Some(area.clone());
-                    login_data.dump(&data_path)?;
-                    area.clone()
-                }
                 (None, Some(area)) => area,
-                _ => {
-                    let area_list = live::live_area_list().await?;
-                    let area = tui::ask_area(&area_list)?.to_string();
+                (area, _) => {
+                    let area_list =
+                        area::area_list(&area_cache_path, config.area_cache_ttl, false).await?;
+                    let area = match area {
+                        Some(area) => resolve_area(&area_list, area)?,
+                        None => None,
+                    };
+                    let area = match area {
+                        Some(area) => area,
+                        None => {
+                            let area = tui::ask_area(&area_list, &mut config)?;
+                            config.dump(&config_path)?;
+                            area.ok_or("no live area selected")?
+                        }
+                    };
                     login_data.area = Some(area.clone());
                     login_data.dump(&data_path)?;
                     area
                 }
             };
+            config.push_recent_area(&area);
+            config.dump(&config_path)?;
+            // checked before going live, a locked profile would undo the write
+            let obs_service = match arg_match.get_one::<String>("write-obs-profile") {
+                Some(profile) => {
+                    let service = obs::find_profile(profile)?;
+                    if !arg_match.get_flag("force") && obs::profile_locked(&service) {
+                        Err(format!(
+                            "OBS is running with profile {}, close it or pass --force",
+                            profile
+                        ))?;
+                    }
+                    Some(service)
+                }
+                None => None,
+            };
+            // connected first too, a wrong password should not leave the room live
+            let obs = match arg_match.get_one::<String>("obs") {
+                Some(url) => {
+                    let password = obs_password(arg_match, &config);
+                    Some((url, obs::Obs::connect(url, password).await?))
+                }
+                None => None,
+            };
             let ((addr, code), message) = live::start_live(&login_data.cookies, &area).await?;
-            let mut pairs = vec![("addr".to_string(), addr), ("code".to_string(), code)];
+            let mut pairs = vec![
+                ("addr".to_string(), addr.clone()),
+                ("code".to_string(), code.clone()),
+            ];
             if !message.is_empty() {
                 pairs.push(("message".to_string(), message));
             }
             cli::print_pairs(&"start", &pairs);
+            if let Some(service) = obs_service {
+                let backup = obs::write_service(&service, &addr, &code)?;
+                let mut pairs = vec![("written".to_string(), service.display().to_string())];
+                if let Some(backup) = backup {
+                    pairs.push(("backup".to_string(), backup.display().to_string()));
+                }
+                cli::print_pairs(&"obs profile", &pairs);
+            }
+            if let Some((url, mut obs)) = obs {
+                obs.set_stream_service(&addr, &code).await?;
+                obs.start_stream().await?;
+                cli::print_pairs(&"obs", &[("streaming".to_string(), url.clone())]);
+            }
         }
-        Some(("stop", _)) => {
+        Some(("stop", arg_match)) => {
             let (login_data, _) = login(&data_path).await?;
+            // check the export path and read the log first, either failing
+            // should not leave the summary half done
+            if let Some(export) = arg_match.get_one::<PathBuf>("export") {
+                stats::ExportFormat::of(export)?;
+            }
+            let mut stats = match arg_match.get_one::<PathBuf>("log") {
+                Some(log) => stats::SessionStats::from_records(&record::read_jsonl(log)?),
+                // ask the room before stopping, the viewers are gone after
+                None => {
+                    let room_id = live::get_room_id(&login_data.cookies["DedeUserID"])
+                        .await?
+                        .parse()?;
+                    stats::SessionStats::from_room(&live::get_room_info(room_id).await?)
+                }
+            };
+            let ((living, start_time), _) =
+                live::get_live_status(&login_data.cookies["DedeUserID"]).await?;
+            if let Some(url) = arg_match.get_one::<String>("obs") {
+                let password = obs_password(arg_match, &config);
+                // the live is stopped anyway, OBS just keeps pushing then
+                let stopped = match obs::Obs::connect(url, password).await {
+                    Ok(mut obs) => obs.stop_stream().await,
+                    Err(e) => Err(e),
+                };
+                match stopped {
+                    Ok(stopped) => cli::print_pairs(
+                        &"obs",
+                        &[("stopped streaming".to_string(), stopped.to_string())],
+                    ),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
             let message = live::stop_live(&login_data.cookies).await?;
             let mut pairs = vec![];
             if !message.is_empty() {
                 pairs.push(("message".to_string(), message));
             }
             cli::print_pairs(&"stop", &pairs);
+
+            if living {
+                stats.start = Some(start_time as i64);
+                stats.end = Some(Utc::now().timestamp());
+            }
+            cli::print_pairs(&"summary", &stats.pairs());
+            if let Some(export) = arg_match.get_one::<PathBuf>("export") {
+                stats.export(export)?;
+            }
+        }
+        Some(("test-stream", arg_match)) => {
+            let file = arg_match.get_one::<PathBuf>("file").unwrap();
+            // open before going live, a bad file should not start anything
+            let mut flv = rtmp::FlvReader::open(file).await?;
+            let mut started = None;
+            let (addr, code) = match (
+                arg_match.get_one::<String>("addr"),
+                arg_match.get_one::<String>("code"),
+            ) {
+                (Some(addr), Some(code)) => (addr.clone(), code.clone()),
+                _ => {
+                    let (login_data, _) = login(&data_path).await?;
+                    let area = area_arg(arg_match, &login_data, &area_cache_path, &config).await?;
+                    let ((addr, code), _) = live::start_live(&login_data.cookies, &area).await?;
+                    cli::print_pairs(&"start", &[("addr".to_string(), addr.clone())]);
+                    started = Some(login_data);
+                    (addr, code)
+                }
+            };
+
+            let begin = std::time::Instant::now();
+            let pushed = async {
+                let mut publisher = rtmp::Publisher::connect(&addr, &code).await?;
+                println!("publishing {}", file.display());
+                let sent = tokio::select! {
+                    sent = rtmp::stream_file(&mut publisher, &mut flv) => sent?,
+                    _ = tokio::signal::ctrl_c() => (0, 0),
+                };
+                publisher.close(&code).await?;
+                Ok::<_, Box<dyn std::error::Error>>(sent)
+            }
+            .await;
+            if let Some(login_data) = started
+                && !arg_match.get_flag("keep-live")
+            {
+                let message = live::stop_live(&login_data.cookies).await?;
+                cli::print_pairs(&"stop", &[("message".to_string(), message)]);
+            }
+            let (tags, media_ms) = pushed?;
+            cli::print_pairs(
+                &"test-stream",
+                &[
+                    ("tags".to_string(), tags.to_string()),
+                    (
+                        "media duration".to_string(),
+                        cli::format_duration(media_ms as i64 / 1000),
+                    ),
+                    (
+                        "wall time".to_string(),
+                        cli::format_duration(begin.elapsed().as_secs() as i64),
+                    ),
+                ],
+            );
+        }
+        Some(("doctor", arg_match)) => match arg_match.subcommand() {
+            Some(("ingest", arg_match)) => {
+                let tries = *arg_match.get_one::<u64>("tries").unwrap() as usize;
+                let limit =
+                    std::time::Duration::from_secs(*arg_match.get_one::<u64>("timeout").unwrap());
+                let json = arg_match.get_flag("json");
+                let mut started = None;
+                let lines = match arg_match.get_many::<String>("addr") {
+                    Some(addrs) => addrs
+                        .map(|addr| live::Ingest {
+                            addr: addr.clone(),
+                            code: String::new(),
+                            provider: String::new(),
+                        })
+                        .collect(),
+                    None => {
+                        let (login_data, _) = login(&data_path).await?;
+                        let area =
+                            area_arg(arg_match, &login_data, &area_cache_path, &config).await?;
+                        let uid = login_data.cookies["DedeUserID"].as_str();
+                        let ((living, _), _) = live::get_live_status(uid).await?;
+                        let (lines, _) = live::start_live_lines(&login_data.cookies, &area).await?;
+                        // only stop a live this check started
+                        if !living {
+                            started = Some(login_data);
+                        }
+                        lines
+                    }
+                };
+
+                let mut reports = vec![];
+                for line in &lines {
+                    if !json {
+                        println!("checking {}", line.addr);
+                    }
+                    reports
+                        .push(doctor::check_line(&line.addr, &line.provider, tries, limit).await);
+                }
+                if let Some(login_data) = started
+                    && !arg_match.get_flag("keep-live")
+                {
+                    let message = live::stop_live(&login_data.cookies).await?;
+                    if !json {
+                        cli::print_pairs(&"stop", &[("message".to_string(), message)]);
+                    }
+                }
+                let best = doctor::best(&reports, limit);
+
+                if json {
+                    let recommended = best.map(|idx| {
+                        let line = &lines[idx];
+                        // no key for lines given with --addr
+                        let code = Some(&line.code).filter(|code| !code.is_empty());
+                        serde_json::json!({"addr": line.addr, "code": code})
+                    });
+                    let output = serde_json::json!({
+                        "lines": reports,
+                        "best": best,
+                        "recommended": recommended,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&output)?);
+                } else {
+                    let ms =
+                        |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{} ms", ms));
+                    for report in &reports {
+                        let mut pairs = vec![];
+                        if !report.provider.is_empty() {
+                            pairs.push(("provider".to_string(), report.provider.clone()));
+                        }
+                        pairs.extend([
+                            ("ips".to_string(), report.ips.join(", ")),
+                            ("dns".to_string(), ms(report.dns_ms)),
+                            ("connect".to_string(), ms(report.connect_ms)),
+                            ("handshake".to_string(), ms(report.handshake_ms)),
+                            (
+                                "failures".to_string(),
+                                format!("{}/{}", report.failures, report.tries),
+                            ),
+                        ]);
+                        if let Some(error) = &report.error {
+                            pairs.push(("error".to_string(), error.clone()));
+                        }
+                        cli::print_pairs(&report.addr, &pairs);
+                    }
+                    if let Some(idx) = best {
+                        cli::print_pairs(
+                            &"recommended",
+                            &[("addr".to_string(), lines[idx].addr.clone())],
+                        );
+                    }
+                }
+                // also with --json, so scripts can check the exit status
+                if best.is_none() {
+                    Err("no ingest line completed an rtmp handshake")?;
+                }
+            }
+            Some((cmd, _)) => panic!("{}", cmd),
+            None => unreachable!(),
+        },
+        Some(("room", arg_match)) => match arg_match.subcommand() {
+            Some(("area", arg_match)) => match arg_match.subcommand() {
+                Some(("set", arg_match)) => {
+                    let (mut login_data, _) = login(&data_path).await?;
+                    let area_list =
+                        area::area_list(&area_cache_path, config.area_cache_ttl, false).await?;
+                    let area = match arg_match.get_one::<String>("AREA") {
+                        Some(area) => known_area(&area_list, area)?,
+                        None => {
+                            let area = tui::ask_area(&area_list, &mut config)?;
+                            config.dump(&config_path)?;
+                            area.ok_or("no live area selected")?
+                        }
+                    };
+                    let message = live::update_room_area(&login_data.cookies, &area).await?;
+                    login_data.area = Some(area.clone());
+                    login_data.dump(&data_path)?;
+                    let mut pairs = vec![("area".to_string(), area)];
+                    if !message.is_empty() {
+                        pairs.push(("message".to_string(), message));
+                    }
+                    cli::print_pairs(&"room area set", &pairs);
+                }
+                Some((cmd, _)) => panic!("{}", cmd),
+                None => unreachable!(),
+            },
+            Some((cmd, _)) => panic!("{}", cmd),
+            None => unreachable!(),
+        },
+        Some(("areas", arg_match)) => {
+            let refresh = *arg_match.get_one::<bool>("refresh").unwrap();
+            let area_list =
+                area::area_list(&area_cache_path, config.area_cache_ttl, refresh).await?;
+            let areas = match arg_match.get_one::<String>("search") {
+                Some(query) => area::search(&area_list, query),
+                None => area_list
+                    .iter()
+                    .flat_map(|(group, li)| {
+                        li.iter()
+                            .map(move |(name, id)| (group.as_str(), name.as_str(), id.as_str()))
+                    })
+                    .collect(),
+            };
+            cli::print_areas(&"areas", &areas);
+        }
+        Some(("chat", arg_match)) if arg_match.subcommand_name() == Some("export-ass") => {
+            let arg_match = arg_match.subcommand_matches("export-ass").unwrap();
+            let log = arg_match.get_one::<PathBuf>("LOG").unwrap();
+            let records = record::read_jsonl(log)?;
+            let start = match arg_match.get_one::<i64>("start") {
+                Some(start) => *start,
+                None => match records.iter().find_map(|record| match record.event {
+                    event::Event::Live { live_time } => live_time,
+                    _ => None,
+                }) {
+                    Some(start) => start,
+                    None => {
+                        let (login_data, _) = login(&data_path).await?;
+                        let ((living, start), _) =
+                            live::get_live_status(&login_data.cookies["DedeUserID"]).await?;
+                        if !living {
+                            Err("no stream start time in the log, pass --start")?
+                        }
+                        start as i64
+                    }
+                },
+            };
+            let &(width, height) = arg_match.get_one::<(u32, u32)>("resolution").unwrap();
+            let options = ass::Options {
+                font: arg_match.get_one::<String>("font").unwrap().clone(),
+                font_size: *arg_match.get_one::<u32>("font-size").unwrap(),
+                opacity: *arg_match.get_one::<f64>("opacity").unwrap(),
+                duration: *arg_match.get_one::<f64>("duration").unwrap(),
+                width,
+                height,
+                gifts: !*arg_match.get_one::<bool>("no-gifts").unwrap(),
+                entrances: !*arg_match.get_one::<bool>("no-entrances").unwrap(),
+            };
+            let out = match arg_match.get_one::<PathBuf>("out") {
+                Some(out) => out.clone(),
+                None => log.with_extension("ass"),
+            };
+            std::fs::write(&out, ass::export(&records, start * 1000, &options))?;
+            cli::print_pairs(
+                &"export-ass",
+                &[
+                    ("events".to_string(), records.len().to_string()),
+                    ("out".to_string(), out.display().to_string()),
+                ],
+            );
+        }
+        Some(("chat", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+            match arg_match.subcommand() {
+                Some(("send", arg_match)) => {
+                    let danmaku = live::OutgoingDanmaku {
+                        text: arg_match.get_one::<String>("TEXT").unwrap().clone(),
+                        color: *arg_match.get_one::<u32>("color").unwrap(),
+                        mode: *arg_match.get_one::<live::DanmakuMode>("mode").unwrap(),
+                        emoticon: *arg_match.get_one::<bool>("emoticon").unwrap(),
+                        reply_to: arg_match.get_one::<u64>("reply-to").copied(),
+                    };
+                    live::send_danmaku(&login_data.cookies, room_id, &danmaku).await?;
+                }
+                Some(("record", arg_match)) => {
+                    let format = *arg_match.get_one::<record::Format>("format").unwrap();
+                    let out = match arg_match.get_one::<PathBuf>("out") {
+                        Some(out) => out.clone(),
+                        None => data_file("bili-live-danmaku"),
+                    };
+                    let mut recorder = record::Recorder::new(format, out);
+                    let (tx, mut rx) = mpsc::unbounded_channel();
+                    danmaku::spawn(tx, login_data.cookies.clone(), room_id);
+                    let mut count = 0;
+                    loop {
+                        let message = tokio::select! {
+                            message = rx.recv() => message,
+                            _ = tokio::signal::ctrl_c() => None,
+                        };
+                        match message {
+                            Some(danmaku::Message::Command(raw)) => {
+                                let record = record::Record::new(raw);
+                                if let Some(path) = recorder.record(&record)? {
+                                    println!("recording to {}", path.display());
+                                }
+                                count += 1;
+                            }
+                            Some(danmaku::Message::Disconnected(e)) => {
+                                eprintln!("disconnected: {}", e)
+                            }
+                            Some(_) => {}
+                            None => break,
+                        }
+                    }
+                    recorder.finish()?;
+                    cli::print_pairs(&"record", &[("events".to_string(), count.to_string())]);
+                }
+                Some((cmd, _)) => panic!("{}", cmd),
+                None => {
+                    let (tx, rx) = mpsc::unbounded_channel();
+                    if arg_match.get_flag("automod") {
+                        let mut automod = automod::Automod::new(
+                            config.automod.clone(),
+                            room_id,
+                            login_data.cookies["DedeUserID"].parse()?,
+                            arg_match.get_flag("dry-run"),
+                            &automod_log(&config),
+                        )?;
+                        // checks every danmaku before the chat shows it
+                        let (checked_tx, mut checked_rx) = mpsc::unbounded_channel();
+                        danmaku::spawn(checked_tx, login_data.cookies.clone(), room_id);
+                        let tx = tx.clone();
+                        let cookies = login_data.cookies.clone();
+                        tokio::spawn(async move {
+                            while let Some(message) = checked_rx.recv().await {
+                                let verdict = match &message {
+                                    danmaku::Message::Command(raw) => automod.check(
+                                        &event::Event::parse(raw.clone()),
+                                        tokio::time::Instant::now(),
+                                    ),
+                                    _ => None,
+                                };
+                                let _ = tx.send(message);
+                                let Some(verdict) = verdict else { continue };
+                                if verdict.action == config::AutomodAction::Log {
+                                    continue;
+                                }
+                                let _ = tx.send(danmaku::Message::Notice(verdict.describe()));
+                                if let Some(bot::Action::Mute { uid, hours }) =
+                                    automod.action(&verdict)
+                                    && let Err(e) =
+                                        live::add_silent_user(&cookies, room_id, uid, hours).await
+                                {
+                                    let notice = format!("禁言 {} 失败: {}", verdict.name, e);
+                                    let _ = tx.send(danmaku::Message::Notice(notice));
+                                }
+                            }
+                        });
+                    } else {
+                        danmaku::spawn(tx.clone(), login_data.cookies.clone(), room_id);
+                    }
+                    let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+                    let cookies = login_data.cookies.clone();
+                    tokio::spawn(async move {
+                        while let Some(action) = action_rx.recv().await {
+                            match action {
+                                tui::ChatAction::Send(text) => {
+                                    let danmaku = live::OutgoingDanmaku::new(text);
+                                    if let Err(e) =
+                                        live::send_danmaku(&cookies, room_id, &danmaku).await
+                                    {
+                                        let notice = format!("发送失败: {}", e);
+                                        let _ = tx.send(danmaku::Message::Notice(notice));
+                                    }
+                                }
+                                tui::ChatAction::Mute { uid, name } => {
+                                    let notice =
+                                        match live::add_silent_user(&cookies, room_id, uid, None)
+                                            .await
+                                        {
+                                            Ok(()) => format!("已禁言 {}", name),
+                                            Err(e) => format!("禁言 {} 失败: {}", name, e),
+                                        };
+                                    let _ = tx.send(danmaku::Message::Notice(notice));
+                                }
+                            }
+                        }
+                    });
+                    tokio::task::spawn_blocking(move || tui::chat(room_id, rx, action_tx))
+                        .await??;
+                }
+            }
+        }
+        Some(("overlay", arg_match)) => match arg_match.subcommand() {
+            Some(("serve", arg_match)) => {
+                let port = *arg_match.get_one::<u16>("port").unwrap();
+                let css = arg_match.get_one::<PathBuf>("css").cloned();
+                let overlay = overlay::Overlay::new();
+                if *arg_match.get_one::<bool>("test").unwrap() {
+                    tokio::spawn(overlay::inject_fake_events(
+                        overlay.clone(),
+                        std::time::Duration::from_millis(1500),
+                    ));
+                } else {
+                    let (login_data, _) = login(&data_path).await?;
+                    let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+                    let (tx, mut rx) = mpsc::unbounded_channel();
+                    danmaku::spawn(tx, login_data.cookies.clone(), room_id);
+                    let overlay = overlay.clone();
+                    tokio::spawn(async move {
+                        while let Some(message) = rx.recv().await {
+                            match message {
+                                danmaku::Message::Command(raw) => {
+                                    overlay.publish(&event::Event::parse(raw))
+                                }
+                                danmaku::Message::Disconnected(e) => {
+                                    eprintln!("disconnected: {}", e)
+                                }
+                                _ => {}
+                            }
+                        }
+                    });
+                }
+                let mut pairs = vec![];
+                for (path, title, _) in overlay::PAGES {
+                    pairs.push((
+                        title.to_string(),
+                        format!("http://127.0.0.1:{}{}", port, path),
+                    ));
+                }
+                cli::print_pairs(&"overlay", &pairs);
+                tokio::select! {
+                    result = overlay::serve(overlay, port, css) => result.map_err(|e| e.to_string())?,
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Some((cmd, _)) => panic!("{}", cmd),
+            None => unreachable!(),
+        },
+        Some(("widgets", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+            let dir = arg_match.get_one::<PathBuf>("dir").unwrap().clone();
+            let interval = *arg_match.get_one::<u64>("interval").unwrap();
+            let mut widgets = widgets::Widgets::new(dir.clone(), &config.widget_templates);
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            danmaku::spawn(tx, login_data.cookies.clone(), room_id);
+            let mut poll = tokio::time::interval(std::time::Duration::from_secs(interval));
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(1));
+            cli::print_pairs(
+                &"widgets",
+                &[("dir".to_string(), dir.display().to_string())],
+            );
+            loop {
+                tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(danmaku::Message::Command(raw)) => {
+                            widgets.handle_event(&event::Event::parse(raw));
+                        }
+                        Some(danmaku::Message::Disconnected(e)) => eprintln!("disconnected: {}", e),
+                        Some(_) => continue,
+                        None => break,
+                    },
+                    _ = poll.tick() => match live::get_room_info(room_id).await {
+                        Ok(room) => widgets.update_room(&room),
+                        Err(e) => eprintln!("room status: {}", e),
+                    },
+                    _ = tick.tick() => {}
+                    _ = tokio::signal::ctrl_c() => break,
+                }
+                widgets.write(Utc::now().timestamp())?;
+            }
+        }
+        Some(("bot", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+            let own_uid = login_data.cookies["DedeUserID"].parse()?;
+            let send_interval = std::time::Duration::from_millis(config.bot.send_interval);
+            let mut bot = bot::Bot::new(config.bot.clone(), own_uid);
+            let area = login_data.area.as_deref();
+            bot::run(&login_data.cookies, room_id, area, &mut bot, send_interval).await?;
+        }
+        Some(("automod", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+            let audit_log = automod_log(&config);
+            let mut automod = automod::Automod::new(
+                config.automod.clone(),
+                room_id,
+                login_data.cookies["DedeUserID"].parse()?,
+                arg_match.get_flag("dry-run"),
+                &audit_log,
+            )?;
+            cli::print_pairs(
+                &"automod",
+                &[
+                    ("rules".to_string(), automod.rule_count().to_string()),
+                    ("audit log".to_string(), audit_log.display().to_string()),
+                ],
+            );
+            let send_interval = std::time::Duration::from_millis(config.bot.send_interval);
+            let area = login_data.area.as_deref();
+            bot::run(
+                &login_data.cookies,
+                room_id,
+                area,
+                &mut automod,
+                send_interval,
+            )
+            .await?;
+        }
+        Some(("raffle", arg_match)) => match arg_match.subcommand() {
+            Some(("start", arg_match)) => {
+                let (login_data, _) = login(&data_path).await?;
+                let cookies = &login_data.cookies;
+                let room_id = resolve_room(arg_match, cookies).await?;
+                let rules = raffle::Rules {
+                    keyword: arg_match.get_one::<String>("keyword").unwrap().clone(),
+                    min_medal_level: arg_match.get_one::<u64>("min-medal-level").copied(),
+                    guards_only: arg_match.get_flag("guards-only"),
+                };
+                let duration = *arg_match.get_one::<u64>("duration").unwrap();
+                let winners = *arg_match.get_one::<u64>("winners").unwrap() as usize;
+                let out = match arg_match.get_one::<PathBuf>("out") {
+                    Some(out) => out.clone(),
+                    None => PathBuf::from(format!(
+                        "raffle-{}.json",
+                        chrono::Local::now().format("%Y%m%d-%H%M%S")
+                    )),
+                };
+                let interval = std::time::Duration::from_millis(config.bot.send_interval);
+                let mut raffle =
+                    raffle::Raffle::new(rules.clone(), room_id, cookies["DedeUserID"].parse()?);
+                let (seed, commitment) = raffle::new_seed();
+
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                danmaku::spawn(tx, cookies.clone(), room_id);
+                let start = Utc::now().timestamp();
+                cli::print_pairs(
+                    &"raffle",
+                    &[
+                        ("keyword".to_string(), rules.keyword.clone()),
+                        (
+                            "duration".to_string(),
+                            cli::format_duration(duration as i64),
+                        ),
+                        ("commitment".to_string(), commitment.clone()),
+                    ],
+                );
+                for text in [
+                    format!("抽奖开始! 发送「{}」参与", rules.keyword),
+                    // 19 characters, within the 20 of a danmaku
+                    format!("哈希 {}", &commitment[..16]),
+                ] {
+                    match bot::send(cookies, room_id, text, interval).await {
+                        Ok(text) => println!("> {}", text),
+                        Err(e) => eprintln!("send failed: {}", e),
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+                let deadline = tokio::time::sleep(std::time::Duration::from_secs(duration));
+                tokio::pin!(deadline);
+                loop {
+                    tokio::select! {
+                        message = rx.recv() => match message {
+                            Some(danmaku::Message::Command(raw)) => {
+                                if let Some(participant) = raffle.add(&event::Event::parse(raw)) {
+                                    println!("+ {} ({})", participant.name, participant.uid);
+                                }
+                            }
+                            Some(danmaku::Message::Disconnected(e)) => eprintln!("disconnected: {}", e),
+                            Some(_) => {}
+                            None => break,
+                        },
+                        _ = &mut deadline => break,
+                        _ = tokio::signal::ctrl_c() => break,
+                    }
+                }
+
+                let draw = raffle::Draw::new(
+                    room_id,
+                    rules,
+                    (start, Utc::now().timestamp()),
+                    seed,
+                    raffle.participants().to_vec(),
+                    winners,
+                );
+                std::fs::write(&out, serde_json::to_string_pretty(&draw)?)?;
+                let mut pairs = vec![
+                    (
+                        "participants".to_string(),
+                        draw.participants.len().to_string(),
+                    ),
+                    ("seed".to_string(), draw.seed.clone()),
+                    ("saved to".to_string(), out.display().to_string()),
+                ];
+                pairs.extend(draw.winners.iter().map(|winner| {
+                    (
+                        "winner".to_string(),
+                        format!("{} ({})", winner.name, winner.uid),
+                    )
+                }));
+                cli::print_pairs(&"draw", &pairs);
+                let mut texts: Vec<_> = draw
+                    .winners
+                    .iter()
+                    .map(|winner| format!("恭喜 {} 中奖!", winner.name))
+                    .collect();
+                if texts.is_empty() {
+                    texts.push("抽奖结束, 无人参与".to_string());
+                }
+                texts.push(format!("种子 {}", &draw.seed[..16]));
+                for text in texts {
+                    match bot::send(cookies, room_id, text, interval).await {
+                        Ok(text) => println!("> {}", text),
+                        Err(e) => eprintln!("send failed: {}", e),
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            }
+            Some(("verify", arg_match)) => {
+                let file = arg_match.get_one::<PathBuf>("FILE").unwrap();
+                let draw: raffle::Draw = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+                draw.verify()?;
+                cli::print_pairs(
+                    &"verified",
+                    &[
+                        (
+                            "participants".to_string(),
+                            draw.participants.len().to_string(),
+                        ),
+                        ("commitment".to_string(), draw.commitment),
+                        ("seed".to_string(), draw.seed),
+                        (
+                            "winners".to_string(),
+                            draw.winners
+                                .iter()
+                                .map(|winner| winner.name.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                        ),
+                    ],
+                );
+            }
+            Some((cmd, _)) => panic!("{}", cmd),
+            None => unreachable!(),
+        },
+        Some(("poll", arg_match)) => match arg_match.subcommand() {
+            Some(("start", arg_match)) => {
+                let (login_data, _) = login(&data_path).await?;
+                let cookies = &login_data.cookies;
+                let room_id = resolve_room(arg_match, cookies).await?;
+                let options: Vec<String> = arg_match
+                    .get_many::<String>("options")
+                    .unwrap()
+                    .map(|option| option.trim().to_string())
+                    .filter(|option| !option.is_empty())
+                    .collect();
+                if options.len() < 2 {
+                    Err("a poll needs at least two options")?;
+                }
+                let duration = *arg_match.get_one::<u64>("duration").unwrap();
+                let port = *arg_match.get_one::<u16>("port").unwrap();
+                let file = arg_match.get_one::<PathBuf>("file").unwrap().clone();
+                let interval = std::time::Duration::from_millis(config.bot.send_interval);
+                let question = arg_match.get_one::<String>("QUESTION").unwrap().clone();
+                let mut poll =
+                    poll::Poll::new(question, options, Utc::now().timestamp() + duration as i64);
+
+                let overlay = overlay::Overlay::new();
+                let listener = overlay::bind(port).await?;
+                tokio::spawn(overlay::serve_on(overlay.clone(), listener, None));
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                danmaku::spawn(tx, cookies.clone(), room_id);
+                let (results_tx, results_rx) = mpsc::unbounded_channel();
+                let mut tui = tokio::task::spawn_blocking(move || tui::poll(results_rx));
+                let mut tui_done = false;
+                let deadline = tokio::time::sleep(std::time::Duration::from_secs(duration));
+                tokio::pin!(deadline);
+                let mut tick = tokio::time::interval(std::time::Duration::from_secs(1));
+                let update = |results: &poll::Results| {
+                    overlay.publish(results);
+                    let _ = results_tx.send(results.clone());
+                    results.write(&file)
+                };
+                loop {
+                    tokio::select! {
+                        message = rx.recv() => match message {
+                            Some(danmaku::Message::Command(raw)) => {
+                                if poll.vote(&event::Event::parse(raw)).is_none() {
+                                    continue;
+                                }
+                            }
+                            Some(_) => continue,
+                            None => break,
+                        },
+                        _ = tick.tick() => {}
+                        _ = &mut deadline => break,
+                        result = &mut tui => {
+                            result??;
+                            tui_done = true;
+                            break;
+                        }
+                    }
+                    update(&poll.results(Utc::now().timestamp(), false))?;
+                }
+
+                let results = poll.results(Utc::now().timestamp(), true);
+                update(&results)?;
+                // printed once the terminal is restored
+                let mut sent = vec![];
+                for text in results.announcement() {
+                    sent.push(bot::send(cookies, room_id, text, interval).await);
+                    tokio::time::sleep(interval).await;
+                }
+                if !tui_done {
+                    tui.await??;
+                }
+                for result in sent {
+                    match result {
+                        Ok(text) => println!("> {}", text),
+                        Err(e) => eprintln!("send failed: {}", e),
+                    }
+                }
+                print!("{}", results.to_text());
+            }
+            Some((cmd, _)) => panic!("{}", cmd),
+            None => unreachable!(),
+        },
+        Some(("songs", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let cookies = &login_data.cookies;
+            let room_id = resolve_room(arg_match, cookies).await?;
+            let port = *arg_match.get_one::<u16>("port").unwrap();
+            let file = arg_match.get_one::<PathBuf>("file").unwrap().clone();
+            let mut requests = songs::SongRequests::load(
+                data_file(&format!("bili-live-songs-{}.json", room_id)),
+                config.songs.clone(),
+                cookies["DedeUserID"].parse()?,
+                room_id,
+            )?;
+
+            let overlay = overlay::Overlay::new();
+            let listener = overlay::bind(port).await?;
+            tokio::spawn(overlay::serve_on(overlay.clone(), listener, None));
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            danmaku::spawn(tx, cookies.clone(), room_id);
+            let (update_tx, update_rx) = mpsc::unbounded_channel();
+            let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+            let mut tui = tokio::task::spawn_blocking(move || tui::songs(update_rx, command_tx));
+            // pages opened later get the queue on the next tick
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(5));
+            let mut changed = true;
+            loop {
+                let result = tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(danmaku::Message::Command(raw)) => {
+                            requests.on_event(&event::Event::parse(raw))
+                        }
+                        Some(_) => None,
+                        None => break,
+                    },
+                    Some(command) = command_rx.recv() => Some(requests.apply(command)),
+                    _ = tick.tick() => {
+                        overlay.publish(requests.queue());
+                        None
+                    }
+                    result = &mut tui => {
+                        result??;
+                        break;
+                    }
+                };
+                let notice = match result {
+                    Some(Ok(notice)) => {
+                        changed = true;
+                        notice
+                    }
+                    Some(Err(notice)) => notice,
+                    None if changed => String::new(),
+                    None => continue,
+                };
+                if changed {
+                    requests.save()?;
+                    requests.write(&file)?;
+                    overlay.publish(requests.queue());
+                    let _ = update_tx.send(songs::Update::Queue(requests.queue().clone()));
+                    changed = false;
+                }
+                if !notice.is_empty() {
+                    let _ = update_tx.send(songs::Update::Notice(notice));
+                }
+            }
+        }
+        Some(("script", arg_match)) => {
+            let dir = match arg_match.get_one::<PathBuf>("dir") {
+                Some(dir) => dir.clone(),
+                None => data_file("bili-live-scripts"),
+            };
+            let mut host = script::ScriptHost::new(dir.clone());
+            host.reload()
+                .map_err(|e| format!("{}: {}", dir.display(), e))?;
+            cli::print_pairs(
+                &"scripts",
+                &host
+                    .script_names()
+                    .into_iter()
+                    .map(|name| ("loaded".to_string(), name))
+                    .collect::<Vec<_>>(),
+            );
+            match arg_match.subcommand() {
+                Some(("run", arg_match)) => {
+                    let (login_data, _) = login(&data_path).await?;
+                    let room_id = resolve_room(arg_match, &login_data.cookies).await?;
+                    let send_interval = std::time::Duration::from_millis(config.bot.send_interval);
+                    let area = login_data.area.as_deref();
+                    bot::run(&login_data.cookies, room_id, area, &mut host, send_interval).await?;
+                }
+                Some(("replay", arg_match)) => {
+                    let records = record::read_jsonl(arg_match.get_one::<PathBuf>("LOG").unwrap())?;
+                    for record in &records {
+                        let now = tokio::time::Instant::now();
+                        for action in bot::EventHandler::on_event(&mut host, &record.event, now) {
+                            let time = DateTime::from_timestamp_millis(record.local_ts)
+                                .ok_or("record time out of range")?;
+                            println!("[{}] {:?}", time, action);
+                        }
+                    }
+                }
+                Some((cmd, _)) => panic!("{}", cmd),
+                None => unreachable!(),
+            }
+        }
+        Some(("mod", arg_match)) => {
+            let (login_data, _) = login(&data_path).await?;
+            let cookies = &login_data.cookies;
+            let room_id = resolve_room(arg_match, cookies).await?;
+            match arg_match.subcommand() {
+                Some(("mute", arg_match)) => {
+                    let user = arg_match.get_one::<String>("USER").unwrap();
+                    let uid = resolve_user(cookies, user).await?;
+                    let hours = arg_match.get_one::<u64>("hours").copied();
+                    live::add_silent_user(cookies, room_id, uid, hours).await?;
+                    let duration = match hours {
+                        Some(hours) => format!("{} hours", hours),
+                        None => "this live".to_string(),
+                    };
+                    cli::print_pairs(
+                        &"muted",
+                        &[
+                            ("uid".to_string(), uid.to_string()),
+                            ("duration".to_string(), duration),
+                        ],
+                    );
+                }
+                Some(("unmute", arg_match)) => {
+                    let user = arg_match.get_one::<String>("USER").unwrap();
+                    let muted = live::get_silent_users(cookies, room_id).await?;
+                    let target = muted
+                        .iter()
+                        .find(|muted| muted.uid.to_string() == *user || muted.name == *user)
+                        .ok_or_else(|| format!("not muted: {}", user))?;
+                    live::del_silent_user(cookies, room_id, target.id).await?;
+                    cli::print_pairs(&"unmuted", &[(target.name.clone(), target.uid.to_string())]);
+                }
+                Some(("list-muted", _)) => {
+                    let muted = live::get_silent_users(cookies, room_id).await?;
+                    let pairs = muted
+                        .into_iter()
+                        .map(|muted| {
+                            (
+                                format!("{}[{}]", muted.name, muted.uid),
+                                format!("until {}", muted.end_time),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    cli::print_pairs(&"muted", &pairs);
+                }
+                Some(("words", arg_match)) => match arg_match.subcommand() {
+                    Some(("add", arg_match)) => {
+                        let word = arg_match.get_one::<String>("WORD").unwrap();
+                        live::add_shield_keyword(cookies, room_id, word).await?;
+                    }
+                    Some(("remove", arg_match)) => {
+                        let word = arg_match.get_one::<String>("WORD").unwrap();
+                        live::del_shield_keyword(cookies, room_id, word).await?;
+                    }
+                    Some(("list", _)) => {
+                        let words = live::get_shield_keywords(cookies, room_id).await?;
+                        for word in words {
+                            println!("- {}", word);
+                        }
+                    }
+                    Some((cmd, _)) => panic!("{}", cmd),
+                    None => unreachable!(),
+                },
+                Some(("admin", arg_match)) => match arg_match.subcommand() {
+                    Some(("add", arg_match)) => {
+                        let user = arg_match.get_one::<String>("USER").unwrap();
+                        live::appoint_admin(cookies, resolve_user(cookies, user).await?).await?;
+                    }
+                    Some(("remove", arg_match)) => {
+                        let user = arg_match.get_one::<String>("USER").unwrap();
+                        live::dismiss_admin(cookies, resolve_user(cookies, user).await?).await?;
+                    }
+                    Some(("list", _)) => {
+                        let admins = live::get_room_admins(cookies).await?;
+                        let pairs = admins
+                            .into_iter()
+                            .map(|(uid, name)| (name, uid.to_string()))
+                            .collect::<Vec<_>>();
+                        cli::print_pairs(&"admins", &pairs);
+                    }
+                    Some((cmd, _)) => panic!("{}", cmd),
+                    None => unreachable!(),
+                },
+                Some((cmd, _)) => panic!("{}", cmd),
+                None => unreachable!(),
+            }
         }
         Some(("clean", arg_match)) => {
             let area = *arg_match.get_one::<bool>("area").unwrap();
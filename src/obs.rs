@@ -0,0 +1,608 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::{
+    net::TcpStream,
+    time::{Duration, Instant, sleep},
+};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite};
+
+pub type Error = Box<dyn std::error::Error>;
+
+const OP_HELLO: u64 = 0;
+const OP_IDENTIFY: u64 = 1;
+const OP_IDENTIFIED: u64 = 2;
+const OP_REQUEST: u64 = 6;
+const OP_REQUEST_RESPONSE: u64 = 7;
+const RPC_VERSION: u64 = 1;
+/// how long `stop_stream` waits for the output to end
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `authentication` string of the identify message,
+/// `base64(sha256(base64(sha256(password + salt)) + challenge))`.
+pub fn auth_response(password: &str, salt: &str, challenge: &str) -> String {
+    let secret = base64::encode(Sha256::digest(format!("{}{}", password, salt)));
+    base64::encode(Sha256::digest(format!("{}{}", secret, challenge)))
+}
+
+/// A client of the obs-websocket 5 protocol.
+pub struct Obs {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+}
+
+impl Obs {
+    /// Connects to `url`, e.g. `ws://localhost:4455`, and identifies with
+    /// `password` when the server asks for one.
+    pub async fn connect(url: &str, password: Option<&str>) -> Result<Obs, Error> {
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|e| format!("cannot connect to OBS at {}: {}", url, e))?;
+        let mut obs = Obs { ws, next_id: 0 };
+
+        let hello = obs.receive(OP_HELLO).await?;
+        let mut identify = json!({
+            "rpcVersion": RPC_VERSION,
+            // no events wanted
+            "eventSubscriptions": 0,
+        });
+        if let Some(auth) = hello.get("authentication") {
+            let password = password.ok_or("OBS asks for a websocket password")?;
+            let salt = auth["salt"].as_str().ok_or("hello without salt")?;
+            let challenge = auth["challenge"]
+                .as_str()
+                .ok_or("hello without challenge")?;
+            identify["authentication"] = auth_response(password, salt, challenge).into();
+        }
+        obs.send(OP_IDENTIFY, identify).await?;
+        obs.receive(OP_IDENTIFIED).await?;
+        Ok(obs)
+    }
+
+    async fn send(&mut self, op: u64, d: Value) -> Result<(), Error> {
+        let message = json!({"op": op, "d": d}).to_string();
+        self.ws.send(tungstenite::Message::text(message)).await?;
+        Ok(())
+    }
+
+    /// Waits for the next message with opcode `op`, skipping others.
+    async fn receive(&mut self, op: u64) -> Result<Value, Error> {
+        loop {
+            match self.ws.next().await {
+                Some(Ok(tungstenite::Message::Text(text))) => {
+                    let mut message: Value = serde_json::from_str(&text)?;
+                    if message["op"].as_u64() == Some(op) {
+                        return Ok(message["d"].take());
+                    }
+                }
+                Some(Ok(tungstenite::Message::Close(frame))) => {
+                    // 4009 is a wrong password
+                    return Err(match frame {
+                        Some(frame) => {
+                            format!("OBS closed the connection: {} {}", frame.code, frame.reason)
+                        }
+                        None => "OBS closed the connection".to_string(),
+                    }
+                    .into());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Err("OBS closed the connection".into()),
+            }
+        }
+    }
+
+    /// Sends a request and returns its `responseData`.
+    pub async fn request(&mut self, request_type: &str, data: Value) -> Result<Value, Error> {
+        self.next_id += 1;
+        let request_id = self.next_id.to_string();
+        self.send(
+            OP_REQUEST,
+            json!({
+                "requestType": request_type,
+                "requestId": request_id,
+                "requestData": data,
+            }),
+        )
+        .await?;
+        loop {
+            let mut response = self.receive(OP_REQUEST_RESPONSE).await?;
+            if response["requestId"].as_str() != Some(&request_id) {
+                continue;
+            }
+            let status = &response["requestStatus"];
+            if status["result"].as_bool() != Some(true) {
+                return Err(format!(
+                    "OBS {} failed: {} {}",
+                    request_type,
+                    status["code"],
+                    status["comment"].as_str().unwrap_or_default()
+                )
+                .into());
+            }
+            return Ok(response["responseData"].take());
+        }
+    }
+
+    /// Points OBS at a custom RTMP server.
+    pub async fn set_stream_service(&mut self, server: &str, key: &str) -> Result<(), Error> {
+        self.request(
+            "SetStreamServiceSettings",
+            json!({
+                "streamServiceType": "rtmp_custom",
+                "streamServiceSettings": {"server": server, "key": key},
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn streaming(&mut self) -> Result<bool, Error> {
+        let status = self.request("GetStreamStatus", json!({})).await?;
+        Ok(status["outputActive"].as_bool().unwrap_or(false))
+    }
+
+    pub async fn start_stream(&mut self) -> Result<(), Error> {
+        if self.streaming().await? {
+            Err("OBS is already streaming")?;
+        }
+        self.request("StartStream", json!({})).await?;
+        Ok(())
+    }
+
+    /// Stops streaming and waits for the output to end, returns whether OBS
+    /// was streaming.
+    pub async fn stop_stream(&mut self) -> Result<bool, Error> {
+        if !self.streaming().await? {
+            return Ok(false);
+        }
+        self.request("StopStream", json!({})).await?;
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while self.streaming().await? {
+            if Instant::now() > deadline {
+                Err("OBS did not stop streaming in time")?;
+            }
+            sleep(Duration::from_millis(200)).await;
+        }
+        Ok(true)
+    }
+}
+
+/// `obs-studio` in the user config directory.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("obs-studio"))
+}
+
+/// The value of `key` in `[section]` of an ini file.
+fn ini_value(path: &Path, section: &str, key: &str) -> Option<String> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let mut in_section = false;
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.starts_with('[') {
+            in_section = line == format!("[{}]", section);
+        } else if in_section
+            && let Some((k, v)) = line.split_once('=')
+            && k.trim() == key
+        {
+            return Some(v.trim().to_string());
+        }
+    }
+    None
+}
+
+/// The `service.json` of a profile given by a path, a profile directory
+/// name or a profile name.
+pub fn find_profile(profile: &str) -> Result<PathBuf, Error> {
+    let path = Path::new(profile);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    if path.is_dir() {
+        return Ok(path.join("service.json"));
+    }
+    let profiles = config_dir()
+        .ok_or("cannot locate the OBS config directory")?
+        .join("basic")
+        .join("profiles");
+    find_profile_in(&profiles, profile)
+}
+
+/// Looks up a profile by directory name or name in `profiles`.
+fn find_profile_in(profiles: &Path, profile: &str) -> Result<PathBuf, Error> {
+    for entry in std::fs::read_dir(profiles)
+        .map_err(|e| format!("no OBS profiles in {}: {}", profiles.display(), e))?
+    {
+        let dir = entry?.path();
+        let name = ini_value(&dir.join("basic.ini"), "General", "Name");
+        if dir.file_name().is_some_and(|dir| dir == profile) || name.as_deref() == Some(profile) {
+            return Ok(dir.join("service.json"));
+        }
+    }
+    Err(format!("no OBS profile {} in {}", profile, profiles.display()).into())
+}
+
+/// Whether a line of `ps -A -o comm=` or `tasklist /FO CSV /NH` is OBS.
+fn is_obs_process(line: &str) -> bool {
+    let name = line.split(',').next().unwrap_or_default();
+    let name = name.trim().trim_matches('"');
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    matches!(
+        name.to_lowercase().as_str(),
+        "obs" | "obs.exe" | "obs64.exe" | "obs32.exe"
+    )
+}
+
+/// Whether OBS is running by the process list, `None` when it cannot be
+/// listed.
+fn obs_running() -> Option<bool> {
+    let output = match cfg!(windows) {
+        true => std::process::Command::new("tasklist")
+            .args(["/FO", "CSV", "/NH"])
+            .output(),
+        false => std::process::Command::new("ps")
+            .args(["-A", "-o", "comm="])
+            .output(),
+    };
+    let output = output.ok().filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(is_obs_process),
+    )
+}
+
+/// Whether OBS is running with the profile of `service` as its current
+/// one, in which case it overwrites the file on exit. Without a process
+/// list, the `run_*` files OBS 30 and later leave in `.sentinel` tell,
+/// though a crash leaves them behind.
+pub fn profile_locked(service: &Path) -> bool {
+    let Some(dir) = config_dir() else {
+        return false;
+    };
+    let running = obs_running().unwrap_or_else(|| {
+        std::fs::read_dir(dir.join(".sentinel")).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("run_"))
+        })
+    });
+    running && is_current_profile(&dir, service)
+}
+
+/// Whether `service` belongs to the profile OBS last used.
+fn is_current_profile(dir: &Path, service: &Path) -> bool {
+    // OBS 31 moved the current profile from global.ini to user.ini
+    let current = ["user.ini", "global.ini"]
+        .iter()
+        .find_map(|ini| ini_value(&dir.join(ini), "Basic", "ProfileDir"));
+    let profile = service
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .map(|dir| dir.to_string_lossy().into_owned());
+    current.is_some_and(|current| profile.is_none_or(|profile| profile == current))
+}
+
+/// Sets a custom RTMP server and key in `service.json`, keeping a backup of
+/// the old file next to it. Returns the backup path.
+pub fn write_service(service: &Path, server: &str, key: &str) -> Result<Option<PathBuf>, Error> {
+    let old = match std::fs::read_to_string(service) {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => Err(e)?,
+    };
+    let mut settings = json!({});
+    let mut backup = None;
+    if let Some(old) = old {
+        let mut old: Value =
+            serde_json::from_str(&old).map_err(|e| format!("{}: {}", service.display(), e))?;
+        // keep extra settings such as `use_auth` of a custom server
+        if old["type"] == "rtmp_custom" && old["settings"].is_object() {
+            settings = old["settings"].take();
+        }
+        let path = service.with_extension(format!(
+            "json.{}.bak",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        ));
+        std::fs::copy(service, &path)?;
+        backup = Some(path);
+    }
+    settings["server"] = server.into();
+    settings["key"] = key.into();
+    let service_json = json!({"type": "rtmp_custom", "settings": settings});
+    std::fs::write(service, serde_json::to_string_pretty(&service_json)?)?;
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const SALT: &str = "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=";
+    const CHALLENGE: &str = "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY=";
+
+    #[test]
+    fn auth_response_example() {
+        // the example of the obs-websocket protocol docs
+        assert_eq!(
+            auth_response("supersecretpassword", SALT, CHALLENGE),
+            "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+        );
+    }
+
+    /// Plays an OBS asking for a password for one connection, returns the
+    /// identify message, if any, and the requests it got.
+    async fn stand_in(listener: TcpListener) -> (Option<Value>, Vec<Value>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let hello = json!({
+            "op": OP_HELLO,
+            "d": {
+                "obsWebSocketVersion": "5.5.0",
+                "rpcVersion": 1,
+                "authentication": {"challenge": CHALLENGE, "salt": SALT},
+            },
+        });
+        ws.send(tungstenite::Message::text(hello.to_string()))
+            .await
+            .unwrap();
+
+        let mut identify = None;
+        let mut requests = vec![];
+        let mut streaming = false;
+        // GetStreamStatus polls still reporting an active output after StopStream
+        let mut stopping = 0;
+        while let Some(Ok(tungstenite::Message::Text(text))) = ws.next().await {
+            let mut message: Value = serde_json::from_str(&text).unwrap();
+            let d = message["d"].take();
+            let reply = match message["op"].as_u64().unwrap() {
+                OP_IDENTIFY => {
+                    identify = Some(d);
+                    json!({"op": OP_IDENTIFIED, "d": {"negotiatedRpcVersion": 1}})
+                }
+                OP_REQUEST => {
+                    let (status, data) = match d["requestType"].as_str().unwrap() {
+                        "GetStreamStatus" => {
+                            if stopping > 0 {
+                                stopping -= 1;
+                                streaming = stopping > 0;
+                            }
+                            (
+                                json!({"result": true, "code": 100}),
+                                json!({"outputActive": streaming}),
+                            )
+                        }
+                        "StartStream" => {
+                            streaming = true;
+                            (json!({"result": true, "code": 100}), Value::Null)
+                        }
+                        "StopStream" => {
+                            stopping = 3;
+                            (json!({"result": true, "code": 100}), Value::Null)
+                        }
+                        "SetStreamServiceSettings" => {
+                            (json!({"result": true, "code": 100}), Value::Null)
+                        }
+                        _ => (
+                            json!({"result": false, "code": 204, "comment": "unknown request"}),
+                            Value::Null,
+                        ),
+                    };
+                    let reply = json!({
+                        "op": OP_REQUEST_RESPONSE,
+                        "d": {
+                            "requestType": d["requestType"],
+                            "requestId": d["requestId"],
+                            "requestStatus": status,
+                            "responseData": data,
+                        },
+                    });
+                    requests.push(d);
+                    reply
+                }
+                op => panic!("unexpected op {}", op),
+            };
+            ws.send(tungstenite::Message::text(reply.to_string()))
+                .await
+                .unwrap();
+        }
+        (identify, requests)
+    }
+
+    #[tokio::test]
+    async fn requests_with_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(stand_in(listener));
+
+        let mut obs = Obs::connect(&url, Some("secret")).await.unwrap();
+        obs.set_stream_service("rtmp://live-push.bilibili.com/live-bvc/", "?key")
+            .await
+            .unwrap();
+        obs.start_stream().await.unwrap();
+        let error = obs.start_stream().await.unwrap_err();
+        assert_eq!(error.to_string(), "OBS is already streaming");
+        assert!(obs.stop_stream().await.unwrap());
+        assert!(!obs.stop_stream().await.unwrap());
+        let error = obs.request("Sleep", json!({})).await.unwrap_err();
+        assert_eq!(error.to_string(), "OBS Sleep failed: 204 unknown request");
+        drop(obs);
+
+        let (identify, requests) = server.await.unwrap();
+        assert_eq!(
+            identify.unwrap(),
+            json!({
+                "rpcVersion": 1,
+                "eventSubscriptions": 0,
+                "authentication": auth_response("secret", SALT, CHALLENGE),
+            })
+        );
+        assert_eq!(
+            requests[0]["requestData"],
+            json!({
+                "streamServiceType": "rtmp_custom",
+                "streamServiceSettings": {
+                    "server": "rtmp://live-push.bilibili.com/live-bvc/",
+                    "key": "?key",
+                },
+            })
+        );
+        let types = requests
+            .iter()
+            .map(|request| request["requestType"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "SetStreamServiceSettings",
+                "GetStreamStatus",
+                "StartStream",
+                "GetStreamStatus",
+                "GetStreamStatus",
+                "StopStream",
+                // polled until the output is no longer active
+                "GetStreamStatus",
+                "GetStreamStatus",
+                "GetStreamStatus",
+                "GetStreamStatus",
+                "Sleep",
+            ]
+        );
+        let ids = requests
+            .iter()
+            .map(|request| request["requestId"].as_str().unwrap())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(ids.len(), requests.len());
+    }
+
+    #[tokio::test]
+    async fn password_required() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(stand_in(listener));
+        let error = Obs::connect(&url, None).await.err().unwrap();
+        assert_eq!(error.to_string(), "OBS asks for a websocket password");
+        assert_eq!(server.await.unwrap(), (None, vec![]));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bili-live-obs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A profile directory `dir` named `name` in `profiles`.
+    fn profile(profiles: &Path, dir: &str, name: &str) -> PathBuf {
+        let dir = profiles.join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let ini = format!(
+            "\u{feff}[General]\r\nName={}\r\n\r\n[Output]\r\nMode=Simple\r\n",
+            name
+        );
+        std::fs::write(dir.join("basic.ini"), ini).unwrap();
+        dir
+    }
+
+    #[test]
+    fn profiles() {
+        let profiles = temp_dir("profiles");
+        let untitled = profile(&profiles, "Untitled", "Untitled");
+        let stream = profile(&profiles, "Stream_Profile", "Stream Profile");
+
+        let found = |profile| find_profile_in(&profiles, profile).unwrap();
+        assert_eq!(found("Stream Profile"), stream.join("service.json"));
+        assert_eq!(found("Stream_Profile"), stream.join("service.json"));
+        assert_eq!(found("Untitled"), untitled.join("service.json"));
+        let error = find_profile_in(&profiles, "Other").unwrap_err().to_string();
+        assert!(error.starts_with("no OBS profile Other in"), "{}", error);
+        assert!(find_profile_in(&profiles.join("missing"), "Untitled").is_err());
+
+        // paths are taken as they are
+        let dir = stream.to_str().unwrap();
+        assert_eq!(find_profile(dir).unwrap(), stream.join("service.json"));
+        std::fs::write(stream.join("service.json"), "{}").unwrap();
+        let file = stream.join("service.json");
+        assert_eq!(find_profile(file.to_str().unwrap()).unwrap(), file);
+        std::fs::remove_dir_all(profiles).unwrap();
+    }
+
+    #[test]
+    fn current_profile() {
+        let dir = temp_dir("current");
+        let service = dir.join("basic/profiles/Untitled/service.json");
+        assert!(!is_current_profile(&dir, &service));
+        std::fs::write(dir.join("global.ini"), "[Basic]\nProfileDir=Untitled\n").unwrap();
+        assert!(is_current_profile(&dir, &service));
+        // user.ini of OBS 31 wins
+        std::fs::write(dir.join("user.ini"), "[Basic]\nProfileDir=Other\n").unwrap();
+        assert!(!is_current_profile(&dir, &service));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn obs_processes() {
+        assert!(is_obs_process("obs"));
+        assert!(is_obs_process("/Applications/OBS.app/Contents/MacOS/OBS"));
+        assert!(is_obs_process(
+            r#""obs64.exe","12345","Console","1","412,000 K""#
+        ));
+        assert!(!is_obs_process("obs-ffmpeg-mux"));
+        assert!(!is_obs_process(r#""explorer.exe","1","Console","1","1 K""#));
+        assert!(!is_obs_process(""));
+    }
+
+    #[test]
+    fn service() {
+        let dir = temp_dir("service");
+        let service = dir.join("service.json");
+        let server = "rtmp://live-push.bilivideo.com/live-bvc/";
+        let read = || -> Value {
+            serde_json::from_str(&std::fs::read_to_string(&service).unwrap()).unwrap()
+        };
+
+        // no file yet, no backup
+        assert_eq!(write_service(&service, server, "?key1").unwrap(), None);
+        assert_eq!(
+            read(),
+            json!({"type": "rtmp_custom", "settings": {"server": server, "key": "?key1"}})
+        );
+
+        let old = json!({
+            "type": "rtmp_custom",
+            "settings": {"server": "rtmp://old/", "key": "old", "use_auth": true, "username": "u"},
+        })
+        .to_string();
+        std::fs::write(&service, &old).unwrap();
+        let backup = write_service(&service, server, "?key2").unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), old);
+        assert!(
+            backup
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .ends_with(".bak")
+        );
+        assert_eq!(
+            read()["settings"],
+            json!({"server": server, "key": "?key2", "use_auth": true, "username": "u"})
+        );
+
+        // settings of another service type are dropped
+        let common = json!({"type": "rtmp_common", "settings": {"service": "Twitch", "key": "t"}});
+        std::fs::write(&service, common.to_string()).unwrap();
+        write_service(&service, server, "?key3").unwrap();
+        assert_eq!(
+            read()["settings"],
+            json!({"server": server, "key": "?key3"})
+        );
+
+        std::fs::write(&service, "{ not json").unwrap();
+        assert!(write_service(&service, server, "?key4").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
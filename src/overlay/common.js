@@ -0,0 +1,23 @@
+// Shared helpers of the overlay pages.
+
+function escapeHtml(text) {
+  const div = document.createElement("div");
+  div.textContent = text;
+  return div.innerHTML;
+}
+
+// Calls onMessage with every message of the overlay server, reconnecting
+// when the connection drops.
+function connect(onMessage) {
+  const ws = new WebSocket(`ws://${location.host}/ws`);
+  ws.onmessage = (e) => onMessage(JSON.parse(e.data));
+  ws.onclose = () => setTimeout(() => connect(onMessage), 2000);
+}
+
+function guardName(level) {
+  return { 1: "总督", 2: "提督", 3: "舰长" }[level] || "";
+}
+
+function colorOf(value) {
+  return "#" + (value & 0xffffff).toString(16).padStart(6, "0");
+}
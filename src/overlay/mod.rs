@@ -0,0 +1,393 @@
+use crate::event::{Danmu, Event, Gift, GuardBuy, Interact, InteractKind, SuperChat, User};
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    time::{Duration, interval},
+};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{self, handshake::derive_accept_key, protocol::Role},
+};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The built-in pages, `(path, title, html)`.
+pub const PAGES: &[(&str, &str, &str)] = &[
+    ("/danmaku", "danmaku overlay", include_str!("danmaku.html")),
+    (
+        "/alerts",
+        "gift and super chat alerts",
+        include_str!("alerts.html"),
+    ),
+    ("/ticker", "follower ticker", include_str!("ticker.html")),
+    ("/poll", "chat poll results", include_str!("poll.html")),
+    ("/songs", "song request queue", include_str!("songs.html")),
+];
+
+const COMMON_JS: &str = include_str!("common.js");
+
+/// Fans JSON messages out to every connected page. Messages carry a `type`
+/// field, room events are sent as serialized [`Event`]s.
+#[derive(Clone)]
+pub struct Overlay {
+    tx: broadcast::Sender<String>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            tx: broadcast::channel(1024).0,
+        }
+    }
+
+    pub fn publish<T: Serialize>(&self, message: &T) {
+        if let Ok(message) = serde_json::to_string(message) {
+            // no page connected is fine
+            let _ = self.tx.send(message);
+        }
+    }
+}
+
+struct Request {
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, Error> {
+    let mut buf = vec![];
+    let mut chunk = [0; 1024];
+    let head = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            Err("connection closed before the request ended")?;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break String::from_utf8_lossy(&buf[..end]).into_owned();
+        }
+        if buf.len() > MAX_REQUEST_LEN {
+            Err("request header too large")?;
+        }
+    };
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("GET") {
+        Err(format!("unsupported request: {}", request_line))?;
+    }
+    let target = parts.next().ok_or("missing request target")?;
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    Ok(Request { path, headers })
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), Error> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    Ok(())
+}
+
+fn index() -> String {
+    let links: String = PAGES
+        .iter()
+        .map(|(path, title, _)| format!("<li><a href=\"{path}\">{path}</a> {title}</li>"))
+        .collect();
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>bili-live overlay</title></head>\
+         <body><h1>bili-live overlay</h1><p>Add a page as a browser source in OBS.</p><ul>{links}</ul></body></html>"
+    )
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    tx: broadcast::Sender<String>,
+    css: Arc<Option<PathBuf>>,
+) -> Result<(), Error> {
+    let request = read_request(&mut stream).await?;
+    match request.path.as_str() {
+        "/ws" => {
+            let key = request
+                .headers
+                .get("sec-websocket-key")
+                .ok_or("websocket request without key")?;
+            // subscribed before the handshake ends, so the page gets every
+            // message published once it is connected
+            let mut rx = tx.subscribe();
+            let head = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            );
+            stream.write_all(head.as_bytes()).await?;
+            let mut ws = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+            loop {
+                tokio::select! {
+                    message = rx.recv() => match message {
+                        Ok(message) => ws.send(tungstenite::Message::text(message)).await?,
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    },
+                    message = ws.next() => match message {
+                        Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Err(e.into()),
+                    },
+                }
+            }
+        }
+        "/" => {
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                index().as_bytes(),
+            )
+            .await
+        }
+        "/common.js" => {
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/javascript; charset=utf-8",
+                COMMON_JS.as_bytes(),
+            )
+            .await
+        }
+        "/theme.css" => {
+            // read on every request so theme edits show up on reload
+            let theme = match css.as_ref() {
+                Some(css) => tokio::fs::read(css).await.map_err(|e| (css, e)),
+                None => Ok(vec![]),
+            };
+            match theme {
+                Ok(theme) => {
+                    respond(&mut stream, "200 OK", "text/css; charset=utf-8", &theme).await
+                }
+                Err((css, e)) => {
+                    let status = match e.kind() {
+                        std::io::ErrorKind::NotFound => "404 Not Found",
+                        _ => "500 Internal Server Error",
+                    };
+                    let message = format!("{}: {}", css.display(), e);
+                    eprintln!("overlay: {}", message);
+                    respond(&mut stream, status, "text/plain", message.as_bytes()).await
+                }
+            }
+        }
+        path => match PAGES.iter().find(|(page, _, _)| *page == path) {
+            Some((_, _, html)) => {
+                respond(
+                    &mut stream,
+                    "200 OK",
+                    "text/html; charset=utf-8",
+                    html.as_bytes(),
+                )
+                .await
+            }
+            None => respond(&mut stream, "404 Not Found", "text/plain", b"not found").await,
+        },
+    }
+}
+
+/// Serves the overlay pages on `127.0.0.1:port` until the task is dropped,
+/// `css` is a user stylesheet loaded after the built-in styles.
+pub async fn serve(overlay: Overlay, port: u16, css: Option<PathBuf>) -> Result<(), Error> {
+    serve_on(overlay, bind(port).await?, css).await
+}
+
+pub async fn bind(port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port)).await
+}
+
+/// Like [`serve`] on a listener bound beforehand, so the caller learns
+/// about a taken port before going on.
+pub async fn serve_on(
+    overlay: Overlay,
+    listener: TcpListener,
+    css: Option<PathBuf>,
+) -> Result<(), Error> {
+    let css = Arc::new(css);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let tx = overlay.tx.clone();
+        let css = css.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, tx, css).await {
+                eprintln!("overlay: {}", e);
+            }
+        });
+    }
+}
+
+/// Sample events cycled through by the test mode.
+fn fake_event(n: u64) -> Event {
+    let now = Utc::now();
+    let names = ["阿梓", "小明", "Tom", "夜猫子", "路人甲"];
+    let user = User {
+        uid: 10000 + n % 5,
+        name: names[(n % 5) as usize].to_string(),
+        ..Default::default()
+    };
+    match n % 8 {
+        3 => Event::Gift(Gift {
+            user,
+            gift_id: 31036,
+            gift_name: "小花花".to_string(),
+            num: 1 + n % 10,
+            price: 100,
+            coin_type: "gold".to_string(),
+            action: "投喂".to_string(),
+            timestamp: now.timestamp(),
+        }),
+        5 => Event::Interact(Interact {
+            user,
+            kind: InteractKind::Follow,
+            timestamp: now.timestamp(),
+        }),
+        6 => Event::SuperChat(SuperChat {
+            id: n,
+            user,
+            message: "测试醒目留言".to_string(),
+            price: 30,
+            start_time: now.timestamp(),
+            end_time: now.timestamp() + 60,
+            background_color: "#EDF5FF".to_string(),
+        }),
+        7 if n % 16 == 15 => Event::GuardBuy(GuardBuy {
+            uid: user.uid,
+            name: user.name,
+            guard_level: 3,
+            num: 1,
+            price: 198000,
+            gift_name: "舰长".to_string(),
+            start_time: now.timestamp(),
+        }),
+        _ => Event::Danmu(Danmu {
+            user,
+            text: format!("测试弹幕 #{}", n),
+            mode: 1,
+            font_size: 25,
+            color: 0xffffff,
+            timestamp_ms: now.timestamp_millis(),
+            emoticon: None,
+            reply_to: None,
+        }),
+    }
+}
+
+/// Publishes a fake event every `every` until the task is dropped.
+pub async fn inject_fake_events(overlay: Overlay, every: Duration) {
+    let mut ticker = interval(every);
+    for n in 0.. {
+        ticker.tick().await;
+        overlay.publish(&fake_event(n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn start(css: Option<PathBuf>) -> (Overlay, std::net::SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let overlay = Overlay::new();
+        tokio::spawn(serve_on(overlay.clone(), listener, css));
+        (overlay, addr)
+    }
+
+    /// The status line and body of a GET request.
+    async fn get(addr: std::net::SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let css = std::env::temp_dir().join(format!("bili-live-theme-{}.css", std::process::id()));
+        std::fs::write(&css, "body { color: red; }").unwrap();
+        let (_, addr) = start(Some(css.clone())).await;
+
+        let (status, body) = get(addr, "/").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        for (path, _, _) in PAGES {
+            assert!(body.contains(&format!("href=\"{}\"", path)));
+            let (status, body) = get(addr, &format!("{}?room=1", path)).await;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert!(body.starts_with("<!DOCTYPE html>"), "{}", path);
+        }
+        assert_eq!(get(addr, "/common.js").await.1, COMMON_JS);
+        assert_eq!(get(addr, "/theme.css").await.1, "body { color: red; }");
+        assert_eq!(get(addr, "/missing").await.0, "HTTP/1.1 404 Not Found");
+
+        std::fs::remove_file(&css).unwrap();
+        let (status, body) = get(addr, "/theme.css").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert!(body.starts_with(&css.display().to_string()));
+
+        // no theme configured
+        let (_, addr) = start(None).await;
+        assert_eq!(
+            get(addr, "/theme.css").await,
+            ("HTTP/1.1 200 OK".to_string(), String::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn fan_out() {
+        let (overlay, addr) = start(None).await;
+        let url = format!("ws://{}/ws", addr);
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        overlay.publish(&json!({"type": "poll", "total": 3}));
+        overlay.publish(&fake_event(0));
+        for ws in [&mut first, &mut second] {
+            let mut texts = vec![];
+            for _ in 0..2 {
+                match ws.next().await {
+                    Some(Ok(tungstenite::Message::Text(text))) => texts.push(text.to_string()),
+                    other => panic!("{:?}", other),
+                }
+            }
+            assert_eq!(texts[0], r#"{"total":3,"type":"poll"}"#);
+            let event: serde_json::Value = serde_json::from_str(&texts[1]).unwrap();
+            assert_eq!(event["type"], "danmu");
+        }
+
+        // a closed page does not stop the others
+        first.close(None).await.unwrap();
+        overlay.publish(&json!({"type": "songs"}));
+        match second.next().await {
+            Some(Ok(tungstenite::Message::Text(text))) => assert_eq!(text, r#"{"type":"songs"}"#),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::event::Event;
+use serde::Serialize;
+use std::{collections::HashMap, path::Path};
+
+/// Counts one vote per uid from danmaku naming an option, either by its
+/// text or by its number starting at 1.
+pub struct Poll {
+    question: String,
+    options: Vec<String>,
+    votes: Vec<u64>,
+    /// the option each uid voted for, the first vote counts
+    voters: HashMap<u64, usize>,
+    /// unix seconds
+    end: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OptionResult {
+    pub name: String,
+    pub votes: u64,
+    pub percent: f64,
+}
+
+/// The standings of a poll, sent to the overlay pages as `{"type": "poll"}`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename = "poll")]
+pub struct Results {
+    pub question: String,
+    pub options: Vec<OptionResult>,
+    pub total: u64,
+    /// seconds until the poll closes
+    pub remaining: i64,
+    pub closed: bool,
+}
+
+impl Poll {
+    pub fn new(question: String, options: Vec<String>, end: i64) -> Poll {
+        Poll {
+            question,
+            votes: vec![0; options.len()],
+            options,
+            voters: HashMap::new(),
+            end,
+        }
+    }
+
+    /// The option named by `text`, its text wins over its number so that
+    /// options like `2024` can be voted for.
+    fn option_of(&self, text: &str) -> Option<usize> {
+        let text = text.trim().to_lowercase();
+        self.options
+            .iter()
+            .position(|option| option.to_lowercase() == text)
+            .or_else(|| {
+                let n = text.parse::<usize>().ok()?;
+                (1..=self.options.len()).contains(&n).then(|| n - 1)
+            })
+    }
+
+    /// Counts the vote of a danmaku, returning the option voted for.
+    pub fn vote(&mut self, event: &Event) -> Option<usize> {
+        let Event::Danmu(danmu) = event else {
+            return None;
+        };
+        let option = self.option_of(&danmu.text)?;
+        if self.voters.contains_key(&danmu.user.uid) {
+            return None;
+        }
+        self.voters.insert(danmu.user.uid, option);
+        self.votes[option] += 1;
+        Some(option)
+    }
+
+    pub fn results(&self, now: i64, closed: bool) -> Results {
+        let total = self.votes.iter().sum();
+        Results {
+            question: self.question.clone(),
+            options: self
+                .options
+                .iter()
+                .zip(&self.votes)
+                .map(|(name, &votes)| OptionResult {
+                    name: name.clone(),
+                    votes,
+                    percent: match total {
+                        0 => 0.0,
+                        total => votes as f64 * 100.0 / total as f64,
+                    },
+                })
+                .collect(),
+            total,
+            remaining: if closed { 0 } else { (self.end - now).max(0) },
+            closed,
+        }
+    }
+}
+
+impl Results {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", self.question);
+        for (i, option) in self.options.iter().enumerate() {
+            text.push_str(&format!(
+                "{}. {}  {} 票 ({:.0}%)\n",
+                i + 1,
+                option.name,
+                option.votes,
+                option.percent
+            ));
+        }
+        match self.closed {
+            true => text.push_str(&format!("共 {} 票, 投票已结束\n", self.total)),
+            false => text.push_str(&format!(
+                "共 {} 票, 剩余 {}\n",
+                self.total,
+                crate::cli::format_duration(self.remaining)
+            )),
+        }
+        text
+    }
+
+    /// Writes the standings for a text source in OBS.
+    pub fn write<P: AsRef<Path>>(&self, fname: P) -> std::io::Result<()> {
+        std::fs::write(fname, self.to_text())
+    }
+
+    /// The danmaku announcing the outcome.
+    pub fn announcement(&self) -> Vec<String> {
+        let most = self.options.iter().map(|option| option.votes).max();
+        let winners: Vec<_> = self
+            .options
+            .iter()
+            .filter(|option| Some(option.votes) == most)
+            .map(|option| option.name.as_str())
+            .collect();
+        let mut texts = vec![match (self.total, winners.as_slice()) {
+            (0, _) => "投票结束, 无人投票".to_string(),
+            (_, [winner]) => format!("投票结束: {} 胜出", winner),
+            (_, winners) => format!("投票结束: {} 平票", winners.join(" / ")),
+        }];
+        if self.total > 0 {
+            texts.push(
+                self.options
+                    .iter()
+                    .map(|option| format!("{} {}票", option.name, option.votes))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+        texts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Danmu, User};
+
+    fn danmu(uid: u64, text: &str) -> Event {
+        Event::Danmu(Danmu {
+            user: User {
+                uid,
+                ..User::default()
+            },
+            text: text.to_string(),
+            mode: 1,
+            font_size: 25,
+            color: 0xffffff,
+            timestamp_ms: 0,
+            emoticon: None,
+            reply_to: None,
+        })
+    }
+
+    fn poll(options: &[&str]) -> Poll {
+        let options = options.iter().map(|option| option.to_string()).collect();
+        Poll::new("问题".to_string(), options, 100)
+    }
+
+    #[test]
+    fn votes() {
+        let mut poll = poll(&["Yes", "No"]);
+        assert_eq!(poll.vote(&danmu(1, "1")), Some(0));
+        assert_eq!(poll.vote(&danmu(2, " no ")), Some(1));
+        assert_eq!(poll.vote(&danmu(3, "3")), None);
+        assert_eq!(poll.vote(&danmu(3, "maybe")), None);
+        // the first vote of a uid counts
+        assert_eq!(poll.vote(&danmu(1, "2")), None);
+        assert_eq!(poll.votes, [1, 1]);
+    }
+
+    #[test]
+    fn numbers_as_options() {
+        let mut poll = poll(&["2023", "2024", "1"]);
+        assert_eq!(poll.vote(&danmu(1, "2024")), Some(1));
+        assert_eq!(poll.vote(&danmu(2, "1")), Some(2));
+        assert_eq!(poll.vote(&danmu(3, "2")), Some(1));
+    }
+
+    #[test]
+    fn results() {
+        let mut poll = poll(&["A", "B", "C"]);
+        for (uid, text) in [(1, "A"), (2, "A"), (3, "b"), (4, "1")] {
+            poll.vote(&danmu(uid, text));
+        }
+        let results = poll.results(40, false);
+        assert_eq!(results.total, 4);
+        assert_eq!(results.remaining, 60);
+        let percents = results
+            .options
+            .iter()
+            .map(|o| o.percent)
+            .collect::<Vec<_>>();
+        assert_eq!(percents, [75.0, 25.0, 0.0]);
+        assert_eq!(
+            results.announcement(),
+            ["投票结束: A 胜出", "A 3票, B 1票, C 0票"]
+        );
+        assert_eq!(poll.results(200, false).remaining, 0);
+        assert_eq!(poll.results(40, true).remaining, 0);
+    }
+
+    #[test]
+    fn announcements() {
+        let mut poll = poll(&["A", "B", "C"]);
+        assert_eq!(poll.results(0, true).announcement(), ["投票结束, 无人投票"]);
+        let results = poll.results(0, true);
+        assert!(results.options.iter().all(|option| option.percent == 0.0));
+
+        poll.vote(&danmu(1, "A"));
+        poll.vote(&danmu(2, "C"));
+        assert_eq!(
+            poll.results(0, true).announcement(),
+            ["投票结束: A / C 平票", "A 1票, B 0票, C 1票"]
+        );
+    }
+}
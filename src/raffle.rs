@@ -0,0 +1,334 @@
+use crate::event::{Danmu, Event};
+use rand_chacha::{
+    ChaCha20Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Who may join a raffle.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rules {
+    /// the danmaku text to send, compared after trimming
+    pub keyword: String,
+    /// the fan medal of this room must be at least this level
+    pub min_medal_level: Option<u64>,
+    pub guards_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Participant {
+    pub uid: u64,
+    pub name: String,
+    /// unix milliseconds of the joining danmaku
+    pub timestamp_ms: i64,
+}
+
+/// Collects the unique participants of a raffle from live events.
+pub struct Raffle {
+    rules: Rules,
+    room_id: u64,
+    /// the host never takes part
+    own_uid: u64,
+    participants: Vec<Participant>,
+    joined: HashSet<u64>,
+}
+
+impl Raffle {
+    pub fn new(rules: Rules, room_id: u64, own_uid: u64) -> Raffle {
+        Raffle {
+            rules,
+            room_id,
+            own_uid,
+            participants: vec![],
+            joined: HashSet::new(),
+        }
+    }
+
+    fn eligible(&self, danmu: &Danmu) -> bool {
+        let user = &danmu.user;
+        if danmu.text.trim() != self.rules.keyword || user.uid == self.own_uid {
+            return false;
+        }
+        if self.rules.guards_only && user.guard_level == 0 {
+            return false;
+        }
+        self.rules.min_medal_level.is_none_or(|level| {
+            user.medal
+                .as_ref()
+                .is_some_and(|medal| medal.anchor_room_id == self.room_id && medal.level >= level)
+        })
+    }
+
+    /// Adds the sender of a matching danmaku, returning the new participant.
+    pub fn add(&mut self, event: &Event) -> Option<&Participant> {
+        let Event::Danmu(danmu) = event else {
+            return None;
+        };
+        if !self.eligible(danmu) || !self.joined.insert(danmu.user.uid) {
+            return None;
+        }
+        self.participants.push(Participant {
+            uid: danmu.user.uid,
+            name: danmu.user.name.clone(),
+            timestamp_ms: danmu.timestamp_ms,
+        });
+        self.participants.last()
+    }
+
+    pub fn participants(&self) -> &[Participant] {
+        &self.participants
+    }
+}
+
+/// A finished raffle, saved so anyone can check the winners with
+/// `raffle verify`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Draw {
+    pub room_id: u64,
+    pub rules: Rules,
+    /// unix seconds
+    pub start: i64,
+    /// unix seconds
+    pub end: i64,
+    /// SHA-256 of the seed, announced when the raffle starts
+    pub commitment: String,
+    /// hex, revealed when the winners are drawn
+    pub seed: String,
+    pub participants: Vec<Participant>,
+    pub winners: Vec<Participant>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    let mut seed = [0; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(seed)
+}
+
+/// A fresh random seed and its commitment.
+pub fn new_seed() -> ([u8; 32], String) {
+    let mut seed = [0; 32];
+    rand::thread_rng().fill_bytes(&mut seed);
+    (seed, to_hex(&Sha256::digest(seed)))
+}
+
+/// Draws up to `count` winners: participants are sorted by uid, then a
+/// ChaCha20 stream seeded with `seed` runs a partial Fisher-Yates shuffle,
+/// swapping position `i` with `i + next_u64() % (n - i)`.
+pub fn draw_winners(
+    participants: &[Participant],
+    seed: [u8; 32],
+    count: usize,
+) -> Vec<Participant> {
+    let mut pool = participants.to_vec();
+    pool.sort_by_key(|participant| participant.uid);
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let count = count.min(pool.len());
+    for i in 0..count {
+        let j = i + (rng.next_u64() % (pool.len() - i) as u64) as usize;
+        pool.swap(i, j);
+    }
+    pool.truncate(count);
+    pool
+}
+
+impl Draw {
+    pub fn new(
+        room_id: u64,
+        rules: Rules,
+        (start, end): (i64, i64),
+        seed: [u8; 32],
+        participants: Vec<Participant>,
+        count: usize,
+    ) -> Draw {
+        Draw {
+            room_id,
+            rules,
+            start,
+            end,
+            commitment: to_hex(&Sha256::digest(seed)),
+            seed: to_hex(&seed),
+            winners: draw_winners(&participants, seed, count),
+            participants,
+        }
+    }
+
+    /// Checks the seed against the commitment and draws the winners again.
+    pub fn verify(&self) -> Result<(), String> {
+        let seed = from_hex(&self.seed).ok_or("the seed is not 64 hex digits")?;
+        if to_hex(&Sha256::digest(seed)) != self.commitment {
+            return Err("the seed does not match the commitment".to_string());
+        }
+        let winners = draw_winners(&self.participants, seed, self.winners.len());
+        if winners
+            .iter()
+            .map(|w| w.uid)
+            .ne(self.winners.iter().map(|w| w.uid))
+        {
+            return Err("the winners do not match the seed".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Medal, User};
+
+    fn danmu(uid: u64, text: &str, medal: Option<(u64, u64)>, guard_level: u64) -> Event {
+        Event::Danmu(Danmu {
+            user: User {
+                uid,
+                name: format!("user{}", uid),
+                medal: medal.map(|(room_id, level)| Medal {
+                    name: "粉丝".to_string(),
+                    level,
+                    guard_level,
+                    anchor_uid: 1,
+                    anchor_room_id: room_id,
+                }),
+                guard_level,
+                ..User::default()
+            },
+            text: text.to_string(),
+            mode: 1,
+            font_size: 25,
+            color: 0xffffff,
+            timestamp_ms: uid as i64,
+            emoticon: None,
+            reply_to: None,
+        })
+    }
+
+    fn rules(min_medal_level: Option<u64>, guards_only: bool) -> Rules {
+        Rules {
+            keyword: "抽奖".to_string(),
+            min_medal_level,
+            guards_only,
+        }
+    }
+
+    fn joined(raffle: &Raffle) -> Vec<u64> {
+        raffle.participants().iter().map(|p| p.uid).collect()
+    }
+
+    #[test]
+    fn add() {
+        let mut raffle = Raffle::new(rules(None, false), 1000, 1);
+        for (uid, text) in [
+            (2, "抽奖"),
+            (3, " 抽奖 "),
+            (2, "抽奖"),
+            (4, "抽奖!"),
+            (1, "抽奖"),
+        ] {
+            raffle.add(&danmu(uid, text, None, 0));
+        }
+        // no second entry, no other text, not the host
+        assert_eq!(joined(&raffle), [2, 3]);
+        assert_eq!(raffle.participants()[1].timestamp_ms, 3);
+    }
+
+    #[test]
+    fn add_with_medal_and_guard() {
+        let mut raffle = Raffle::new(rules(Some(5), false), 1000, 1);
+        raffle.add(&danmu(2, "抽奖", Some((1000, 5)), 0));
+        raffle.add(&danmu(3, "抽奖", Some((1000, 4)), 0));
+        raffle.add(&danmu(4, "抽奖", Some((2000, 20)), 0));
+        raffle.add(&danmu(5, "抽奖", None, 0));
+        assert_eq!(joined(&raffle), [2]);
+
+        let mut raffle = Raffle::new(rules(None, true), 1000, 1);
+        raffle.add(&danmu(2, "抽奖", Some((1000, 21)), 3));
+        raffle.add(&danmu(3, "抽奖", Some((1000, 30)), 0));
+        assert_eq!(joined(&raffle), [2]);
+    }
+
+    fn participants(n: u64) -> Vec<Participant> {
+        (1..=n)
+            .map(|uid| Participant {
+                uid,
+                name: format!("user{}", uid),
+                timestamp_ms: 0,
+            })
+            .collect()
+    }
+
+    fn uids(winners: &[Participant]) -> Vec<u64> {
+        winners.iter().map(|w| w.uid).collect()
+    }
+
+    #[test]
+    fn draw_is_deterministic() {
+        let seed = [7; 32];
+        let winners = uids(&draw_winners(&participants(50), seed, 3));
+        assert_eq!(winners.len(), 3);
+        assert_eq!(uids(&draw_winners(&participants(50), seed, 3)), winners);
+        // the order participants joined in does not matter
+        let mut reversed = participants(50);
+        reversed.reverse();
+        assert_eq!(uids(&draw_winners(&reversed, seed, 3)), winners);
+        // more winners extend the same draw
+        assert_eq!(
+            uids(&draw_winners(&participants(50), seed, 5))[..3],
+            winners
+        );
+        assert_ne!(uids(&draw_winners(&participants(50), [8; 32], 3)), winners);
+        // never more winners than participants, each once
+        let mut all = uids(&draw_winners(&participants(4), seed, 10));
+        all.sort();
+        assert_eq!(all, [1, 2, 3, 4]);
+        assert!(draw_winners(&[], seed, 3).is_empty());
+    }
+
+    #[test]
+    fn verify() {
+        let (seed, commitment) = new_seed();
+        let draw = Draw::new(1000, rules(None, false), (0, 60), seed, participants(20), 2);
+        assert_eq!(draw.commitment, commitment);
+        assert_eq!(draw.verify(), Ok(()));
+
+        let mut wrong_seed = draw.clone();
+        wrong_seed.seed = to_hex(&[0; 32]);
+        assert_eq!(
+            wrong_seed.verify(),
+            Err("the seed does not match the commitment".to_string())
+        );
+        let mut bad_seed = draw.clone();
+        bad_seed.seed.truncate(10);
+        assert_eq!(
+            bad_seed.verify(),
+            Err("the seed is not 64 hex digits".to_string())
+        );
+        let mut bad_commitment = draw.clone();
+        bad_commitment.commitment = to_hex(&Sha256::digest([0; 32]));
+        assert!(bad_commitment.verify().is_err());
+
+        let mut wrong_winners = draw.clone();
+        let loser = draw
+            .participants
+            .iter()
+            .find(|p| !uids(&draw.winners).contains(&p.uid))
+            .unwrap();
+        wrong_winners.winners[0] = loser.clone();
+        assert_eq!(
+            wrong_winners.verify(),
+            Err("the winners do not match the seed".to_string())
+        );
+        // nor may participants be dropped after the draw
+        let mut fewer = draw.clone();
+        fewer.participants.retain(|p| p.uid != draw.winners[1].uid);
+        assert!(fewer.verify().is_err());
+    }
+}
@@ -0,0 +1,609 @@
+use crate::event::Event;
+use chrono::{Local, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+pub type Error = Box<dyn std::error::Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+    Sqlite,
+    Xml,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Sqlite => "sqlite",
+            Format::Xml => "xml",
+        }
+    }
+}
+
+/// One recorded room event, a line of the JSONL format.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    /// local receive time in milliseconds
+    pub local_ts: i64,
+    /// server time in milliseconds, if the command carries one
+    pub server_ts: Option<i64>,
+    pub cmd: String,
+    pub event: Event,
+    pub raw: Value,
+}
+
+impl Record {
+    pub fn new(raw: Value) -> Record {
+        let event = Event::parse(raw.clone());
+        Record {
+            local_ts: Utc::now().timestamp_millis(),
+            server_ts: event.timestamp_ms(),
+            cmd: raw["cmd"].as_str().unwrap_or_default().to_string(),
+            event,
+            raw,
+        }
+    }
+}
+
+/// Reads the records of a JSONL log written by [`Recorder`], the other
+/// formats cannot be read back.
+pub fn read_jsonl<P: AsRef<Path>>(fname: P) -> Result<Vec<Record>, Error> {
+    let path = fname.as_ref();
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let format = if data.starts_with(b"SQLite format 3\0") {
+        Some(Format::Sqlite)
+    } else if data.starts_with(b"local_ts,") {
+        Some(Format::Csv)
+    } else if data.starts_with(b"<?xml") {
+        Some(Format::Xml)
+    } else {
+        None
+    };
+    if let Some(format) = format {
+        Err(format!(
+            "{} is a {} recording, only jsonl ones can be read",
+            path.display(),
+            format.extension()
+        ))?;
+    }
+    let mut records = vec![];
+    for (idx, line) in data.lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            let record = serde_json::from_str(&line).map_err(|e| {
+                format!(
+                    "{}:{}: not a jsonl recording: {}",
+                    path.display(),
+                    idx + 1,
+                    e
+                )
+            })?;
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+trait Sink: Send {
+    fn write(&mut self, record: &Record) -> Result<(), Error>;
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+struct JsonlSink(BufWriter<File>);
+
+impl Sink for JsonlSink {
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.0, record)?;
+        self.0.write_all(b"\n")?;
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct CsvSink(csv::Writer<File>);
+
+impl CsvSink {
+    fn new(file: File) -> Result<CsvSink, Error> {
+        let mut writer = csv::Writer::from_writer(file);
+        writer.write_record([
+            "local_ts",
+            "server_ts",
+            "cmd",
+            "type",
+            "uid",
+            "name",
+            "text",
+            "raw",
+        ])?;
+        Ok(CsvSink(writer))
+    }
+}
+
+impl Sink for CsvSink {
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let event = &record.event;
+        self.0.write_record([
+            record.local_ts.to_string().as_str(),
+            &record
+                .server_ts
+                .map(|ts| ts.to_string())
+                .unwrap_or_default(),
+            &record.cmd,
+            event.name(),
+            &event.uid().map(|uid| uid.to_string()).unwrap_or_default(),
+            event.user_name().unwrap_or_default(),
+            event.text().unwrap_or_default(),
+            &record.raw.to_string(),
+        ])?;
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+struct SqliteSink(rusqlite::Connection);
+
+impl SqliteSink {
+    fn new(path: &Path) -> Result<SqliteSink, Error> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY,
+                local_ts INTEGER NOT NULL,
+                server_ts INTEGER,
+                cmd TEXT NOT NULL,
+                type TEXT NOT NULL,
+                uid INTEGER,
+                name TEXT,
+                text TEXT,
+                event TEXT NOT NULL,
+                raw TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS events_local_ts ON events (local_ts);",
+        )?;
+        Ok(SqliteSink(conn))
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let event = &record.event;
+        self.0.execute(
+            "INSERT INTO events (local_ts, server_ts, cmd, type, uid, name, text, event, raw)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                record.local_ts,
+                record.server_ts,
+                record.cmd,
+                event.name(),
+                event.uid().map(|uid| uid as i64),
+                event.user_name(),
+                event.text(),
+                serde_json::to_string(event)?,
+                record.raw.to_string(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Error> {
+        self.0.close().map_err(|(_, e)| e)?;
+        Ok(())
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The danmaku XML format of the bilibili player, with `<sc>`, `<gift>`
+/// and `<guard>` elements as written by common live recorders.
+struct XmlSink {
+    writer: BufWriter<File>,
+    start_ts: i64,
+}
+
+impl XmlSink {
+    fn new(file: File, start_ts: i64) -> Result<XmlSink, Error> {
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, "<i>")?;
+        writeln!(writer, "<chatserver>chat.bilibili.com</chatserver>")?;
+        writeln!(writer, "<chatid>0</chatid>")?;
+        writeln!(writer, "<mission>0</mission>")?;
+        writeln!(writer, "<maxlimit>1000</maxlimit>")?;
+        writeln!(writer, "<state>0</state>")?;
+        writeln!(writer, "<real_name>0</real_name>")?;
+        writeln!(writer, "<source>k-v</source>")?;
+        writer.flush()?;
+        Ok(XmlSink { writer, start_ts })
+    }
+}
+
+impl Sink for XmlSink {
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let ts = record.local_ts;
+        let offset = (ts - self.start_ts).max(0) as f64 / 1000.0;
+        let w = &mut self.writer;
+        match &record.event {
+            Event::Danmu(danmu) => {
+                let mut crc = flate2::Crc::new();
+                crc.update(danmu.user.uid.to_string().as_bytes());
+                writeln!(
+                    w,
+                    r#"<d p="{:.3},{},{},{},{},0,{:x},{}" user="{}" uid="{}">{}</d>"#,
+                    offset,
+                    danmu.mode,
+                    danmu.font_size,
+                    danmu.color,
+                    record.server_ts.unwrap_or(ts) / 1000,
+                    crc.sum(),
+                    ts,
+                    xml_escape(&danmu.user.name),
+                    danmu.user.uid,
+                    xml_escape(&danmu.text),
+                )?;
+            }
+            Event::SuperChat(sc) => writeln!(
+                w,
+                r#"<sc ts="{:.3}" user="{}" uid="{}" price="{}" time="{}">{}</sc>"#,
+                offset,
+                xml_escape(&sc.user.name),
+                sc.user.uid,
+                sc.price,
+                sc.end_time - sc.start_time,
+                xml_escape(&sc.message),
+            )?,
+            Event::Gift(gift) => writeln!(
+                w,
+                r#"<gift ts="{:.3}" user="{}" uid="{}" giftname="{}" giftcount="{}" />"#,
+                offset,
+                xml_escape(&gift.user.name),
+                gift.user.uid,
+                xml_escape(&gift.gift_name),
+                gift.num,
+            )?,
+            Event::GuardBuy(guard) => writeln!(
+                w,
+                r#"<guard ts="{:.3}" user="{}" uid="{}" level="{}" count="{}" />"#,
+                offset,
+                xml_escape(&guard.name),
+                guard.uid,
+                guard.guard_level,
+                guard.num,
+            )?,
+            _ => return Ok(()),
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        writeln!(self.writer, "</i>")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes room events to files named after `out`, one file per live
+/// session: `PREPARING` ends a file and the next event starts another.
+/// `LIVE` comes more than once at the start of a stream, so it never
+/// rotates an open file.
+pub struct Recorder {
+    format: Format,
+    out: PathBuf,
+    sink: Option<Box<dyn Sink>>,
+}
+
+impl Recorder {
+    pub fn new(format: Format, out: PathBuf) -> Recorder {
+        Recorder {
+            format,
+            out,
+            sink: None,
+        }
+    }
+
+    /// `out` with the local time appended to the file stem,
+    /// e.g. `danmaku-20240720-193000.xml`, and a counter when a file of the
+    /// same second exists.
+    fn session_path(&self) -> PathBuf {
+        let stem = self
+            .out
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "danmaku".to_string());
+        let extension = self
+            .out
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.format.extension().to_string());
+        let stem = format!("{}-{}", stem, Local::now().format("%Y%m%d-%H%M%S"));
+        let mut path = self.out.with_file_name(format!("{}.{}", stem, extension));
+        let mut n = 1;
+        while path.exists() {
+            path = self
+                .out
+                .with_file_name(format!("{}-{}.{}", stem, n, extension));
+            n += 1;
+        }
+        path
+    }
+
+    fn open(&self, start_ts: i64) -> Result<(PathBuf, Box<dyn Sink>), Error> {
+        let path = self.session_path();
+        let sink: Box<dyn Sink> = match self.format {
+            Format::Jsonl => Box::new(JsonlSink(BufWriter::new(File::create(&path)?))),
+            Format::Csv => Box::new(CsvSink::new(File::create(&path)?)?),
+            Format::Sqlite => Box::new(SqliteSink::new(&path)?),
+            Format::Xml => Box::new(XmlSink::new(File::create(&path)?, start_ts)?),
+        };
+        Ok((path, sink))
+    }
+
+    /// Records `record`, returns the path of a newly started file.
+    pub fn record(&mut self, record: &Record) -> Result<Option<PathBuf>, Error> {
+        let mut opened = None;
+        if self.sink.is_none() {
+            let (path, sink) = self.open(record.local_ts)?;
+            self.sink = Some(sink);
+            opened = Some(path);
+        }
+        self.sink.as_mut().unwrap().write(record)?;
+        if matches!(record.event, Event::Preparing) {
+            self.finish()?;
+        }
+        Ok(opened)
+    }
+
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match self.sink.take() {
+            Some(sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A record of `tests/fixtures/events/<name>.json` received at `local_ts`.
+    fn fixture(name: &str, local_ts: i64) -> Record {
+        let path = format!(
+            "{}/tests/fixtures/events/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let raw = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        Record {
+            local_ts,
+            ..Record::new(raw)
+        }
+    }
+
+    fn records() -> Vec<Record> {
+        let mut danmu = fixture("DANMU_MSG", 1700000001500);
+        if let Event::Danmu(danmu) = &mut danmu.event {
+            danmu.text = "<b>&\u{1}".to_string();
+        }
+        vec![
+            fixture("LIVE", 1700000000000),
+            danmu,
+            fixture("SEND_GIFT", 1700000002000),
+            fixture("SUPER_CHAT_MESSAGE", 1700000003000),
+            fixture("GUARD_BUY", 1700000004250),
+            fixture("INTERACT_WORD", 1700000005000),
+        ]
+    }
+
+    /// Records `records()` as one session, returning the file.
+    fn record_all(format: Format, dir: &Path) -> PathBuf {
+        let mut recorder = Recorder::new(format, dir.join("danmaku"));
+        let mut path = None;
+        for record in records() {
+            path = path.or(recorder.record(&record).unwrap());
+        }
+        recorder.finish().unwrap();
+        path.unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bili-live-record-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn xml() {
+        let dir = temp_dir("xml");
+        let path = record_all(Format::Xml, &dir);
+        assert_eq!(path.extension().unwrap(), "xml");
+        let xml = std::fs::read_to_string(&path).unwrap();
+        let lines = xml.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        assert_eq!(lines[1], "<i>");
+        assert_eq!(
+            lines[9..],
+            [
+                // the uid hash is the crc32 of "12345"
+                r#"<d p="1.500,1,25,16777215,1700000000,0,cbf53a1c,1700000001500" user="观众A" uid="12345">&lt;b&gt;&amp;</d>"#,
+                r#"<gift ts="2.000" user="观众B" uid="23456" giftname="小花花" giftcount="5" />"#,
+                r#"<sc ts="3.000" user="观众C" uid="34567" price="30" time="60">主播加油</sc>"#,
+                r#"<guard ts="4.250" user="观众D" uid="45678" level="3" count="1" />"#,
+                "</i>",
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn csv() {
+        let dir = temp_dir("csv");
+        let path = record_all(Format::Csv, &dir);
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "local_ts",
+                "server_ts",
+                "cmd",
+                "type",
+                "uid",
+                "name",
+                "text",
+                "raw"
+            ]
+        );
+        let rows = reader.records().map(|row| row.unwrap()).collect::<Vec<_>>();
+        assert_eq!(rows.len(), records().len());
+        assert_eq!(
+            rows[1].iter().take(7).collect::<Vec<_>>(),
+            [
+                "1700000001500",
+                "1700000000123",
+                "DANMU_MSG:4:0:2:2:2:0",
+                "danmu",
+                "12345",
+                "观众A",
+                "<b>&\u{1}",
+            ]
+        );
+        assert_eq!(&rows[0][3], "live");
+        assert_eq!(&rows[0][4], "");
+        let raw: Value = serde_json::from_str(&rows[2][7]).unwrap();
+        assert_eq!(raw["cmd"], "SEND_GIFT");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite() {
+        let dir = temp_dir("sqlite");
+        let path = record_all(Format::Sqlite, &dir);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT local_ts, type, uid, name, text, event FROM events ORDER BY id")
+            .unwrap();
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), records().len());
+        let (local_ts, kind, uid, name, text, _) = &rows[3];
+        assert_eq!(
+            (
+                *local_ts,
+                kind.as_str(),
+                *uid,
+                name.as_deref(),
+                text.as_deref()
+            ),
+            (
+                1700000003000,
+                "super_chat",
+                Some(34567),
+                Some("观众C"),
+                Some("主播加油")
+            )
+        );
+        let event: Event = serde_json::from_str(&rows[4].5).unwrap();
+        assert_eq!(event, records()[4].event);
+        assert_eq!((rows[0].2, rows[0].3.clone()), (None, None));
+        drop(statement);
+        conn.close().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_back() {
+        let dir = temp_dir("read");
+        let path = record_all(Format::Jsonl, &dir);
+        let read = read_jsonl(&path).unwrap();
+        let events = read.into_iter().map(|record| record.event);
+        assert!(events.eq(records().into_iter().map(|record| record.event)));
+
+        for format in [Format::Csv, Format::Sqlite, Format::Xml] {
+            let path = record_all(format, &dir);
+            let error = read_jsonl(&path).unwrap_err().to_string();
+            assert!(
+                error.ends_with("recording, only jsonl ones can be read"),
+                "{}",
+                error
+            );
+        }
+        let text = dir.join("notes.txt");
+        std::fs::write(&text, "{\"cmd\": \"LIVE\"}\n").unwrap();
+        let error = read_jsonl(&text).unwrap_err().to_string();
+        assert!(
+            error.contains("notes.txt:1: not a jsonl recording"),
+            "{}",
+            error
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sessions() {
+        let dir = std::env::temp_dir().join(format!("bili-live-record-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut recorder = Recorder::new(Format::Jsonl, dir.join("danmaku"));
+        let live = json!({"cmd": "LIVE", "live_time": 1700000000});
+        let first = recorder.record(&Record::new(live.clone())).unwrap();
+        // a repeated LIVE keeps writing to the open file
+        assert_eq!(recorder.record(&Record::new(live.clone())).unwrap(), None);
+        recorder
+            .record(&Record::new(json!({"cmd": "PREPARING"})))
+            .unwrap();
+        let second = recorder.record(&Record::new(live)).unwrap();
+        recorder.finish().unwrap();
+
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_ne!(first, second);
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!((lines(&first), lines(&second)), (3, 1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
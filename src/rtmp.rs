@@ -0,0 +1,902 @@
+use serde_json::{Map, Value, json};
+use std::{collections::HashMap, path::Path};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{Duration, Instant, sleep_until},
+};
+
+pub type Error = Box<dyn std::error::Error>;
+
+pub const DEFAULT_PORT: u16 = 1935;
+const HANDSHAKE_SIZE: usize = 1536;
+/// chunk size we send with, the protocol default is 128
+const OUT_CHUNK_SIZE: usize = 4096;
+
+const MSG_SET_CHUNK_SIZE: u8 = 1;
+const MSG_ACK: u8 = 3;
+const MSG_WINDOW_ACK_SIZE: u8 = 5;
+const MSG_AUDIO: u8 = 8;
+const MSG_VIDEO: u8 = 9;
+const MSG_DATA_AMF0: u8 = 18;
+const MSG_COMMAND_AMF0: u8 = 20;
+
+const CSID_COMMAND: u32 = 3;
+const CSID_AUDIO: u32 = 4;
+const CSID_DATA: u32 = 5;
+const CSID_VIDEO: u32 = 6;
+
+/// Where to publish, split out of an address like
+/// `rtmp://live-push.bilivideo.com/live-bvc/`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    pub app: String,
+    pub tc_url: String,
+}
+
+impl Target {
+    pub fn parse(addr: &str) -> Result<Target, Error> {
+        let rest = addr
+            .strip_prefix("rtmp://")
+            .ok_or_else(|| format!("not an rtmp:// address: {}", addr))?;
+        let (authority, app) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            Err(format!("no host in {}", addr))?;
+        }
+        let app = app.trim_matches('/').to_string();
+        Ok(Target {
+            host: host.to_string(),
+            port,
+            tc_url: format!("rtmp://{}:{}/{}", host, port, app),
+            app,
+        })
+    }
+}
+
+/// Writes AMF0 values, objects are written from JSON objects.
+pub fn amf0_encode(value: &Value, out: &mut Vec<u8>) {
+    fn string(text: &str, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(text.len() as u16).to_be_bytes());
+        out.extend_from_slice(text.as_bytes());
+    }
+    match value {
+        Value::Number(n) => {
+            out.push(0x00);
+            out.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+        }
+        Value::Bool(b) => out.extend_from_slice(&[0x01, *b as u8]),
+        Value::String(s) if s.len() > u16::MAX as usize => {
+            out.push(0x0c);
+            out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::String(s) => {
+            out.push(0x02);
+            string(s, out);
+        }
+        Value::Object(map) => {
+            out.push(0x03);
+            for (k, v) in map {
+                string(k, out);
+                amf0_encode(v, out);
+            }
+            out.extend_from_slice(&[0x00, 0x00, 0x09]);
+        }
+        Value::Array(items) => {
+            out.push(0x0a);
+            out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+            for item in items {
+                amf0_encode(item, out);
+            }
+        }
+        Value::Null => out.push(0x05),
+    }
+}
+
+/// Reads AMF0 values until `data` ends, unsupported types end the list.
+pub fn amf0_decode(mut data: &[u8]) -> Vec<Value> {
+    fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = (data.get(..n)?, data.get(n..)?);
+        *data = rest;
+        Some(head)
+    }
+    fn string(data: &mut &[u8]) -> Option<String> {
+        let len = u16::from_be_bytes(take(data, 2)?.try_into().ok()?) as usize;
+        Some(String::from_utf8_lossy(take(data, len)?).into_owned())
+    }
+    fn properties(data: &mut &[u8]) -> Option<Value> {
+        let mut map = Map::new();
+        loop {
+            let key = string(data)?;
+            if key.is_empty() && data.first() == Some(&0x09) {
+                take(data, 1)?;
+                return Some(Value::Object(map));
+            }
+            map.insert(key, value(data)?);
+        }
+    }
+    fn value(data: &mut &[u8]) -> Option<Value> {
+        Some(match take(data, 1)?[0] {
+            0x00 => json!(f64::from_be_bytes(take(data, 8)?.try_into().ok()?)),
+            0x01 => json!(take(data, 1)?[0] != 0),
+            0x02 => json!(string(data)?),
+            0x03 => properties(data)?,
+            0x05 | 0x06 => Value::Null,
+            0x08 => {
+                take(data, 4)?;
+                properties(data)?
+            }
+            0x0a => {
+                let len = u32::from_be_bytes(take(data, 4)?.try_into().ok()?);
+                (0..len).map(|_| value(data)).collect::<Option<_>>()?
+            }
+            0x0c => {
+                let len = u32::from_be_bytes(take(data, 4)?.try_into().ok()?) as usize;
+                json!(String::from_utf8_lossy(take(data, len)?))
+            }
+            _ => return None,
+        })
+    }
+    let mut values = vec![];
+    while !data.is_empty() {
+        match value(&mut data) {
+            Some(v) => values.push(v),
+            None => break,
+        }
+    }
+    values
+}
+
+pub fn amf0_values(values: &[Value]) -> Vec<u8> {
+    let mut out = vec![];
+    for value in values {
+        amf0_encode(value, &mut out);
+    }
+    out
+}
+
+/// A complete message read from the chunk stream.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub type_id: u8,
+    pub stream_id: u32,
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Default, Clone)]
+struct ChunkState {
+    timestamp: u32,
+    delta: u32,
+    length: usize,
+    type_id: u8,
+    stream_id: u32,
+    extended: bool,
+    payload: Vec<u8>,
+}
+
+/// Reassembles messages from incoming chunks.
+pub struct ChunkReader<R> {
+    reader: R,
+    chunk_size: usize,
+    states: HashMap<u32, ChunkState>,
+    /// bytes read, for acknowledgements
+    pub received: u64,
+}
+
+impl<R: AsyncRead + Unpin> ChunkReader<R> {
+    pub fn new(reader: R) -> ChunkReader<R> {
+        ChunkReader {
+            reader,
+            chunk_size: 128,
+            states: HashMap::new(),
+            received: 0,
+        }
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.reader.read_exact(buf).await?;
+        self.received += buf.len() as u64;
+        Ok(())
+    }
+
+    async fn read_uint(&mut self, n: usize) -> std::io::Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf[4 - n..]).await?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Reads chunks until a message is complete. Set chunk size messages are
+    /// applied here as well as returned.
+    pub async fn read_message(&mut self) -> Result<Message, Error> {
+        loop {
+            let first = self.read_uint(1).await? as u8;
+            let fmt = first >> 6;
+            let csid = match first & 0x3f {
+                0 => self.read_uint(1).await? + 64,
+                1 => {
+                    let mut buf = [0; 2];
+                    self.read_exact(&mut buf).await?;
+                    buf[0] as u32 + buf[1] as u32 * 256 + 64
+                }
+                csid => csid as u32,
+            };
+            let mut state = self.states.remove(&csid).unwrap_or_default();
+            if fmt <= 2 {
+                let mut timestamp = self.read_uint(3).await?;
+                if fmt <= 1 {
+                    state.length = self.read_uint(3).await? as usize;
+                    state.type_id = self.read_uint(1).await? as u8;
+                }
+                if fmt == 0 {
+                    let mut buf = [0; 4];
+                    self.read_exact(&mut buf).await?;
+                    state.stream_id = u32::from_le_bytes(buf);
+                }
+                state.extended = timestamp == 0xffffff;
+                if state.extended {
+                    timestamp = self.read_uint(4).await?;
+                }
+                if fmt == 0 {
+                    state.timestamp = timestamp;
+                    state.delta = 0;
+                } else {
+                    state.delta = timestamp;
+                }
+            } else if state.extended {
+                self.read_uint(4).await?;
+            }
+            if state.payload.is_empty() && fmt != 0 {
+                state.timestamp = state.timestamp.wrapping_add(state.delta);
+            }
+            let n = (state.length - state.payload.len()).min(self.chunk_size);
+            let mut chunk = vec![0; n];
+            self.read_exact(&mut chunk).await?;
+            state.payload.extend_from_slice(&chunk);
+            if state.payload.len() < state.length {
+                self.states.insert(csid, state);
+                continue;
+            }
+            let message = Message {
+                type_id: state.type_id,
+                stream_id: state.stream_id,
+                timestamp: state.timestamp,
+                payload: std::mem::take(&mut state.payload),
+            };
+            self.states.insert(csid, state);
+            if message.type_id == MSG_SET_CHUNK_SIZE && message.payload.len() >= 4 {
+                let size = u32::from_be_bytes(message.payload[..4].try_into()?) & 0x7fff_ffff;
+                if size == 0 {
+                    Err("invalid chunk size 0")?;
+                }
+                self.chunk_size = size as usize;
+            }
+            return Ok(message);
+        }
+    }
+}
+
+/// Splits a message into chunks, a type 0 header then type 3 ones.
+pub fn encode_chunks(csid: u32, message: &Message, chunk_size: usize) -> Vec<u8> {
+    let extended = message.timestamp >= 0xffffff;
+    let basic = |fmt: u8, out: &mut Vec<u8>| match csid {
+        2..=63 => out.push(fmt << 6 | csid as u8),
+        64..=319 => out.extend_from_slice(&[fmt << 6, (csid - 64) as u8]),
+        _ => {
+            let id = csid - 64;
+            out.extend_from_slice(&[fmt << 6 | 1, id as u8, (id >> 8) as u8]);
+        }
+    };
+    let mut out = Vec::with_capacity(message.payload.len() + 16);
+    basic(0, &mut out);
+    let timestamp = message.timestamp.min(0xffffff);
+    out.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+    out.extend_from_slice(&(message.payload.len() as u32).to_be_bytes()[1..]);
+    out.push(message.type_id);
+    out.extend_from_slice(&message.stream_id.to_le_bytes());
+    if extended {
+        out.extend_from_slice(&message.timestamp.to_be_bytes());
+    }
+    for (i, chunk) in message.payload.chunks(chunk_size).enumerate() {
+        if i > 0 {
+            basic(3, &mut out);
+            if extended {
+                out.extend_from_slice(&message.timestamp.to_be_bytes());
+            }
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// Client side of the simple handshake: C0 and C1, S0 to S2, then C2
+/// echoing S1.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<(), Error> {
+    let mut c0c1 = vec![0x03];
+    let mut c1 = [0u8; HANDSHAKE_SIZE];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut c1[8..]);
+    c0c1.extend_from_slice(&c1);
+    stream.write_all(&c0c1).await?;
+    let mut s0 = [0; 1];
+    stream.read_exact(&mut s0).await?;
+    if s0[0] != 0x03 {
+        Err(format!("unsupported RTMP version {}", s0[0]))?;
+    }
+    let mut s1 = vec![0; HANDSHAKE_SIZE];
+    stream.read_exact(&mut s1).await?;
+    let mut s2 = vec![0; HANDSHAKE_SIZE];
+    stream.read_exact(&mut s2).await?;
+    stream.write_all(&s1).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// An RTMP connection publishing one stream.
+pub struct Publisher<S> {
+    reader: ChunkReader<tokio::io::ReadHalf<S>>,
+    writer: tokio::io::WriteHalf<S>,
+    next_transaction: f64,
+    stream_id: u32,
+    window: u64,
+    acked: u64,
+}
+
+impl Publisher<TcpStream> {
+    /// Connects, handshakes and publishes `key` on `addr` as returned by
+    /// `start_live`.
+    pub async fn connect(addr: &str, key: &str) -> Result<Publisher<TcpStream>, Error> {
+        let target = Target::parse(addr)?;
+        let stream = TcpStream::connect((target.host.as_str(), target.port)).await?;
+        stream.set_nodelay(true)?;
+        Publisher::publish(stream, &target, key).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Publisher<S> {
+    /// Runs the handshake, `connect`, `createStream` and `publish` on an
+    /// open connection.
+    pub async fn publish(mut stream: S, target: &Target, key: &str) -> Result<Publisher<S>, Error> {
+        handshake(&mut stream).await?;
+        let (reader, writer) = tokio::io::split(stream);
+        let mut publisher = Publisher {
+            reader: ChunkReader::new(reader),
+            writer,
+            next_transaction: 1.0,
+            stream_id: 0,
+            window: 2_500_000,
+            acked: 0,
+        };
+        publisher
+            .send(
+                2,
+                MSG_SET_CHUNK_SIZE,
+                0,
+                0,
+                (OUT_CHUNK_SIZE as u32).to_be_bytes().to_vec(),
+            )
+            .await?;
+        publisher
+            .call(
+                "connect",
+                json!({
+                    "app": target.app,
+                    "type": "nonprivate",
+                    "flashVer": "FMLE/3.0 (compatible; FMSc/1.0)",
+                    "tcUrl": target.tc_url,
+                }),
+                &[],
+                true,
+            )
+            .await?;
+        for name in ["releaseStream", "FCPublish"] {
+            publisher
+                .call(name, Value::Null, &[json!(key)], false)
+                .await?;
+        }
+        let result = publisher
+            .call("createStream", Value::Null, &[], true)
+            .await?;
+        publisher.stream_id = result
+            .get(3)
+            .and_then(Value::as_f64)
+            .ok_or("createStream without a stream id")? as u32;
+
+        let payload = amf0_values(&[
+            json!("publish"),
+            json!(0),
+            Value::Null,
+            json!(key),
+            json!("live"),
+        ]);
+        publisher
+            .send(
+                CSID_COMMAND,
+                MSG_COMMAND_AMF0,
+                publisher.stream_id,
+                0,
+                payload,
+            )
+            .await?;
+        loop {
+            let values = publisher.read_command().await?;
+            if values.first().and_then(Value::as_str) != Some("onStatus") {
+                continue;
+            }
+            let info = values.get(3).cloned().unwrap_or_default();
+            match info["code"].as_str() {
+                Some("NetStream.Publish.Start") => return Ok(publisher),
+                _ if info["level"] == "error" || info["level"] == "warning" => Err(format!(
+                    "publish refused: {} {}",
+                    info["code"].as_str().unwrap_or_default(),
+                    info["description"].as_str().unwrap_or_default()
+                ))?,
+                _ => {}
+            }
+        }
+    }
+
+    async fn send(
+        &mut self,
+        csid: u32,
+        type_id: u8,
+        stream_id: u32,
+        timestamp: u32,
+        payload: Vec<u8>,
+    ) -> Result<(), Error> {
+        let chunk_size = if type_id == MSG_SET_CHUNK_SIZE {
+            128
+        } else {
+            OUT_CHUNK_SIZE
+        };
+        let message = Message {
+            type_id,
+            stream_id,
+            timestamp,
+            payload,
+        };
+        self.writer
+            .write_all(&encode_chunks(csid, &message, chunk_size))
+            .await?;
+        Ok(())
+    }
+
+    /// Reads messages until a command, handling protocol control ones.
+    async fn read_command(&mut self) -> Result<Vec<Value>, Error> {
+        loop {
+            let message = self.reader.read_message().await?;
+            match message.type_id {
+                MSG_WINDOW_ACK_SIZE if message.payload.len() >= 4 => {
+                    self.window = u32::from_be_bytes(message.payload[..4].try_into()?) as u64;
+                }
+                MSG_COMMAND_AMF0 => return Ok(amf0_decode(&message.payload)),
+                _ => {}
+            }
+            if self.reader.received - self.acked >= self.window {
+                self.acked = self.reader.received;
+                let received = (self.reader.received as u32).to_be_bytes().to_vec();
+                self.send(2, MSG_ACK, 0, 0, received).await?;
+            }
+        }
+    }
+
+    /// Calls a remote method, waiting for its `_result` when `wait`.
+    async fn call(
+        &mut self,
+        name: &str,
+        object: Value,
+        args: &[Value],
+        wait: bool,
+    ) -> Result<Vec<Value>, Error> {
+        let transaction = self.next_transaction;
+        self.next_transaction += 1.0;
+        let mut values = vec![json!(name), json!(transaction), object];
+        values.extend_from_slice(args);
+        self.send(CSID_COMMAND, MSG_COMMAND_AMF0, 0, 0, amf0_values(&values))
+            .await?;
+        if !wait {
+            return Ok(vec![]);
+        }
+        loop {
+            let values = self.read_command().await?;
+            if values.get(1).and_then(Value::as_f64) != Some(transaction) {
+                continue;
+            }
+            return match values.first().and_then(Value::as_str) {
+                Some("_result") => Ok(values),
+                _ => {
+                    let info = values.get(3).cloned().unwrap_or_default();
+                    Err(format!(
+                        "{} failed: {} {}",
+                        name,
+                        info["code"].as_str().unwrap_or_default(),
+                        info["description"].as_str().unwrap_or_default()
+                    )
+                    .into())
+                }
+            };
+        }
+    }
+
+    /// Sends an FLV tag body, script data gets the `@setDataFrame` prefix
+    /// servers expect.
+    pub async fn send_tag(&mut self, tag: &FlvTag) -> Result<(), Error> {
+        let (csid, payload) = match tag.tag_type {
+            MSG_AUDIO => (CSID_AUDIO, tag.data.clone()),
+            MSG_VIDEO => (CSID_VIDEO, tag.data.clone()),
+            MSG_DATA_AMF0 => {
+                let mut payload = amf0_values(&[json!("@setDataFrame")]);
+                payload.extend_from_slice(&tag.data);
+                (CSID_DATA, payload)
+            }
+            _ => return Ok(()),
+        };
+        self.send(csid, tag.tag_type, self.stream_id, tag.timestamp, payload)
+            .await
+    }
+
+    /// Ends the stream and closes the connection.
+    pub async fn close(mut self, key: &str) -> Result<(), Error> {
+        self.call("FCUnpublish", Value::Null, &[json!(key)], false)
+            .await?;
+        self.call("deleteStream", Value::Null, &[json!(self.stream_id)], false)
+            .await?;
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlvTag {
+    /// 8 audio, 9 video, 18 script data
+    pub tag_type: u8,
+    /// milliseconds
+    pub timestamp: u32,
+    pub data: Vec<u8>,
+}
+
+/// Reads the tags of an FLV file one at a time.
+pub struct FlvReader<R> {
+    reader: R,
+}
+
+impl FlvReader<BufReader<tokio::fs::File>> {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        FlvReader::new(BufReader::new(file)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> FlvReader<R> {
+    /// Checks the FLV header and skips to the first tag.
+    pub async fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0; 9];
+        reader.read_exact(&mut header).await?;
+        if &header[..3] != b"FLV" {
+            Err("not an FLV file")?;
+        }
+        let offset = u32::from_be_bytes(header[5..9].try_into()?) as usize;
+        // the rest of the header and the first previous tag size
+        let mut skip = vec![0; offset.saturating_sub(9) + 4];
+        reader.read_exact(&mut skip).await?;
+        Ok(FlvReader { reader })
+    }
+
+    pub async fn next_tag(&mut self) -> Result<Option<FlvTag>, Error> {
+        let mut header = [0; 11];
+        match self.reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => Err(e)?,
+        }
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+        let mut data = vec![0; size + 4];
+        self.reader.read_exact(&mut data).await?;
+        data.truncate(size);
+        Ok(Some(FlvTag {
+            tag_type: header[0] & 0x1f,
+            timestamp,
+            data,
+        }))
+    }
+}
+
+/// Pushes an FLV file at its own pace, returning the tags sent and the
+/// media duration in milliseconds.
+pub async fn stream_file<S, R>(
+    publisher: &mut Publisher<S>,
+    flv: &mut FlvReader<R>,
+) -> Result<(u64, u32), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    let start = Instant::now();
+    let mut first = None;
+    let (mut tags, mut last) = (0, 0);
+    while let Some(mut tag) = flv.next_tag().await? {
+        let first = *first.get_or_insert(tag.timestamp);
+        tag.timestamp = tag.timestamp.saturating_sub(first);
+        sleep_until(start + Duration::from_millis(tag.timestamp as u64)).await;
+        publisher.send_tag(&tag).await?;
+        tags += 1;
+        last = last.max(tag.timestamp);
+    }
+    Ok((tags, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn target_parse() {
+        assert_eq!(
+            Target::parse("rtmp://live-push.bilibili.com/live-bvc/").unwrap(),
+            Target {
+                host: "live-push.bilibili.com".to_string(),
+                port: 1935,
+                app: "live-bvc".to_string(),
+                tc_url: "rtmp://live-push.bilibili.com:1935/live-bvc".to_string(),
+            }
+        );
+        let target = Target::parse("rtmp://127.0.0.1:1936/live").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("127.0.0.1", 1936));
+        assert_eq!(target.tc_url, "rtmp://127.0.0.1:1936/live");
+        assert_eq!(Target::parse("rtmp://host").unwrap().app, "");
+        for addr in ["http://host/live", "rtmp:///live", "rtmp://host:port/live"] {
+            assert!(Target::parse(addr).is_err(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn amf0_round_trip() {
+        let values = [
+            json!(1.5),
+            json!(true),
+            json!("live"),
+            Value::Null,
+            json!({"app": "live-bvc", "fpad": false, "capabilities": 15.0}),
+            json!([1.0, "two", {"three": 3.0}]),
+            json!("x".repeat(70000)),
+        ];
+        assert_eq!(amf0_decode(&amf0_values(&values)), values);
+
+        let mut data = amf0_values(&[json!("@setDataFrame")]);
+        assert_eq!(data.len(), 16);
+        // an ECMA array as sent in onMetaData
+        data.extend_from_slice(&[0x08, 0, 0, 0, 1, 0, 5]);
+        data.extend_from_slice(b"width");
+        amf0_encode(&json!(1280.0), &mut data);
+        data.extend_from_slice(&[0, 0, 0x09]);
+        // an unsupported type ends the values
+        data.extend_from_slice(&[0x11, 0x05]);
+        assert_eq!(
+            amf0_decode(&data),
+            [json!("@setDataFrame"), json!({"width": 1280.0})]
+        );
+        // so does a truncated one
+        assert_eq!(amf0_decode(&[0x02, 0x00, 0x05, b'a']), Vec::<Value>::new());
+    }
+
+    #[tokio::test]
+    async fn chunk_round_trip() {
+        let messages = [
+            (3, 1, 0),
+            // extended timestamps
+            (4, 300, 0x0100_0000),
+            // two and three byte chunk stream ids
+            (100, 129, 20),
+            (400, 128, 40),
+        ];
+        let mut data = vec![];
+        for (csid, len, timestamp) in messages {
+            let message = Message {
+                type_id: MSG_VIDEO,
+                stream_id: 1,
+                timestamp,
+                payload: (0..len).map(|i| i as u8).collect(),
+            };
+            data.extend(encode_chunks(csid, &message, 128));
+        }
+        let mut reader = ChunkReader::new(&data[..]);
+        for (_, len, timestamp) in messages {
+            let message = reader.read_message().await.unwrap();
+            assert_eq!((message.stream_id, message.timestamp), (1, timestamp));
+            assert_eq!(
+                message.payload,
+                (0..len).map(|i| i as u8).collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(reader.received, data.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn chunk_size_zero() {
+        let message = Message {
+            type_id: MSG_SET_CHUNK_SIZE,
+            stream_id: 0,
+            timestamp: 0,
+            payload: vec![0; 4],
+        };
+        let data = encode_chunks(2, &message, 128);
+        let error = ChunkReader::new(&data[..]).read_message().await.err();
+        assert_eq!(error.unwrap().to_string(), "invalid chunk size 0");
+    }
+
+    /// An FLV file of `(tag type, timestamp, size)` tags, with `pattern` data.
+    fn flv(tags: &[(u8, u32, usize)]) -> Vec<u8> {
+        let mut out = b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00".to_vec();
+        for &(tag_type, timestamp, size) in tags {
+            out.push(tag_type);
+            out.extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+            out.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+            out.push((timestamp >> 24) as u8);
+            out.extend_from_slice(&[0, 0, 0]);
+            out.extend(pattern(size));
+            out.extend_from_slice(&(size as u32 + 11).to_be_bytes());
+        }
+        out
+    }
+
+    fn pattern(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Server side of one publishing connection, sending with a chunk size
+    /// of 60. Returns the messages it got after the handshake.
+    async fn stand_in(listener: TcpListener) -> Vec<Message> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut c0c1 = vec![0; 1 + HANDSHAKE_SIZE];
+        stream.read_exact(&mut c0c1).await.unwrap();
+        assert_eq!(c0c1[0], 0x03);
+        let s1 = pattern(HANDSHAKE_SIZE);
+        let mut s0s1s2 = vec![0x03];
+        s0s1s2.extend_from_slice(&s1);
+        s0s1s2.extend_from_slice(&c0c1[1..]);
+        stream.write_all(&s0s1s2).await.unwrap();
+        let mut c2 = vec![0; HANDSHAKE_SIZE];
+        stream.read_exact(&mut c2).await.unwrap();
+        assert_eq!(c2, s1);
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = ChunkReader::new(reader);
+        let control = |type_id, value: u32, chunk_size| {
+            let message = Message {
+                type_id,
+                stream_id: 0,
+                timestamp: 0,
+                payload: value.to_be_bytes().to_vec(),
+            };
+            encode_chunks(2, &message, chunk_size)
+        };
+        writer
+            .write_all(&control(MSG_WINDOW_ACK_SIZE, 2_500_000, 128))
+            .await
+            .unwrap();
+        writer
+            .write_all(&control(MSG_SET_CHUNK_SIZE, 60, 128))
+            .await
+            .unwrap();
+
+        let mut messages = vec![];
+        while let Ok(message) = reader.read_message().await {
+            if message.type_id == MSG_COMMAND_AMF0 {
+                let values = amf0_decode(&message.payload);
+                let transaction = values[1].clone();
+                let reply = match values[0].as_str().unwrap() {
+                    "connect" => Some(vec![
+                        json!("_result"),
+                        transaction,
+                        json!({"fmsVer": "FMS/3,0,1,123"}),
+                        json!({"level": "status", "code": "NetConnection.Connect.Success"}),
+                    ]),
+                    "createStream" => {
+                        Some(vec![json!("_result"), transaction, Value::Null, json!(1.0)])
+                    }
+                    "publish" => Some(vec![
+                        json!("onStatus"),
+                        json!(0.0),
+                        Value::Null,
+                        json!({"level": "status", "code": "NetStream.Publish.Start"}),
+                    ]),
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    let reply = Message {
+                        type_id: MSG_COMMAND_AMF0,
+                        stream_id: message.stream_id,
+                        timestamp: 0,
+                        payload: amf0_values(&reply),
+                    };
+                    writer
+                        .write_all(&encode_chunks(CSID_COMMAND, &reply, 60))
+                        .await
+                        .unwrap();
+                }
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn publish_with_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("rtmp://{}/live-bvc/", listener.local_addr().unwrap());
+        let tags = [
+            (MSG_DATA_AMF0, 0, 300),
+            (MSG_VIDEO, 0, OUT_CHUNK_SIZE * 2 + 100),
+            (MSG_AUDIO, 20, 50),
+            (MSG_VIDEO, 40, OUT_CHUNK_SIZE),
+        ];
+        // the errors of this module are not Send, so no spawning
+        let (messages, ()) = tokio::join!(stand_in(listener), async {
+            let mut publisher = Publisher::connect(&addr, "?streamname=live_1")
+                .await
+                .unwrap();
+            let data = flv(&tags);
+            let mut flv = FlvReader::new(&data[..]).await.unwrap();
+            let sent = stream_file(&mut publisher, &mut flv).await.unwrap();
+            assert_eq!(sent, (4, 40));
+            publisher.close("?streamname=live_1").await.unwrap();
+        });
+        assert_eq!(messages[0].type_id, MSG_SET_CHUNK_SIZE);
+        assert_eq!(messages[0].payload, 4096u32.to_be_bytes());
+        let commands = messages
+            .iter()
+            .filter(|message| message.type_id == MSG_COMMAND_AMF0)
+            .map(|message| amf0_decode(&message.payload))
+            .collect::<Vec<_>>();
+        let names = commands
+            .iter()
+            .map(|values| values[0].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "connect",
+                "releaseStream",
+                "FCPublish",
+                "createStream",
+                "publish",
+                "FCUnpublish",
+                "deleteStream",
+            ]
+        );
+        assert_eq!(commands[0][2]["app"], "live-bvc");
+        assert_eq!(commands[0][2]["tcUrl"], addr.trim_end_matches('/'));
+        assert_eq!(
+            commands[4][3..],
+            [json!("?streamname=live_1"), json!("live")]
+        );
+
+        let media = messages
+            .iter()
+            .filter(|message| [MSG_AUDIO, MSG_VIDEO, MSG_DATA_AMF0].contains(&message.type_id))
+            .collect::<Vec<_>>();
+        assert_eq!(media.len(), tags.len());
+        for (message, &(tag_type, timestamp, size)) in media.iter().zip(&tags) {
+            assert_eq!(
+                (message.type_id, message.stream_id, message.timestamp),
+                (tag_type, 1, timestamp)
+            );
+            let mut payload = &message.payload[..];
+            if tag_type == MSG_DATA_AMF0 {
+                let prefix = amf0_values(&[json!("@setDataFrame")]);
+                payload = payload.strip_prefix(&prefix[..]).unwrap();
+            }
+            assert_eq!(payload, pattern(size));
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    bot::{Action, EventHandler},
+    event::Event,
+};
+use rhai::{AST, CallFnOptions, Dynamic, Engine, Map, Scope};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+use tokio::time::{Duration, Instant};
+
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+const MAX_CALL_LEVELS: usize = 32;
+/// wall time a single callback may take
+const TIME_LIMIT: Duration = Duration::from_millis(200);
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+struct Script {
+    ast: AST,
+    scope: Scope<'static>,
+    /// bound to `this` in callbacks, kept between events
+    state: Dynamic,
+    modified: SystemTime,
+}
+
+/// Runs the `on_event(event)` function of every `*.rhai` script in a
+/// directory, reloading scripts when they change.
+///
+/// Scripts call `send(text)`, `mute(uid)`, `mute(uid, hours)`,
+/// `set_title(title)`, `start_live()` and `stop_live()`, and keep state in
+/// `this`, e.g. `this.count = (this.count ?? 0) + 1`.
+pub struct ScriptHost {
+    engine: Engine,
+    dir: PathBuf,
+    scripts: BTreeMap<PathBuf, Script>,
+    /// modification times of scripts whose last load failed
+    failed: BTreeMap<PathBuf, SystemTime>,
+    actions: Rc<RefCell<Vec<Action>>>,
+    deadline: Rc<Cell<Option<Instant>>>,
+    last_reload: Option<Instant>,
+}
+
+fn sandboxed_engine(
+    actions: &Rc<RefCell<Vec<Action>>>,
+    deadline: &Rc<Cell<Option<Instant>>>,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(64, 64)
+        .set_max_modules(0);
+    let deadline = deadline.clone();
+    engine.on_progress(move |_| match deadline.get() {
+        Some(deadline) if Instant::now() > deadline => Some("time limit exceeded".into()),
+        _ => None,
+    });
+
+    let push = |actions: &Rc<RefCell<Vec<Action>>>| {
+        let actions = actions.clone();
+        move |action| actions.borrow_mut().push(action)
+    };
+    let send = push(actions);
+    engine.register_fn("send", move |text: &str| {
+        send(Action::Send(text.to_string()))
+    });
+    let mute = push(actions);
+    engine.register_fn("mute", move |uid: i64| {
+        mute(Action::Mute {
+            uid: uid as u64,
+            hours: None,
+        })
+    });
+    let mute = push(actions);
+    engine.register_fn("mute", move |uid: i64, hours: i64| {
+        mute(Action::Mute {
+            uid: uid as u64,
+            hours: Some(hours.max(1) as u64),
+        })
+    });
+    let set_title = push(actions);
+    engine.register_fn("set_title", move |title: &str| {
+        set_title(Action::SetTitle(title.to_string()))
+    });
+    let start_live = push(actions);
+    engine.register_fn("start_live", move || start_live(Action::StartLive));
+    let stop_live = push(actions);
+    engine.register_fn("stop_live", move || stop_live(Action::StopLive));
+    engine
+}
+
+impl ScriptHost {
+    pub fn new(dir: PathBuf) -> ScriptHost {
+        let actions = Rc::new(RefCell::new(vec![]));
+        let deadline = Rc::new(Cell::new(None));
+        ScriptHost {
+            engine: sandboxed_engine(&actions, &deadline),
+            dir,
+            scripts: BTreeMap::new(),
+            failed: BTreeMap::new(),
+            actions,
+            deadline,
+            last_reload: None,
+        }
+    }
+
+    pub fn script_names(&self) -> Vec<String> {
+        self.scripts
+            .keys()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    fn load(&mut self, path: &Path, modified: SystemTime) -> Result<(), String> {
+        let ast = self
+            .engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        let queued = self.actions.borrow().len();
+        self.deadline.set(Some(Instant::now() + TIME_LIMIT));
+        let result = self.engine.run_ast_with_scope(&mut scope, &ast);
+        self.deadline.set(None);
+        if let Err(e) = result {
+            self.actions.borrow_mut().truncate(queued);
+            return Err(e.to_string());
+        }
+        self.scripts.insert(
+            path.to_path_buf(),
+            Script {
+                ast,
+                scope,
+                state: Map::new().into(),
+                modified,
+            },
+        );
+        Ok(())
+    }
+
+    /// Loads new and changed scripts and drops deleted ones. A script that
+    /// fails to compile keeps its previous version running.
+    pub fn reload(&mut self) -> std::io::Result<()> {
+        let mut found = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rhai") {
+                let modified = std::fs::metadata(&path)?.modified()?;
+                found.push((path, modified));
+            }
+        }
+        self.scripts
+            .retain(|path, _| found.iter().any(|(found, _)| found == path));
+        self.failed
+            .retain(|path, _| found.iter().any(|(found, _)| found == path));
+        for (path, modified) in found {
+            if self
+                .scripts
+                .get(&path)
+                .is_some_and(|script| script.modified == modified)
+                || self.failed.get(&path) == Some(&modified)
+            {
+                continue;
+            }
+            let reloading = self.scripts.contains_key(&path);
+            match self.load(&path, modified) {
+                Ok(()) if reloading => eprintln!("reloaded {}", path.display()),
+                Ok(()) => {}
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    // don't retry until the file changes again
+                    self.failed.insert(path.clone(), modified);
+                    continue;
+                }
+            }
+            self.failed.remove(&path);
+        }
+        Ok(())
+    }
+}
+
+impl EventHandler for ScriptHost {
+    fn on_event(&mut self, event: &Event, _now: Instant) -> Vec<Action> {
+        let event = match rhai::serde::to_dynamic(event) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("{}", e);
+                return vec![];
+            }
+        };
+        for (path, script) in &mut self.scripts {
+            if !script.ast.iter_functions().any(|f| f.name == "on_event") {
+                continue;
+            }
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut script.state);
+            let queued = self.actions.borrow().len();
+            self.deadline.set(Some(Instant::now() + TIME_LIMIT));
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut script.scope,
+                &script.ast,
+                "on_event",
+                (event.clone(),),
+            );
+            self.deadline.set(None);
+            if let Err(e) = result {
+                // half of what a failed callback wanted is worse than nothing
+                self.actions.borrow_mut().truncate(queued);
+                eprintln!("{}: {}", path.display(), e);
+            }
+        }
+        self.actions.take()
+    }
+
+    fn tick(&mut self, now: Instant) -> Vec<Action> {
+        if self
+            .last_reload
+            .is_none_or(|last| now.duration_since(last) >= RELOAD_INTERVAL)
+        {
+            self.last_reload = Some(now);
+            if let Err(e) = self.reload() {
+                eprintln!("{}: {}", self.dir.display(), e);
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures() {
+        let dir = std::env::temp_dir().join(format!("bili-live-scripts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.rhai"), "send(\"loaded\"); throw \"boom\";").unwrap();
+        std::fs::write(
+            dir.join("half.rhai"),
+            "fn on_event(event) { send(\"first\"); throw \"boom\"; }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("ok.rhai"), "fn on_event(event) { send(\"ok\"); }").unwrap();
+
+        let mut host = ScriptHost::new(dir.clone());
+        host.reload().unwrap();
+        assert_eq!(host.scripts.len(), 2);
+        let broken = dir.join("broken.rhai");
+        let modified = std::fs::metadata(&broken).unwrap().modified().unwrap();
+        assert_eq!(host.failed.get(&broken), Some(&modified));
+
+        let actions = host.on_event(&Event::Preparing, Instant::now());
+        assert_eq!(actions, [Action::Send("ok".to_string())]);
+
+        std::fs::remove_file(&broken).unwrap();
+        host.reload().unwrap();
+        assert!(host.failed.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
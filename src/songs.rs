@@ -0,0 +1,484 @@
+use crate::{
+    config::SongConfig,
+    event::{Event, User},
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Song {
+    pub name: String,
+    pub uid: u64,
+    pub user: String,
+    /// requested by a guard or with a super chat
+    pub priority: bool,
+    /// unix seconds
+    pub time: i64,
+}
+
+/// The playing song and the waiting ones, sent to the overlay pages as
+/// `{"type": "songs"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename = "songs")]
+pub struct Queue {
+    pub current: Option<Song>,
+    pub waiting: Vec<Song>,
+}
+
+/// Changes to the queue, positions count from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// play the next song
+    Skip,
+    Remove(usize),
+    Move {
+        from: usize,
+        to: usize,
+    },
+}
+
+/// What the TUI is told.
+#[derive(Debug, Clone)]
+pub enum Update {
+    Queue(Queue),
+    Notice(String),
+}
+
+/// Song requests from `!点歌 <name>`. Guards and super chats starting with
+/// `点歌` go before other requests, room admins and the streamer can
+/// `!切歌`, `!删歌 <n>`, `!顶歌 <n>` and `!移歌 <from> <to>`, viewers can
+/// `!取消点歌` their latest request. The queue is saved after every change.
+pub struct SongRequests {
+    config: SongConfig,
+    own_uid: u64,
+    room_id: u64,
+    path: PathBuf,
+    queue: Queue,
+}
+
+fn command_of(text: &str) -> Option<(&str, &str)> {
+    let command = text.trim().strip_prefix(['!', '！'])?;
+    let (name, args) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    Some((name, args.trim()))
+}
+
+impl SongRequests {
+    /// Loads the queue saved at `path`, starting empty if there is none.
+    pub fn load(
+        path: PathBuf,
+        config: SongConfig,
+        own_uid: u64,
+        room_id: u64,
+    ) -> Result<SongRequests, Box<dyn std::error::Error>> {
+        let queue = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Queue::default(),
+            Err(e) => Err(e)?,
+        };
+        Ok(SongRequests {
+            config,
+            own_uid,
+            room_id,
+            path,
+            queue,
+        })
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.queue)?)
+    }
+
+    fn is_guard(&self, user: &User) -> bool {
+        user.guard_level > 0
+            || user
+                .medal
+                .as_ref()
+                .is_some_and(|medal| medal.guard_level > 0 && medal.anchor_room_id == self.room_id)
+    }
+
+    fn request(&mut self, user: &User, name: &str, priority: bool) -> Result<String, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("{}: 点歌请带上歌名", user.name));
+        }
+        if name.chars().count() > self.config.max_name_len {
+            return Err(format!("{}: 歌名太长", user.name));
+        }
+        if self.queue.waiting.len() >= self.config.max_queue {
+            return Err(format!("{}: 歌单已满", user.name));
+        }
+        let same_name = |song: &Song| song.name.to_lowercase() == name.to_lowercase();
+        if self.queue.current.as_ref().is_some_and(same_name)
+            || self.queue.waiting.iter().any(same_name)
+        {
+            return Err(format!("{}: {} 已在歌单中", user.name, name));
+        }
+        let mine = self
+            .queue
+            .waiting
+            .iter()
+            .filter(|song| song.uid == user.uid)
+            .count();
+        if mine >= self.config.per_user_limit {
+            return Err(format!(
+                "{}: 每人最多点 {} 首",
+                user.name, self.config.per_user_limit
+            ));
+        }
+        let position = match priority {
+            true => self
+                .queue
+                .waiting
+                .iter()
+                .take_while(|song| song.priority)
+                .count(),
+            false => self.queue.waiting.len(),
+        };
+        self.queue.waiting.insert(
+            position,
+            Song {
+                name: name.to_string(),
+                uid: user.uid,
+                user: user.name.clone(),
+                priority,
+                time: chrono::Utc::now().timestamp(),
+            },
+        );
+        Ok(format!(
+            "{} 点歌 {} (第 {} 位)",
+            user.name,
+            name,
+            position + 1
+        ))
+    }
+
+    /// Carries out a moderator command, returning what happened.
+    pub fn apply(&mut self, command: Command) -> Result<String, String> {
+        let len = self.queue.waiting.len();
+        let check = |n: usize| match n {
+            1.. if n <= len => Ok(n - 1),
+            _ => Err(format!("没有第 {} 首", n)),
+        };
+        let message = match command {
+            Command::Skip => {
+                let skipped = self.queue.current.take();
+                self.queue.current = match self.queue.waiting.is_empty() {
+                    true => None,
+                    false => Some(self.queue.waiting.remove(0)),
+                };
+                match (&skipped, &self.queue.current) {
+                    (_, Some(next)) => format!("正在播放 {} ({})", next.name, next.user),
+                    (Some(_), None) => "歌单已播完".to_string(),
+                    (None, None) => return Err("歌单是空的".to_string()),
+                }
+            }
+            Command::Remove(n) => {
+                let song = self.queue.waiting.remove(check(n)?);
+                format!("已删除 {}", song.name)
+            }
+            Command::Move { from, to } => {
+                let song = self.queue.waiting.remove(check(from)?);
+                let to = to.clamp(1, len) - 1;
+                let message = format!("{} 移到第 {} 位", song.name, to + 1);
+                self.queue.waiting.insert(to, song);
+                message
+            }
+        };
+        Ok(message)
+    }
+
+    /// Handles request and moderator danmaku, returning a notice when the
+    /// queue changed or a request was turned down.
+    pub fn on_event(&mut self, event: &Event) -> Option<Result<String, String>> {
+        let (user, text, super_chat) = match event {
+            Event::Danmu(danmu) => (&danmu.user, danmu.text.as_str(), false),
+            Event::SuperChat(sc) => (&sc.user, sc.message.as_str(), true),
+            _ => return None,
+        };
+        if super_chat {
+            let name = text.trim().trim_start_matches(['!', '！']);
+            let name = name.strip_prefix("点歌")?;
+            return Some(self.request(user, name, true));
+        }
+        let (command, args) = command_of(text)?;
+        let is_mod = user.uid == self.own_uid || user.is_admin;
+        let mut numbers = args.split_whitespace().map(|n| n.parse::<usize>());
+        let mut number = || match numbers.next() {
+            Some(Ok(n)) => Ok(n),
+            _ => Err(format!("{}: 请带上歌单序号", user.name)),
+        };
+        Some(match command {
+            "点歌" => {
+                let priority = self.is_guard(user);
+                self.request(user, args, priority)
+            }
+            "取消点歌" => match self
+                .queue
+                .waiting
+                .iter()
+                .rposition(|song| song.uid == user.uid)
+            {
+                Some(idx) => {
+                    let song = self.queue.waiting.remove(idx);
+                    Ok(format!("{} 取消了 {}", user.name, song.name))
+                }
+                None => Err(format!("{}: 没有等待中的点歌", user.name)),
+            },
+            "切歌" if is_mod => self.apply(Command::Skip),
+            "删歌" if is_mod => number().and_then(|n| self.apply(Command::Remove(n))),
+            "顶歌" if is_mod => {
+                number().and_then(|from| self.apply(Command::Move { from, to: 1 }))
+            }
+            "移歌" if is_mod => number()
+                .and_then(|from| Ok((from, number()?)))
+                .and_then(|(from, to)| self.apply(Command::Move { from, to })),
+            _ => return None,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = match &self.queue.current {
+            Some(song) => format!("正在播放: {} ({})\n", song.name, song.user),
+            None => "正在播放: -\n".to_string(),
+        };
+        for (i, song) in self.queue.waiting.iter().enumerate() {
+            text.push_str(&format!("{}. {} ({})\n", i + 1, song.name, song.user));
+        }
+        text
+    }
+
+    /// Writes the queue for a text source in OBS.
+    pub fn write<P: AsRef<Path>>(&self, fname: P) -> std::io::Result<()> {
+        std::fs::write(fname, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Danmu, SuperChat};
+
+    fn user(uid: u64) -> User {
+        User {
+            uid,
+            name: format!("user{}", uid),
+            ..User::default()
+        }
+    }
+
+    fn danmu(user: User, text: &str) -> Event {
+        Event::Danmu(Danmu {
+            user,
+            text: text.to_string(),
+            mode: 1,
+            font_size: 25,
+            color: 0xffffff,
+            timestamp_ms: 0,
+            emoticon: None,
+            reply_to: None,
+        })
+    }
+
+    fn super_chat(uid: u64, message: &str) -> Event {
+        Event::SuperChat(SuperChat {
+            id: 1,
+            user: user(uid),
+            message: message.to_string(),
+            price: 30,
+            start_time: 0,
+            end_time: 60,
+            background_color: String::new(),
+        })
+    }
+
+    fn guard(uid: u64) -> User {
+        User {
+            guard_level: 3,
+            ..user(uid)
+        }
+    }
+
+    fn requests(name: &str, config: SongConfig) -> SongRequests {
+        let path = std::env::temp_dir().join(format!(
+            "bili-live-songs-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        SongRequests::load(path, config, 1, 1000).unwrap()
+    }
+
+    fn names(requests: &SongRequests) -> Vec<&str> {
+        let waiting = requests.queue().waiting.iter();
+        waiting.map(|song| song.name.as_str()).collect()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command_of("!点歌 晴天"), Some(("点歌", "晴天")));
+        // the full-width space typed by Chinese IMEs
+        assert_eq!(command_of("！点歌\u{3000}晴天"), Some(("点歌", "晴天")));
+        assert_eq!(command_of(" !移歌 3  1 "), Some(("移歌", "3  1")));
+        assert_eq!(command_of("!切歌"), Some(("切歌", "")));
+        assert_eq!(command_of("点歌 晴天"), None);
+    }
+
+    #[test]
+    fn priority() {
+        let mut songs = requests("priority", SongConfig::default());
+        songs.on_event(&danmu(user(2), "!点歌 a"));
+        songs.on_event(&danmu(user(3), "!点歌 b"));
+        assert_eq!(
+            songs.on_event(&danmu(guard(4), "!点歌 c")),
+            Some(Ok("user4 点歌 c (第 1 位)".to_string()))
+        );
+        // after earlier priority requests, before the rest
+        songs.on_event(&super_chat(5, "点歌 d"));
+        songs.on_event(&super_chat(6, "！点歌e"));
+        // a guard of this room by medal only
+        let mut medal = user(7);
+        medal.medal = Some(crate::event::Medal {
+            name: "粉丝".to_string(),
+            level: 21,
+            guard_level: 2,
+            anchor_uid: 1,
+            anchor_room_id: 1000,
+        });
+        songs.on_event(&danmu(medal, "!点歌 f"));
+        // a super chat that is no request
+        assert_eq!(songs.on_event(&super_chat(8, "加油")), None);
+        assert_eq!(names(&songs), ["c", "d", "e", "f", "a", "b"]);
+        let priorities = songs.queue().waiting.iter().map(|song| song.priority);
+        assert!(priorities.eq([true, true, true, true, false, false]));
+    }
+
+    #[test]
+    fn limits() {
+        let config = SongConfig {
+            per_user_limit: 2,
+            max_queue: 4,
+            max_name_len: 5,
+        };
+        let mut songs = requests("limits", config);
+        let mut request = |uid, text: &str| songs.on_event(&danmu(user(uid), text)).unwrap();
+        assert!(request(2, "!点歌 a").is_ok());
+        assert!(request(2, "!点歌 b").is_ok());
+        assert_eq!(
+            request(2, "!点歌 c"),
+            Err("user2: 每人最多点 2 首".to_string())
+        );
+        assert_eq!(
+            request(3, "!点歌 A"),
+            Err("user3: A 已在歌单中".to_string())
+        );
+        assert_eq!(
+            request(3, "!点歌"),
+            Err("user3: 点歌请带上歌名".to_string())
+        );
+        assert_eq!(
+            request(3, "!点歌 123456"),
+            Err("user3: 歌名太长".to_string())
+        );
+        assert!(request(3, "!点歌 c").is_ok());
+        assert!(request(4, "!点歌 d").is_ok());
+        assert_eq!(request(5, "!点歌 e"), Err("user5: 歌单已满".to_string()));
+        // cancelling frees a place for the user
+        assert_eq!(request(2, "!取消点歌"), Ok("user2 取消了 b".to_string()));
+        assert_eq!(
+            request(5, "!取消点歌"),
+            Err("user5: 没有等待中的点歌".to_string())
+        );
+        assert!(request(2, "!点歌 e").is_ok());
+
+        // the playing song counts as a duplicate too
+        assert!(songs.apply(Command::Skip).is_ok());
+        assert_eq!(songs.queue().current.as_ref().unwrap().name, "a");
+        let result = songs.on_event(&danmu(user(6), "!点歌 a"));
+        assert_eq!(result, Some(Err("user6: a 已在歌单中".to_string())));
+    }
+
+    #[test]
+    fn apply() {
+        let mut songs = requests("apply", SongConfig::default());
+        assert_eq!(songs.apply(Command::Skip), Err("歌单是空的".to_string()));
+        for (uid, name) in [(2, "a"), (3, "b"), (4, "c"), (5, "d")] {
+            songs.on_event(&danmu(user(uid), &format!("!点歌 {}", name)));
+        }
+        assert_eq!(
+            songs.apply(Command::Move { from: 4, to: 1 }),
+            Ok("d 移到第 1 位".to_string())
+        );
+        assert_eq!(names(&songs), ["d", "a", "b", "c"]);
+        // positions past either end go to the end
+        songs.apply(Command::Move { from: 1, to: 9 }).unwrap();
+        assert_eq!(names(&songs), ["a", "b", "c", "d"]);
+        songs.apply(Command::Move { from: 2, to: 0 }).unwrap();
+        assert_eq!(names(&songs), ["b", "a", "c", "d"]);
+        assert_eq!(
+            songs.apply(Command::Move { from: 5, to: 1 }),
+            Err("没有第 5 首".to_string())
+        );
+        assert_eq!(
+            songs.apply(Command::Remove(0)),
+            Err("没有第 0 首".to_string())
+        );
+        assert_eq!(songs.apply(Command::Remove(4)), Ok("已删除 d".to_string()));
+
+        assert_eq!(
+            songs.apply(Command::Skip),
+            Ok("正在播放 b (user3)".to_string())
+        );
+        songs.apply(Command::Skip).unwrap();
+        songs.apply(Command::Skip).unwrap();
+        assert_eq!(songs.apply(Command::Skip), Ok("歌单已播完".to_string()));
+        assert!(songs.queue().current.is_none());
+    }
+
+    #[test]
+    fn mod_commands() {
+        let mut songs = requests("mod", SongConfig::default());
+        for (uid, name) in [(2, "a"), (3, "b"), (4, "c")] {
+            songs.on_event(&danmu(user(uid), &format!("!点歌 {}", name)));
+        }
+        // only the streamer and room admins
+        assert_eq!(songs.on_event(&danmu(user(2), "!删歌 1")), None);
+        let admin = User {
+            is_admin: true,
+            ..user(9)
+        };
+        let result = songs.on_event(&danmu(admin.clone(), "!顶歌 3"));
+        assert_eq!(result, Some(Ok("c 移到第 1 位".to_string())));
+        let result = songs.on_event(&danmu(user(1), "!移歌 1　3"));
+        assert_eq!(result, Some(Ok("c 移到第 3 位".to_string())));
+        let result = songs.on_event(&danmu(admin, "!删歌 x"));
+        assert_eq!(result, Some(Err("user9: 请带上歌单序号".to_string())));
+        assert_eq!(names(&songs), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn saved() {
+        let mut songs = requests("saved", SongConfig::default());
+        songs.on_event(&danmu(user(2), "!点歌 a"));
+        songs.on_event(&danmu(guard(3), "!点歌 b"));
+        songs.apply(Command::Skip).unwrap();
+        songs.save().unwrap();
+
+        let loaded = SongRequests::load(songs.path.clone(), SongConfig::default(), 1, 1000);
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.queue().current.as_ref().unwrap().name, "b");
+        assert_eq!(names(&loaded), ["a"]);
+        assert_eq!(loaded.to_text(), "正在播放: b (user3)\n1. a (user2)\n");
+        std::fs::remove_file(&songs.path).unwrap();
+
+        std::fs::write(&songs.path, "not json").unwrap();
+        let error = SongRequests::load(songs.path.clone(), SongConfig::default(), 1, 1000);
+        assert!(error.is_err());
+        std::fs::remove_file(&songs.path).unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{cli, event::Event, event::InteractKind, live::RoomInfo, record::Record};
+use serde::Serialize;
+use std::{collections::HashSet, path::Path};
+
+/// Summary of one live session.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct SessionStats {
+    /// unix seconds
+    pub start: Option<i64>,
+    /// unix seconds
+    pub end: Option<i64>,
+    pub peak_viewers: Option<u64>,
+    pub avg_viewers: Option<f64>,
+    /// viewers when the live stopped, from the room info
+    pub viewers: Option<u64>,
+    /// followers of the streamer, from the room info
+    pub followers: Option<u64>,
+    /// only known from a `chat record` log
+    #[serde(flatten)]
+    pub counts: Option<Counts>,
+}
+
+/// What happened in the chat during a session.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Counts {
+    pub danmaku: u64,
+    pub unique_chatters: u64,
+    /// paid gifts and guards
+    pub gift_cny: f64,
+    pub super_chat_cny: f64,
+    pub new_followers: u64,
+    pub new_guards: u64,
+}
+
+impl SessionStats {
+    /// Sums up the records of the latest session in the log, that is the
+    /// records since the last `LIVE` command if there is one.
+    pub fn from_records(records: &[Record]) -> SessionStats {
+        let session_start = records
+            .iter()
+            .rposition(|record| matches!(record.event, Event::Live { .. }))
+            .unwrap_or(0);
+        let records = &records[session_start..];
+
+        let mut counts = Counts::default();
+        let mut chatters = HashSet::new();
+        let mut viewers = vec![];
+        for record in records {
+            match &record.event {
+                Event::Danmu(danmu) => {
+                    counts.danmaku += 1;
+                    chatters.insert(danmu.user.uid);
+                }
+                Event::SuperChat(sc) => counts.super_chat_cny += sc.price as f64,
+                Event::GuardBuy(_) => counts.new_guards += 1,
+                Event::Interact(interact)
+                    if matches!(
+                        interact.kind,
+                        InteractKind::Follow
+                            | InteractKind::SpecialFollow
+                            | InteractKind::MutualFollow
+                    ) =>
+                {
+                    counts.new_followers += 1
+                }
+                Event::OnlineRankCount {
+                    online_count: Some(count),
+                    ..
+                } => viewers.push(*count),
+                _ => {}
+            }
+            if !matches!(record.event, Event::SuperChat(_))
+                && let Some(cny) = record.event.cny()
+            {
+                counts.gift_cny += cny;
+            }
+        }
+        counts.unique_chatters = chatters.len() as u64;
+        SessionStats {
+            start: records.first().map(|record| match record.event {
+                Event::Live {
+                    live_time: Some(live_time),
+                } => live_time,
+                _ => record.local_ts / 1000,
+            }),
+            end: records.last().map(|record| record.local_ts / 1000),
+            peak_viewers: viewers.iter().copied().max(),
+            avg_viewers: (!viewers.is_empty())
+                .then(|| viewers.iter().sum::<u64>() as f64 / viewers.len() as f64),
+            counts: Some(counts),
+            ..Default::default()
+        }
+    }
+
+    /// What the room info tells without a log: the viewers and followers
+    /// right now.
+    pub fn from_room(info: &RoomInfo) -> SessionStats {
+        SessionStats {
+            start: info.live_time,
+            viewers: Some(info.online),
+            followers: Some(info.attention),
+            ..Default::default()
+        }
+    }
+
+    pub fn pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = self.time_pairs();
+        if let Some(viewers) = self.viewers {
+            pairs.push(("viewers".to_string(), viewers.to_string()));
+        }
+        if let Some(followers) = self.followers {
+            pairs.push(("followers".to_string(), followers.to_string()));
+        }
+        match &self.counts {
+            Some(counts) => pairs.extend(counts.pairs()),
+            None => pairs.push((
+                "counts".to_string(),
+                "unavailable, pass the log of `chat record` with --log".to_string(),
+            )),
+        }
+        pairs
+    }
+
+    fn time_pairs(&self) -> Vec<(String, String)> {
+        let time = |ts: Option<i64>| {
+            ts.and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+                .map(|ts| ts.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        };
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        vec![
+            ("start time".to_string(), time(self.start)),
+            ("end time".to_string(), time(self.end)),
+            (
+                "live duration".to_string(),
+                or_dash(
+                    self.start
+                        .zip(self.end)
+                        .map(|(start, end)| cli::format_duration((end - start).max(0))),
+                ),
+            ),
+            (
+                "peak viewers".to_string(),
+                or_dash(self.peak_viewers.map(|peak| peak.to_string())),
+            ),
+            (
+                "average viewers".to_string(),
+                or_dash(self.avg_viewers.map(|avg| format!("{:.0}", avg))),
+            ),
+        ]
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = "# Live session summary\n\n| | |\n|---|---|\n".to_string();
+        for (k, v) in self.pairs() {
+            markdown.push_str(&format!("| {} | {} |\n", k, v));
+        }
+        markdown
+    }
+
+    pub fn to_html(&self) -> String {
+        let rows: String = self
+            .pairs()
+            .iter()
+            .map(|(k, v)| format!("<tr><th>{}</th><td>{}</td></tr>", k, v))
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Live session summary</title></head>\n\
+             <body><h1>Live session summary</h1><table>{}</table></body></html>\n",
+            rows
+        )
+    }
+
+    /// Writes the summary as Markdown, HTML or JSON by the file extension.
+    pub fn export<P: AsRef<Path>>(&self, fname: P) -> Result<(), Box<dyn std::error::Error>> {
+        let fname = fname.as_ref();
+        let text = match ExportFormat::of(fname)? {
+            ExportFormat::Markdown => self.to_markdown(),
+            ExportFormat::Html => self.to_html(),
+            ExportFormat::Json => serde_json::to_string_pretty(self)?,
+        };
+        std::fs::write(fname, text)?;
+        Ok(())
+    }
+}
+
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    /// The format of a summary file by its extension.
+    pub fn of(fname: &Path) -> Result<ExportFormat, String> {
+        match fname.extension().and_then(|ext| ext.to_str()) {
+            Some("md") => Ok(ExportFormat::Markdown),
+            Some("html" | "htm") => Ok(ExportFormat::Html),
+            Some("json") => Ok(ExportFormat::Json),
+            _ => Err(format!(
+                "unknown summary format {}, expected .md, .html or .json",
+                fname.display()
+            )),
+        }
+    }
+}
+
+impl Counts {
+    pub fn revenue_cny(&self) -> f64 {
+        self.gift_cny + self.super_chat_cny
+    }
+
+    fn pairs(&self) -> Vec<(String, String)> {
+        vec![
+            ("danmaku".to_string(), self.danmaku.to_string()),
+            (
+                "unique chatters".to_string(),
+                self.unique_chatters.to_string(),
+            ),
+            ("gift revenue".to_string(), format!("¥{:.2}", self.gift_cny)),
+            (
+                "super chat revenue".to_string(),
+                format!("¥{:.2}", self.super_chat_cny),
+            ),
+            (
+                "total revenue".to_string(),
+                format!("¥{:.2}", self.revenue_cny()),
+            ),
+            ("new followers".to_string(), self.new_followers.to_string()),
+            ("new guards".to_string(), self.new_guards.to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record of `tests/fixtures/events/<name>.json` received at `local_ts`.
+    fn fixture(name: &str, local_ts: i64) -> Record {
+        let path = format!(
+            "{}/tests/fixtures/events/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let raw = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        Record {
+            local_ts,
+            ..Record::new(raw)
+        }
+    }
+
+    fn viewers(online_count: u64, local_ts: i64) -> Record {
+        let mut record = fixture("ONLINE_RANK_COUNT", local_ts);
+        record.event = Event::OnlineRankCount {
+            count: 0,
+            online_count: Some(online_count),
+        };
+        record
+    }
+
+    fn session() -> SessionStats {
+        SessionStats::from_records(&[
+            // an earlier session, not counted
+            fixture("LIVE", 1699990000000),
+            fixture("DANMU_MSG", 1699990001000),
+            viewers(9999, 1699990002000),
+            fixture("LIVE", 1700000501000),
+            fixture("DANMU_MSG", 1700000502000),
+            fixture("DANMU_MSG", 1700000503000),
+            viewers(100, 1700000504000),
+            fixture("SEND_GIFT", 1700000505000),
+            fixture("SUPER_CHAT_MESSAGE", 1700000506000),
+            fixture("GUARD_BUY", 1700000507000),
+            fixture("INTERACT_WORD", 1700000508000),
+            viewers(300, 1700000509000),
+        ])
+    }
+
+    #[test]
+    fn from_records_sums_up_the_last_session() {
+        let stats = session();
+        // the start comes from the LIVE command, the end from the last record
+        assert_eq!(stats.start, Some(1700000500));
+        assert_eq!(stats.end, Some(1700000509));
+        assert_eq!(stats.peak_viewers, Some(300));
+        assert_eq!(stats.avg_viewers, Some(200.0));
+        let counts = stats.counts.unwrap();
+        assert_eq!(counts.danmaku, 2);
+        assert_eq!(counts.unique_chatters, 1);
+        // 5 gifts of 100 gold and a guard of 198000 gold, super chats apart
+        assert_eq!(counts.gift_cny, 198.5);
+        assert_eq!(counts.super_chat_cny, 30.0);
+        assert_eq!(counts.revenue_cny(), 228.5);
+        assert_eq!(counts.new_followers, 1);
+        assert_eq!(counts.new_guards, 1);
+    }
+
+    #[test]
+    fn from_records_without_live_takes_everything() {
+        let stats = SessionStats::from_records(&[
+            fixture("DANMU_MSG", 1700000001000),
+            fixture("DANMU_MSG", 1700000002000),
+        ]);
+        assert_eq!(stats.start, Some(1700000001));
+        assert_eq!(stats.end, Some(1700000002));
+        assert_eq!(stats.peak_viewers, None);
+        assert_eq!(stats.avg_viewers, None);
+        assert_eq!(stats.counts.unwrap().danmaku, 2);
+
+        let stats = SessionStats::from_records(&[]);
+        assert_eq!(stats.start, None);
+        assert_eq!(stats.counts.unwrap().danmaku, 0);
+    }
+
+    #[test]
+    fn pairs_without_log() {
+        let stats = SessionStats {
+            viewers: Some(42),
+            followers: Some(1000),
+            ..Default::default()
+        };
+        let pairs = stats.pairs();
+        let value = |key: &str| {
+            pairs
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(value("viewers"), Some("42"));
+        assert_eq!(value("followers"), Some("1000"));
+        assert_eq!(value("peak viewers"), Some("-"));
+        assert!(value("counts").unwrap().starts_with("unavailable"));
+    }
+
+    #[test]
+    fn export_by_extension() {
+        let dir = std::env::temp_dir().join(format!("bili-live-stats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stats = session();
+
+        stats.export(dir.join("summary.md")).unwrap();
+        let markdown = std::fs::read_to_string(dir.join("summary.md")).unwrap();
+        assert!(markdown.starts_with("# Live session summary\n"));
+        assert!(markdown.contains("| danmaku | 2 |\n"));
+        assert!(markdown.contains("| total revenue | ¥228.50 |\n"));
+
+        for html in ["summary.html", "summary.htm"] {
+            stats.export(dir.join(html)).unwrap();
+            let html = std::fs::read_to_string(dir.join(html)).unwrap();
+            assert!(html.starts_with("<!DOCTYPE html>"));
+            assert!(html.contains("<tr><th>new guards</th><td>1</td></tr>"));
+        }
+
+        stats.export(dir.join("summary.json")).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("summary.json")).unwrap())
+                .unwrap();
+        // the counts are flattened into the summary
+        assert_eq!(json["start"], 1700000500);
+        assert_eq!(json["danmaku"], 2);
+        assert_eq!(json["gift_cny"], 198.5);
+
+        assert!(stats.export(dir.join("summary.txt")).is_err());
+        assert!(!dir.join("summary.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
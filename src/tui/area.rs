@@ -0,0 +1,553 @@
+use super::{init_error_hooks, init_terminal, restore_terminal};
+use crate::{area, config::Config};
+use ratatui::{
+    backend::Backend,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, MouseButton, MouseEventKind},
+    layout::{Constraint, Direction, Layout, Position, Rect},
+    style::{Modifier, Style},
+    terminal::{Frame, Terminal},
+    text::Line,
+    widgets::{Block, List, ListState},
+};
+
+const PAGE: usize = 10;
+
+fn list_vertical(keycode: KeyCode, list_state: &mut ListState, max_len: usize) {
+    if max_len == 0 {
+        return;
+    }
+    let tmp = list_state.selected_mut().get_or_insert(0);
+    match keycode {
+        KeyCode::Down | KeyCode::Char('j') => {
+            if *tmp < max_len - 1 {
+                *tmp += 1;
+            } else {
+                *tmp = 0;
+            }
+        }
+        KeyCode::Up | KeyCode::Char('k') => {
+            if *tmp > 0 {
+                *tmp -= 1;
+            } else {
+                *tmp = max_len - 1;
+            }
+        }
+        KeyCode::Home => *tmp = 0,
+        KeyCode::End => *tmp = max_len - 1,
+        KeyCode::PageDown => *tmp = (*tmp + PAGE).min(max_len - 1),
+        KeyCode::PageUp => *tmp = tmp.saturating_sub(PAGE),
+        _ => unreachable!(),
+    }
+}
+
+/// The row of a bordered list under `position`, if any.
+fn list_row(rect: Rect, list_state: &ListState, position: Position) -> Option<usize> {
+    let inner = rect.inner(ratatui::layout::Margin::new(1, 1));
+    inner
+        .contains(position)
+        .then(|| (position.y - inner.y) as usize + list_state.offset())
+}
+
+/// Groups shown by the picker: pinned "Recent" and "Favorites" groups
+/// followed by the live areas, or only the matches while searching.
+fn picker_groups(
+    area_list: &[(String, Vec<(String, String)>)],
+    config: &Config,
+    search: Option<&str>,
+) -> Vec<(String, Vec<(String, String)>)> {
+    let label = |name: &str, id: &str| {
+        if config.favorite_areas.iter().any(|fav| fav == id) {
+            format!("★ {}", name)
+        } else {
+            name.to_string()
+        }
+    };
+    if let Some(query) = search {
+        let items = if query.is_empty() {
+            vec![]
+        } else {
+            area::search(area_list, query)
+                .into_iter()
+                .map(|(group, name, id)| {
+                    (format!("{} ({})", label(name, id), group), id.to_string())
+                })
+                .collect()
+        };
+        return vec![(format!("/{}", query), items)];
+    }
+    let find = |ids: &[String]| {
+        ids.iter()
+            .filter_map(|id| {
+                area_list
+                    .iter()
+                    .flat_map(|(_, li)| li.iter())
+                    .find(|(_, area_id)| area_id == id)
+                    .map(|(name, id)| (label(name, id), id.clone()))
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut groups = vec![];
+    for (title, ids) in [
+        ("Recent", &config.recent_areas),
+        ("Favorites", &config.favorite_areas),
+    ] {
+        let items = find(ids);
+        if !items.is_empty() {
+            groups.push((title.to_string(), items));
+        }
+    }
+    groups.extend(area_list.iter().map(|(group, li)| {
+        (
+            group.clone(),
+            li.iter()
+                .map(|(name, id)| (label(name, id), id.clone()))
+                .collect(),
+        )
+    }));
+    groups
+}
+
+#[derive(Debug, PartialEq)]
+enum PickerState {
+    Browse,
+    Search(String),
+    Selected(String),
+    Cancelled,
+}
+
+/// The two-pane live area picker, driven by terminal events and drawn
+/// into any ratatui frame.
+pub struct AreaPicker<'a> {
+    area_list: &'a [(String, Vec<(String, String)>)],
+    config: &'a mut Config,
+    state: PickerState,
+    groups: Vec<(String, Vec<(String, String)>)>,
+    list_state: ListState,
+    list_states: Vec<ListState>,
+    is_left: bool,
+    left_length: usize,
+    rects: (Rect, Rect),
+}
+
+impl<'a> AreaPicker<'a> {
+    const TITLE: &'static str = "Live Area";
+
+    pub fn new(area_list: &'a [(String, Vec<(String, String)>)], config: &'a mut Config) -> Self {
+        let groups = picker_groups(area_list, config, None);
+        let left_length = area_list
+            .iter()
+            .map(|(name, _)| Line::from(name.as_str()).width())
+            .chain(["Favorites".len(), Self::TITLE.len()])
+            .max()
+            .unwrap();
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+        AreaPicker {
+            area_list,
+            config,
+            state: PickerState::Browse,
+            list_states: vec![ListState::default(); groups.len()],
+            groups,
+            list_state,
+            is_left: true,
+            left_length,
+            rects: (Rect::default(), Rect::default()),
+        }
+    }
+
+    fn cur_idx(&self) -> usize {
+        self.list_state.selected().unwrap()
+    }
+
+    fn selected_id(&self) -> Option<String> {
+        let cur_idx = self.cur_idx();
+        let cur_idx_r = self.list_states[cur_idx].selected()?;
+        Some(self.groups[cur_idx].1[cur_idx_r].1.clone())
+    }
+
+    fn focus_left(&mut self) {
+        let cur_idx = self.cur_idx();
+        self.is_left = true;
+        self.list_states[cur_idx].select(None);
+    }
+
+    fn focus_right(&mut self) {
+        let cur_idx = self.cur_idx();
+        if !self.groups[cur_idx].1.is_empty() {
+            self.is_left = false;
+            if self.list_states[cur_idx].selected().is_none() {
+                self.list_states[cur_idx].select(Some(0));
+            }
+        }
+    }
+
+    fn move_selection(&mut self, keycode: KeyCode) {
+        let cur_idx = self.cur_idx();
+        if self.is_left {
+            list_vertical(keycode, &mut self.list_state, self.groups.len());
+        } else {
+            let cur_len = self.groups[cur_idx].1.len();
+            list_vertical(keycode, &mut self.list_states[cur_idx], cur_len);
+        }
+    }
+
+    fn toggle_favorite(&mut self) {
+        let Some(id) = self.selected_id() else {
+            return;
+        };
+        let favorites = &mut self.config.favorite_areas;
+        match favorites.iter().position(|fav| *fav == id) {
+            Some(pos) => drop(favorites.remove(pos)),
+            None => favorites.push(id),
+        }
+        self.regroup();
+    }
+
+    /// Rebuilds the groups after the favorites or the search query changed,
+    /// keeping the selection where possible.
+    fn regroup(&mut self) {
+        let search = match &self.state {
+            PickerState::Search(query) => Some(query.as_str()),
+            _ => None,
+        };
+        let cur_title = self.groups[self.cur_idx()].0.clone();
+        let cur_idx_r = self.list_states[self.cur_idx()].selected();
+        self.groups = picker_groups(self.area_list, self.config, search);
+        self.list_states = vec![ListState::default(); self.groups.len()];
+        let cur_idx = self
+            .groups
+            .iter()
+            .position(|(title, _)| *title == cur_title)
+            .unwrap_or(0);
+        self.list_state.select(Some(cur_idx));
+        let len = self.groups[cur_idx].1.len();
+        if search.is_some() {
+            self.is_left = false;
+            self.list_states[cur_idx].select((len > 0).then_some(0));
+        } else if !self.is_left && len > 0 {
+            self.list_states[cur_idx].select(Some(cur_idx_r.unwrap_or(0).min(len - 1)));
+        } else {
+            self.is_left = true;
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => self.handle_key(key.code),
+            Event::Mouse(mouse) => {
+                let position = Position::new(mouse.column, mouse.row);
+                match mouse.kind {
+                    MouseEventKind::Down(MouseButton::Left) => self.click(position),
+                    MouseEventKind::ScrollDown => self.scroll(position, KeyCode::Down),
+                    MouseEventKind::ScrollUp => self.scroll(position, KeyCode::Up),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, keycode: KeyCode) {
+        match (keycode, &mut self.state) {
+            (KeyCode::Esc, PickerState::Search(_)) => {
+                self.state = PickerState::Browse;
+                self.regroup();
+            }
+            (KeyCode::Backspace, PickerState::Search(query)) => {
+                query.pop();
+                self.regroup();
+            }
+            (KeyCode::Char(c), PickerState::Search(query)) => {
+                query.push(c);
+                self.regroup();
+            }
+            (KeyCode::Esc | KeyCode::Char('q'), PickerState::Browse) => {
+                self.state = PickerState::Cancelled;
+            }
+            (KeyCode::Char('/'), PickerState::Browse) => {
+                self.state = PickerState::Search(String::new());
+                self.regroup();
+            }
+            (KeyCode::Left | KeyCode::Char('h'), PickerState::Browse) => self.focus_left(),
+            (KeyCode::Right | KeyCode::Char('l'), PickerState::Browse) => self.focus_right(),
+            (KeyCode::Enter, PickerState::Browse | PickerState::Search(_)) => {
+                if self.is_left {
+                    self.focus_right();
+                } else if let Some(id) = self.selected_id() {
+                    self.state = PickerState::Selected(id);
+                }
+            }
+            (
+                KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Home
+                | KeyCode::End
+                | KeyCode::PageUp
+                | KeyCode::PageDown,
+                PickerState::Browse | PickerState::Search(_),
+            )
+            | (KeyCode::Char('j' | 'k'), PickerState::Browse) => self.move_selection(keycode),
+            (KeyCode::Char('f'), PickerState::Browse) => self.toggle_favorite(),
+            _ => {}
+        }
+    }
+
+    fn click(&mut self, position: Position) {
+        let cur_idx = self.cur_idx();
+        if let Some(row) = list_row(self.rects.0, &self.list_state, position)
+            && row < self.groups.len()
+            && !matches!(self.state, PickerState::Search(_))
+        {
+            self.focus_left();
+            self.list_state.select(Some(row));
+        } else if let Some(row) = list_row(self.rects.1, &self.list_states[cur_idx], position)
+            && row < self.groups[cur_idx].1.len()
+        {
+            if !self.is_left && self.list_states[cur_idx].selected() == Some(row) {
+                self.state = PickerState::Selected(self.groups[cur_idx].1[row].1.clone());
+            } else {
+                self.list_states[cur_idx].select(Some(row));
+                self.is_left = false;
+            }
+        }
+    }
+
+    fn scroll(&mut self, position: Position, keycode: KeyCode) {
+        if self.rects.0.contains(position) && !matches!(self.state, PickerState::Search(_)) {
+            self.focus_left();
+            self.move_selection(keycode);
+        } else if self.rects.1.contains(position) {
+            self.focus_right();
+            self.move_selection(keycode);
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        let [left_area, right_area] = Layout::new(
+            Direction::Horizontal,
+            [
+                Constraint::Length((self.left_length + 4) as u16),
+                Constraint::Fill(1),
+            ],
+        )
+        .areas(frame.size());
+        self.rects = (left_area, right_area);
+        let highlight_style = Style::new().add_modifier(Modifier::REVERSED);
+        let list = List::new(self.groups.iter().map(|(title, _)| title.as_str()))
+            .block(Block::bordered().title(Self::TITLE))
+            .highlight_style(highlight_style)
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, left_area, &mut self.list_state);
+        let cur_idx = self.cur_idx();
+        let (group, items) = &self.groups[cur_idx];
+        let list = List::new(items.iter().map(|(label, _)| label.as_str()))
+            .block(Block::bordered().title(group.as_str()))
+            .highlight_style(highlight_style)
+            .highlight_symbol(">> ");
+        frame.render_stateful_widget(list, right_area, &mut self.list_states[cur_idx]);
+    }
+
+    /// Draws and feeds events until an area is selected or the picker is cancelled.
+    pub fn run<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        mut next_event: impl FnMut() -> std::io::Result<Event>,
+    ) -> std::io::Result<Option<String>> {
+        loop {
+            match &self.state {
+                PickerState::Selected(id) => return Ok(Some(id.clone())),
+                PickerState::Cancelled => return Ok(None),
+                PickerState::Browse | PickerState::Search(_) => {}
+            }
+            terminal.draw(|frame| self.render(frame))?;
+            self.handle_event(next_event()?);
+        }
+    }
+}
+
+/// Asks for a live area, `None` if the user cancelled.
+/// Favorites toggled in the picker are stored in `config`.
+pub fn ask_area(
+    area_list: &[(String, Vec<(String, String)>)],
+    config: &mut Config,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    init_error_hooks()?;
+    let mut terminal = init_terminal()?;
+    let area = AreaPicker::new(area_list, config).run(&mut terminal, event::read);
+    restore_terminal()?;
+    Ok(area?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::{
+        backend::TestBackend,
+        crossterm::event::{KeyEvent, KeyModifiers},
+    };
+
+    fn area_list() -> Vec<(String, Vec<(String, String)>)> {
+        vec![
+            (
+                "网游".to_string(),
+                vec![
+                    ("英雄联盟".to_string(), "86".to_string()),
+                    ("原神".to_string(), "321".to_string()),
+                ],
+            ),
+            (
+                "单机游戏".to_string(),
+                vec![("我的世界".to_string(), "216".to_string())],
+            ),
+        ]
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn keys(text: &str) -> Vec<Event> {
+        text.chars().map(|c| key(KeyCode::Char(c))).collect()
+    }
+
+    /// Feeds `events` to a picker and draws it once more, the result is
+    /// `None` when the events ran out before the picker ended.
+    fn drive(
+        config: &mut Config,
+        events: Vec<Event>,
+    ) -> (Option<Option<String>>, Terminal<TestBackend>) {
+        let area_list = area_list();
+        let mut terminal = Terminal::new(TestBackend::new(40, 6)).unwrap();
+        let mut picker = AreaPicker::new(&area_list, config);
+        let mut events = events.into_iter();
+        let result = picker
+            .run(&mut terminal, || {
+                events
+                    .next()
+                    .ok_or_else(|| std::io::Error::other("out of events"))
+            })
+            .ok();
+        terminal.draw(|frame| picker.render(frame)).unwrap();
+        (result, terminal)
+    }
+
+    /// The text of each row of the screen, skipping the cells covered by
+    /// wide characters.
+    fn screen(terminal: &Terminal<TestBackend>) -> Vec<String> {
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                let mut row = String::new();
+                let mut x = 0;
+                while x < buffer.area.width {
+                    let symbol = buffer.get(x, y).symbol();
+                    row.push_str(symbol);
+                    x += Line::from(symbol).width().max(1) as u16;
+                }
+                row
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_render() {
+        let mut config = Config::default();
+        let (result, terminal) = drive(&mut config, vec![]);
+        assert_eq!(result, None);
+        assert_eq!(
+            screen(&terminal),
+            [
+                "┌Live Area──┐┌网游─────────────────────┐",
+                "│> 网游     ││英雄联盟                 │",
+                "│  单机游戏 ││原神                     │",
+                "│           ││                         │",
+                "│           ││                         │",
+                "└───────────┘└─────────────────────────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn search() {
+        let mut config = Config::default();
+        let (result, terminal) = drive(&mut config, keys("/yuan"));
+        assert_eq!(result, None);
+        assert_eq!(
+            screen(&terminal),
+            [
+                "┌Live Area──┐┌/yuan────────────────────┐",
+                "│> /yuan    ││>> 原神 (网游)           │",
+                "│           ││                         │",
+                "│           ││                         │",
+                "│           ││                         │",
+                "└───────────┘└─────────────────────────┘",
+            ]
+        );
+
+        let mut events = keys("/yuan");
+        events.push(key(KeyCode::Enter));
+        let (result, _) = drive(&mut config, events);
+        assert_eq!(result, Some(Some("321".to_string())));
+    }
+
+    #[test]
+    fn toggle_favorite() {
+        let mut config = Config::default();
+        let events = vec![key(KeyCode::Right), key(KeyCode::Char('f'))];
+        let (result, terminal) = drive(&mut config, events.clone());
+        assert_eq!(result, None);
+        assert_eq!(config.favorite_areas, ["86"]);
+        assert_eq!(
+            screen(&terminal),
+            [
+                "┌Live Area──┐┌网游─────────────────────┐",
+                "│  Favorites││>> ★ 英雄联盟            │",
+                "│> 网游     ││   原神                  │",
+                "│  单机游戏 ││                         │",
+                "│           ││                         │",
+                "└───────────┘└─────────────────────────┘",
+            ]
+        );
+
+        let (_, terminal) = drive(&mut config, events);
+        assert!(config.favorite_areas.is_empty());
+        assert_eq!(
+            screen(&terminal)[1],
+            "│> 网游     ││>> 英雄联盟              │"
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let mut config = Config::default();
+        for events in [vec![key(KeyCode::Esc)], keys("q")] {
+            let (result, _) = drive(&mut config, events);
+            assert_eq!(result, Some(None));
+        }
+        // Esc first leaves the search, q is part of the query
+        let mut events = keys("/q");
+        events.push(key(KeyCode::Esc));
+        let (result, terminal) = drive(&mut config, events.clone());
+        assert_eq!(result, None);
+        assert_eq!(
+            screen(&terminal)[1],
+            "│> 网游     ││>> 英雄联盟              │"
+        );
+        events.push(key(KeyCode::Esc));
+        let (result, _) = drive(&mut config, events);
+        assert_eq!(result, Some(None));
+    }
+
+    #[test]
+    fn enter_selects() {
+        let mut config = Config::default();
+        let events = vec![key(KeyCode::Right), key(KeyCode::Down), key(KeyCode::Enter)];
+        let (result, _) = drive(&mut config, events);
+        assert_eq!(result, Some(Some("321".to_string())));
+
+        // Enter on a group moves into it first
+        let events = vec![key(KeyCode::Down), key(KeyCode::Enter), key(KeyCode::Enter)];
+        let (result, _) = drive(&mut config, events);
+        assert_eq!(result, Some(Some("216".to_string())));
+    }
+}
@@ -0,0 +1,631 @@
+use super::{init_error_hooks, init_terminal, restore_terminal};
+use crate::{
+    danmaku::Message,
+    event::{Event as LiveEvent, InteractKind, Medal, User},
+};
+use chrono::{DateTime, Local};
+use ratatui::{
+    backend::Backend,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, MouseEventKind},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    terminal::{Frame, Terminal},
+    text::{Line, Span},
+    widgets::{Block, List, Paragraph},
+};
+use std::{collections::VecDeque, io, time::Duration};
+use tokio::sync::mpsc;
+
+const MAX_ENTRIES: usize = 5000;
+
+/// Requests from the chat view to the async side.
+#[derive(Debug, Clone)]
+pub enum Action {
+    Send(String),
+    /// mute the user for the current live
+    Mute {
+        uid: u64,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Danmu,
+    Gift,
+    SuperChat,
+    Interact,
+    System,
+}
+
+impl Kind {
+    const ALL: [Kind; 5] = [
+        Kind::Danmu,
+        Kind::Gift,
+        Kind::SuperChat,
+        Kind::Interact,
+        Kind::System,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Kind::Danmu => "弹幕",
+            Kind::Gift => "礼物",
+            Kind::SuperChat => "SC",
+            Kind::Interact => "进场",
+            Kind::System => "系统",
+        }
+    }
+}
+
+struct Entry {
+    /// increases with every entry, identifies the selected one
+    seq: u64,
+    time: DateTime<Local>,
+    kind: Kind,
+    spans: Vec<Span<'static>>,
+    /// uid and name of the user who caused the entry
+    user: Option<(u64, String)>,
+}
+
+/// Fan medal colors by level, as shown on the live page.
+fn medal_color(level: u64) -> Color {
+    match level {
+        0..=4 => Color::Rgb(0x5c, 0x96, 0x8e),
+        5..=8 => Color::Rgb(0x5d, 0x7b, 0x9e),
+        9..=12 => Color::Rgb(0x8d, 0x7c, 0xa6),
+        13..=16 => Color::Rgb(0xbe, 0x66, 0x86),
+        17..=20 => Color::Rgb(0xc7, 0x9d, 0x24),
+        21..=24 => Color::Rgb(0x1a, 0x54, 0x4b),
+        25..=28 => Color::Rgb(0x06, 0x15, 0x4c),
+        29..=32 => Color::Rgb(0x2d, 0x08, 0x55),
+        33..=36 => Color::Rgb(0x7a, 0x04, 0x23),
+        _ => Color::Rgb(0xff, 0x61, 0x0b),
+    }
+}
+
+fn guard_name(guard_level: u64) -> Option<&'static str> {
+    match guard_level {
+        1 => Some("总督"),
+        2 => Some("提督"),
+        3 => Some("舰长"),
+        _ => None,
+    }
+}
+
+fn guard_color(guard_level: u64) -> Color {
+    match guard_level {
+        1 => Color::Rgb(0xff, 0xb0, 0x27),
+        2 => Color::Rgb(0xe1, 0x7a, 0xff),
+        3 => Color::Rgb(0x00, 0xd1, 0xf1),
+        _ => Color::Gray,
+    }
+}
+
+fn medal_span(medal: &Medal) -> Span<'static> {
+    Span::styled(
+        format!(" {} {} ", medal.name, medal.level),
+        Style::new().fg(Color::White).bg(medal_color(medal.level)),
+    )
+}
+
+fn user_spans(user: &User) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    if let Some(medal) = &user.medal {
+        spans.push(medal_span(medal));
+        spans.push(Span::raw(" "));
+    }
+    if let Some(guard) = guard_name(user.guard_level) {
+        spans.push(Span::styled(
+            format!("[{}]", guard),
+            Style::new().fg(guard_color(user.guard_level)),
+        ));
+    }
+    if user.is_admin {
+        spans.push(Span::styled("[房]", Style::new().fg(Color::Red)));
+    }
+    let style = Style::new().fg(guard_color(user.guard_level));
+    let style = if user.guard_level > 0 {
+        style.add_modifier(Modifier::BOLD)
+    } else {
+        style
+    };
+    spans.push(Span::styled(user.name.clone(), style));
+    spans
+}
+
+fn parse_color(color: &str) -> Option<Color> {
+    let rgb = u32::from_str_radix(color.strip_prefix('#')?, 16).ok()?;
+    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// The kind and text of the line shown for `event`, if it is shown at all.
+fn event_spans(event: &LiveEvent) -> Option<(Kind, Vec<Span<'static>>)> {
+    let gift_style = Style::new().fg(Color::Yellow);
+    let system_style = Style::new().fg(Color::LightRed);
+    let result = match event {
+        LiveEvent::Danmu(danmu) => {
+            let mut spans = user_spans(&danmu.user);
+            spans.push(Span::raw(": "));
+            if let Some(reply_to) = &danmu.reply_to {
+                spans.push(Span::styled(format!("@{} ", reply_to), Color::Cyan));
+            }
+            spans.push(Span::raw(danmu.text.clone()));
+            (Kind::Danmu, spans)
+        }
+        LiveEvent::Gift(gift) => {
+            let mut spans = user_spans(&gift.user);
+            spans.push(Span::styled(
+                format!(" {} {} x{}", gift.action, gift.gift_name, gift.num),
+                gift_style,
+            ));
+            (Kind::Gift, spans)
+        }
+        LiveEvent::Combo(combo) => {
+            let mut spans = user_spans(&combo.user);
+            spans.push(Span::styled(
+                format!(
+                    " {} {} 连击 x{} (共 {})",
+                    combo.action, combo.gift_name, combo.combo_num, combo.total_num
+                ),
+                gift_style,
+            ));
+            (Kind::Gift, spans)
+        }
+        LiveEvent::GuardBuy(guard) => (
+            Kind::Gift,
+            vec![Span::styled(
+                format!("{} 开通了 {} x{}", guard.name, guard.gift_name, guard.num),
+                Style::new()
+                    .fg(guard_color(guard.guard_level))
+                    .add_modifier(Modifier::BOLD),
+            )],
+        ),
+        LiveEvent::SuperChat(sc) => {
+            let bg = parse_color(&sc.background_color).unwrap_or(Color::Blue);
+            let mut spans = vec![Span::styled(
+                format!(" ¥{} ", sc.price),
+                Style::new().fg(Color::Black).bg(bg),
+            )];
+            spans.push(Span::raw(" "));
+            spans.extend(user_spans(&sc.user));
+            spans.push(Span::raw(": "));
+            spans.push(Span::styled(sc.message.clone(), Style::new().bold()));
+            (Kind::SuperChat, spans)
+        }
+        LiveEvent::Interact(interact) => {
+            let action = match interact.kind {
+                InteractKind::Enter => "进入直播间",
+                InteractKind::Follow => "关注了直播间",
+                InteractKind::Share => "分享了直播间",
+                InteractKind::SpecialFollow => "特别关注了直播间",
+                InteractKind::MutualFollow => "互相关注了",
+                InteractKind::Other(_) => return None,
+            };
+            let mut spans = user_spans(&interact.user);
+            spans.push(Span::styled(format!(" {}", action), Color::DarkGray));
+            (Kind::Interact, spans)
+        }
+        LiveEvent::Live { .. } => (Kind::System, vec![Span::styled("直播开始", system_style)]),
+        LiveEvent::Preparing => (Kind::System, vec![Span::styled("直播结束", system_style)]),
+        LiveEvent::RoomChange(room) => (
+            Kind::System,
+            vec![Span::styled(
+                format!(
+                    "房间信息更新: {} · {}-{}",
+                    room.title, room.parent_area_name, room.area_name
+                ),
+                system_style,
+            )],
+        ),
+        LiveEvent::Warning { message } => (
+            Kind::System,
+            vec![Span::styled(format!("警告: {}", message), system_style)],
+        ),
+        LiveEvent::CutOff { message } => (
+            Kind::System,
+            vec![Span::styled(
+                format!("直播被切断: {}", message),
+                system_style,
+            )],
+        ),
+        LiveEvent::OnlineRankCount { .. }
+        | LiveEvent::WatchedChange { .. }
+        | LiveEvent::Unknown { .. } => return None,
+    };
+    Some(result)
+}
+
+/// The scrolling chat view of a live room, fed with danmaku messages and
+/// terminal events and drawn into any ratatui frame.
+pub struct ChatView {
+    room_id: u64,
+    entries: VecDeque<Entry>,
+    shown: [bool; Kind::ALL.len()],
+    paused: bool,
+    /// filtered entries hidden below the bottom of the view
+    offset: usize,
+    connection: String,
+    popularity: Option<u32>,
+    watched: Option<u64>,
+    online_rank: Option<u64>,
+    /// the danmaku being typed, if the input box is open
+    input: Option<String>,
+    /// `seq` of the selected entry
+    selected: Option<u64>,
+    next_seq: u64,
+    actions: mpsc::UnboundedSender<Action>,
+    height: usize,
+    quit: bool,
+}
+
+impl ChatView {
+    pub fn new(room_id: u64, actions: mpsc::UnboundedSender<Action>) -> Self {
+        ChatView {
+            room_id,
+            entries: VecDeque::new(),
+            shown: [true; Kind::ALL.len()],
+            paused: false,
+            offset: 0,
+            connection: "connecting".to_string(),
+            popularity: None,
+            watched: None,
+            online_rank: None,
+            input: None,
+            selected: None,
+            next_seq: 0,
+            actions,
+            height: 0,
+            quit: false,
+        }
+    }
+
+    fn is_shown(&self, kind: Kind) -> bool {
+        self.shown[Kind::ALL.iter().position(|&k| k == kind).unwrap()]
+    }
+
+    fn shown_len(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| self.is_shown(entry.kind))
+            .count()
+    }
+
+    fn add_entry(&mut self, kind: Kind, spans: Vec<Span<'static>>, user: Option<(u64, String)>) {
+        // keep the view still while paused or scrolled back
+        if self.is_shown(kind) && (self.paused || self.offset > 0) {
+            self.offset += 1;
+        }
+        self.entries.push_back(Entry {
+            seq: self.next_seq,
+            time: Local::now(),
+            kind,
+            spans,
+            user,
+        });
+        self.next_seq += 1;
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+            self.offset = self.offset.min(self.shown_len());
+        }
+    }
+
+    pub fn push_event(&mut self, event: &LiveEvent) {
+        match event {
+            LiveEvent::OnlineRankCount { count, .. } => self.online_rank = Some(*count),
+            LiveEvent::WatchedChange { num, .. } => self.watched = Some(*num),
+            _ => {}
+        }
+        if let Some((kind, spans)) = event_spans(event) {
+            let user = event
+                .uid()
+                .zip(event.user_name())
+                .map(|(uid, name)| (uid, name.to_string()));
+            self.add_entry(kind, spans, user);
+        }
+    }
+
+    pub fn push(&mut self, message: Message) {
+        match message {
+            Message::Connected => {
+                self.connection = "connected".to_string();
+                self.add_entry(
+                    Kind::System,
+                    vec![Span::styled(
+                        format!("已连接到直播间 {}", self.room_id),
+                        Color::Green,
+                    )],
+                    None,
+                );
+            }
+            Message::Disconnected(error) => {
+                self.connection = "reconnecting".to_string();
+                self.add_entry(
+                    Kind::System,
+                    vec![Span::styled(format!("连接断开: {}", error), Color::Red)],
+                    None,
+                );
+            }
+            Message::Popularity(popularity) => self.popularity = Some(popularity),
+            Message::Command(raw) => self.push_event(&LiveEvent::parse(raw)),
+            Message::Notice(notice) => {
+                self.add_entry(Kind::System, vec![Span::styled(notice, Color::Cyan)], None);
+            }
+        }
+    }
+
+    fn scroll(&mut self, lines: isize) {
+        let max = self.shown_len().saturating_sub(self.height);
+        self.offset = self.offset.saturating_add_signed(lines).min(max);
+    }
+
+    /// Moves the selection by `steps` towards older entries, selecting the
+    /// newest visible entry first. Scrolls to keep the selection in view.
+    fn select(&mut self, steps: isize) {
+        let shown = self
+            .entries
+            .iter()
+            .filter(|entry| self.is_shown(entry.kind))
+            .map(|entry| entry.seq)
+            .collect::<Vec<_>>();
+        if shown.is_empty() {
+            return;
+        }
+        let newest_visible = shown.len().saturating_sub(self.offset + 1);
+        let idx = match self
+            .selected
+            .and_then(|seq| shown.iter().position(|&s| s == seq))
+        {
+            Some(idx) => idx.saturating_add_signed(-steps).min(shown.len() - 1),
+            None => newest_visible,
+        };
+        self.selected = Some(shown[idx]);
+        self.paused = true;
+        let end = shown.len() - self.offset;
+        if idx >= end {
+            self.offset = shown.len() - idx - 1;
+        } else if idx + self.height < end {
+            self.offset = shown.len() - idx - self.height;
+        }
+    }
+
+    fn mute_selected(&mut self) {
+        let user = self
+            .selected
+            .and_then(|seq| self.entries.iter().find(|entry| entry.seq == seq))
+            .and_then(|entry| entry.user.clone());
+        let Some((uid, name)) = user else {
+            return;
+        };
+        if self.actions.send(Action::Mute { uid, name }).is_err() {
+            self.add_entry(
+                Kind::System,
+                vec![Span::styled("无法禁言", Color::Red)],
+                None,
+            );
+        }
+    }
+
+    fn handle_input(&mut self, keycode: KeyCode) {
+        let Some(input) = self.input.as_mut() else {
+            return;
+        };
+        match keycode {
+            KeyCode::Enter => {
+                let text = std::mem::take(input);
+                self.input = None;
+                if !text.trim().is_empty() && self.actions.send(Action::Send(text)).is_err() {
+                    self.add_entry(
+                        Kind::System,
+                        vec![Span::styled("无法发送弹幕", Color::Red)],
+                        None,
+                    );
+                }
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Backspace => drop(input.pop()),
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        let page = self.height.max(1) as isize;
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press && self.input.is_some() => {
+                self.handle_input(key.code)
+            }
+            Event::Paste(text) if self.input.is_some() => {
+                self.input.as_mut().unwrap().push_str(&text);
+            }
+            Event::Key(key) if key.kind == KeyEventKind::Press && self.selected.is_some() => {
+                match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => self.select(1),
+                    KeyCode::Down | KeyCode::Char('j') => self.select(-1),
+                    KeyCode::PageUp => self.select(page),
+                    KeyCode::PageDown => self.select(-page),
+                    KeyCode::Char('m') => self.mute_selected(),
+                    KeyCode::Char('s') | KeyCode::Esc => self.selected = None,
+                    KeyCode::Char('q') => self.quit = true,
+                    _ => {}
+                }
+            }
+            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Char('s') => self.select(0),
+                KeyCode::Char('i') | KeyCode::Enter => self.input = Some(String::new()),
+                KeyCode::Char(' ') | KeyCode::Char('p') => {
+                    self.paused = !self.paused;
+                    if !self.paused {
+                        self.offset = 0;
+                    }
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.scroll(1),
+                KeyCode::Down | KeyCode::Char('j') => self.scroll(-1),
+                KeyCode::PageUp => self.scroll(page),
+                KeyCode::PageDown => self.scroll(-page),
+                KeyCode::Home | KeyCode::Char('g') => self.scroll(isize::MAX),
+                KeyCode::End | KeyCode::Char('G') => self.offset = 0,
+                KeyCode::Char(c @ '1'..='5') => {
+                    let idx = c as usize - '1' as usize;
+                    self.shown[idx] = !self.shown[idx];
+                    self.offset = 0;
+                }
+                _ => {}
+            },
+            Event::Mouse(mouse) => match mouse.kind {
+                MouseEventKind::ScrollUp => self.scroll(3),
+                MouseEventKind::ScrollDown => self.scroll(-3),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let mut spans = vec![
+            Span::styled(
+                format!(" 直播间 {} ", self.room_id),
+                Style::new().reversed(),
+            ),
+            Span::raw(format!(" {}", self.connection)),
+        ];
+        if let Some(watched) = self.watched {
+            spans.push(Span::raw(format!(" · 看过 {}", watched)));
+        }
+        if let Some(online_rank) = self.online_rank {
+            spans.push(Span::raw(format!(" · 高能 {}", online_rank)));
+        }
+        if let Some(popularity) = self.popularity {
+            spans.push(Span::raw(format!(" · 人气 {}", popularity)));
+        }
+        if self.paused {
+            spans.push(Span::styled(
+                " · PAUSED",
+                Style::new().fg(Color::Yellow).bold(),
+            ));
+        } else if self.offset > 0 {
+            spans.push(Span::styled(
+                format!(" · ↑{}", self.offset),
+                Style::new().fg(Color::Yellow),
+            ));
+        }
+        Line::from(spans)
+    }
+
+    fn help_line(&self) -> Line<'static> {
+        let mut spans = vec![];
+        for (idx, (kind, shown)) in Kind::ALL.iter().zip(self.shown).enumerate() {
+            let style = if shown {
+                Style::new().fg(Color::Green)
+            } else {
+                Style::new().fg(Color::DarkGray).crossed_out()
+            };
+            spans.push(Span::styled(
+                format!("[{}]{} ", idx + 1, kind.label()),
+                style,
+            ));
+        }
+        spans.push(Span::styled(
+            if self.selected.is_some() {
+                " ↑↓ select · m mute · s/Esc done · q quit"
+            } else {
+                " i send · s select · space pause · ↑↓ PgUp PgDn scroll · q quit"
+            },
+            Color::DarkGray,
+        ));
+        Line::from(spans)
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        let input_height = if self.input.is_some() { 3 } else { 0 };
+        let [status_area, body_area, input_area, help_area] = Layout::new(
+            Direction::Vertical,
+            [
+                Constraint::Length(1),
+                Constraint::Fill(1),
+                Constraint::Length(input_height),
+                Constraint::Length(1),
+            ],
+        )
+        .areas(frame.size());
+        self.height = body_area.height.saturating_sub(2) as usize;
+
+        let shown = self
+            .entries
+            .iter()
+            .filter(|entry| self.is_shown(entry.kind))
+            .collect::<Vec<_>>();
+        let end = shown.len().saturating_sub(self.offset);
+        let start = end.saturating_sub(self.height);
+        let items = shown[start..end].iter().map(|entry| {
+            let mut spans = vec![Span::styled(
+                entry.time.format("%H:%M:%S ").to_string(),
+                Color::DarkGray,
+            )];
+            spans.extend(entry.spans.iter().cloned());
+            let line = Line::from(spans);
+            if self.selected == Some(entry.seq) {
+                line.style(Style::new().reversed())
+            } else {
+                line
+            }
+        });
+
+        frame.render_widget(Paragraph::new(self.status_line()), status_area);
+        frame.render_widget(List::new(items).block(Block::bordered()), body_area);
+        frame.render_widget(Paragraph::new(self.help_line()), help_area);
+        if let Some(input) = &self.input {
+            let line = Line::from(input.as_str());
+            let width = line.width() as u16;
+            frame.render_widget(
+                Paragraph::new(line)
+                    .block(Block::bordered().title("发送弹幕 (Enter 发送, Esc 取消)")),
+                input_area,
+            );
+            frame.set_cursor(
+                (input_area.x + 1 + width).min(input_area.right().saturating_sub(2)),
+                input_area.y + 1,
+            );
+        }
+    }
+
+    /// Draws, feeds terminal events and drains `rx` until the user quits.
+    pub fn run<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        rx: &mut mpsc::UnboundedReceiver<Message>,
+        mut next_event: impl FnMut() -> io::Result<Option<Event>>,
+    ) -> io::Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.render(frame))?;
+            if let Some(event) = next_event()? {
+                self.handle_event(event);
+            }
+            while let Ok(message) = rx.try_recv() {
+                self.push(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shows the chat of `room_id` until the user quits, blocking the thread.
+/// Danmaku typed into the input box are handed to `actions`.
+pub fn chat(
+    room_id: u64,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    actions: mpsc::UnboundedSender<Action>,
+) -> io::Result<()> {
+    init_error_hooks().map_err(|e| io::Error::other(e.to_string()))?;
+    let mut terminal = init_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    let result = ChatView::new(room_id, actions).run(&mut terminal, &mut rx, || {
+        if event::poll(Duration::from_millis(100))? {
+            event::read().map(Some)
+        } else {
+            Ok(None)
+        }
+    });
+    restore_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    result
+}
@@ -0,0 +1,82 @@
+use super::{init_error_hooks, init_terminal, restore_terminal};
+use crate::{cli, poll::Results};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style, Stylize},
+    terminal::Frame,
+    text::Line,
+    widgets::{Block, LineGauge, Paragraph},
+};
+use std::{io, time::Duration};
+use tokio::sync::mpsc;
+
+fn render(frame: &mut Frame, results: &Results) {
+    let [status_area, body_area, help_area] = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ],
+    )
+    .areas(frame.size());
+    let status = match results.closed {
+        true => format!("共 {} 票 · 投票已结束", results.total),
+        false => format!(
+            "共 {} 票 · 剩余 {}",
+            results.total,
+            cli::format_duration(results.remaining)
+        ),
+    };
+    frame.render_widget(Paragraph::new(status), status_area);
+
+    let block = Block::bordered().title(results.question.as_str());
+    let rows = Layout::new(
+        Direction::Vertical,
+        results.options.iter().map(|_| Constraint::Length(2)),
+    )
+    .split(block.inner(body_area));
+    frame.render_widget(block, body_area);
+    for (i, (option, row)) in results.options.iter().zip(rows.iter()).enumerate() {
+        let gauge = LineGauge::default()
+            .label(format!(
+                "{}. {}  {} 票 ({:.0}%)",
+                i + 1,
+                option.name,
+                option.votes,
+                option.percent
+            ))
+            .ratio(option.percent / 100.0)
+            .filled_style(Style::new().fg(Color::Cyan));
+        frame.render_widget(gauge, *row);
+    }
+    frame.render_widget(Paragraph::new(Line::from("q 退出".dark_gray())), help_area);
+}
+
+/// Shows the standings sent on `rx` until the user quits, blocking the
+/// thread.
+pub fn poll(mut rx: mpsc::UnboundedReceiver<Results>) -> io::Result<()> {
+    init_error_hooks().map_err(|e| io::Error::other(e.to_string()))?;
+    let mut terminal = init_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    let result = (|| {
+        let Some(mut results) = rx.blocking_recv() else {
+            return Ok(());
+        };
+        loop {
+            terminal.draw(|frame| render(frame, &results))?;
+            if event::poll(Duration::from_millis(100))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            {
+                return Ok(());
+            }
+            while let Ok(latest) = rx.try_recv() {
+                results = latest;
+            }
+        }
+    })();
+    restore_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    result
+}
@@ -0,0 +1,166 @@
+use super::{init_error_hooks, init_terminal, restore_terminal};
+use crate::songs::{Command, Queue, Update};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style, Stylize},
+    terminal::Frame,
+    text::{Line, Span},
+    widgets::{Block, List, ListState, Paragraph},
+};
+use std::{io, time::Duration};
+use tokio::sync::mpsc;
+
+struct SongsView {
+    queue: Queue,
+    notice: Option<String>,
+    list: ListState,
+    commands: mpsc::UnboundedSender<Command>,
+    quit: bool,
+}
+
+impl SongsView {
+    fn selected(&self) -> Option<usize> {
+        self.list
+            .selected()
+            .filter(|&idx| idx < self.queue.waiting.len())
+    }
+
+    fn push(&mut self, update: Update) {
+        match update {
+            Update::Queue(queue) => {
+                self.queue = queue;
+                let last = self.queue.waiting.len().checked_sub(1);
+                self.list.select(
+                    self.list
+                        .selected()
+                        .zip(last)
+                        .map(|(idx, last)| idx.min(last)),
+                );
+            }
+            Update::Notice(notice) => self.notice = Some(notice),
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) {
+        let len = self.queue.waiting.len();
+        let send = |command| {
+            let _ = self.commands.send(command);
+        };
+        match (code, self.selected()) {
+            (KeyCode::Char('q') | KeyCode::Esc, _) => self.quit = true,
+            (KeyCode::Char('n'), _) => send(Command::Skip),
+            (KeyCode::Down | KeyCode::Char('j'), selected) if len > 0 => {
+                self.list
+                    .select(Some(selected.map_or(0, |idx| (idx + 1).min(len - 1))));
+            }
+            (KeyCode::Up | KeyCode::Char('k'), selected) if len > 0 => {
+                self.list
+                    .select(Some(selected.map_or(0, |idx| idx.saturating_sub(1))));
+            }
+            (KeyCode::Char('d') | KeyCode::Delete, Some(idx)) => send(Command::Remove(idx + 1)),
+            (KeyCode::Char('t'), Some(idx)) => {
+                send(Command::Move {
+                    from: idx + 1,
+                    to: 1,
+                });
+                self.list.select(Some(0));
+            }
+            (KeyCode::Char('K'), Some(idx)) if idx > 0 => {
+                send(Command::Move {
+                    from: idx + 1,
+                    to: idx,
+                });
+                self.list.select(Some(idx - 1));
+            }
+            (KeyCode::Char('J'), Some(idx)) if idx + 1 < len => {
+                send(Command::Move {
+                    from: idx + 1,
+                    to: idx + 2,
+                });
+                self.list.select(Some(idx + 1));
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [current_area, body_area, notice_area, help_area] = Layout::new(
+            Direction::Vertical,
+            [
+                Constraint::Length(1),
+                Constraint::Fill(1),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ],
+        )
+        .areas(frame.size());
+        let current = match &self.queue.current {
+            Some(song) => Line::from(vec![
+                Span::raw("正在播放 "),
+                Span::styled(song.name.as_str(), Color::Cyan),
+                Span::styled(format!(" ({})", song.user), Color::DarkGray),
+            ]),
+            None => Line::from("正在播放 -"),
+        };
+        frame.render_widget(Paragraph::new(current), current_area);
+
+        let items = self.queue.waiting.iter().enumerate().map(|(i, song)| {
+            let mut spans = vec![
+                Span::raw(format!("{:>2}. ", i + 1)),
+                Span::raw(song.name.as_str()),
+                Span::styled(format!(" ({})", song.user), Color::DarkGray),
+            ];
+            if song.priority {
+                spans.push(Span::styled(" ★", Color::Yellow));
+            }
+            Line::from(spans)
+        });
+        let list = List::new(items)
+            .block(Block::bordered().title(format!("歌单 {} 首", self.queue.waiting.len())))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, body_area, &mut self.list);
+
+        if let Some(notice) = &self.notice {
+            frame.render_widget(Paragraph::new(notice.as_str().yellow()), notice_area);
+        }
+        frame.render_widget(
+            Paragraph::new("↑↓ 选择  n 切歌  d 删除  t 置顶  K/J 上移/下移  q 退出".dark_gray()),
+            help_area,
+        );
+    }
+}
+
+/// Shows the song queue sent on `rx` until the user quits, blocking the
+/// thread. Keys that change the queue are handed to `commands`.
+pub fn songs(
+    mut rx: mpsc::UnboundedReceiver<Update>,
+    commands: mpsc::UnboundedSender<Command>,
+) -> io::Result<()> {
+    init_error_hooks().map_err(|e| io::Error::other(e.to_string()))?;
+    let mut terminal = init_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    let mut view = SongsView {
+        queue: Queue::default(),
+        notice: None,
+        list: ListState::default(),
+        commands,
+        quit: false,
+    };
+    let result = (|| {
+        while !view.quit {
+            terminal.draw(|frame| view.render(frame))?;
+            if event::poll(Duration::from_millis(100))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                view.handle_key(key.code);
+            }
+            while let Ok(update) = rx.try_recv() {
+                view.push(update);
+            }
+        }
+        Ok(())
+    })();
+    restore_terminal().map_err(|e| io::Error::other(e.to_string()))?;
+    result
+}
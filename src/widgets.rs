@@ -0,0 +1,307 @@
+use crate::{
+    cli,
+    event::{Event, InteractKind},
+    live::RoomInfo,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+/// Widget names with their default templates, each widget is written to
+/// `<name>.txt` with `{key}` placeholders filled in.
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("latest_follower", "{name}"),
+    ("latest_super_chat", "{name} ¥{price}: {message}"),
+    ("top_gifter", "{name} ¥{cny}"),
+    ("viewers", "{count}"),
+    ("uptime", "{uptime}"),
+    ("title", "{title}"),
+    ("area", "{parent_area} - {area}"),
+];
+
+pub struct Widgets {
+    dir: PathBuf,
+    templates: HashMap<&'static str, String>,
+    values: HashMap<&'static str, Vec<(&'static str, String)>>,
+    written: HashMap<&'static str, String>,
+    /// CNY spent per uid this session
+    gifters: HashMap<u64, (String, f64)>,
+    live_time: Option<i64>,
+    /// start time of the live the gifters belong to, kept when it ends
+    session: Option<i64>,
+    /// the viewer count came from a live event, which beats polling
+    viewers_from_event: bool,
+}
+
+impl Widgets {
+    pub fn new(dir: PathBuf, templates: &BTreeMap<String, String>) -> Widgets {
+        let templates = DEFAULT_TEMPLATES
+            .iter()
+            .map(|&(name, template)| {
+                let template = templates.get(name).map_or(template, String::as_str);
+                (name, template.to_string())
+            })
+            .collect();
+        Widgets {
+            dir,
+            templates,
+            values: HashMap::new(),
+            written: HashMap::new(),
+            gifters: HashMap::new(),
+            live_time: None,
+            session: None,
+            viewers_from_event: false,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::Interact(interact)
+                if matches!(
+                    interact.kind,
+                    InteractKind::Follow | InteractKind::SpecialFollow | InteractKind::MutualFollow
+                ) =>
+            {
+                self.values.insert(
+                    "latest_follower",
+                    vec![
+                        ("name", interact.user.name.clone()),
+                        ("uid", interact.user.uid.to_string()),
+                    ],
+                );
+            }
+            Event::SuperChat(sc) => {
+                self.values.insert(
+                    "latest_super_chat",
+                    vec![
+                        ("name", sc.user.name.clone()),
+                        ("uid", sc.user.uid.to_string()),
+                        ("price", sc.price.to_string()),
+                        ("message", sc.message.clone()),
+                    ],
+                );
+            }
+            Event::OnlineRankCount {
+                online_count: Some(count),
+                ..
+            } => {
+                self.viewers_from_event = true;
+                self.values
+                    .insert("viewers", vec![("count", count.to_string())]);
+            }
+            Event::Live { live_time } => {
+                self.live_time = live_time.or(self.live_time);
+                if let Some(live_time) = live_time {
+                    self.start_session(*live_time);
+                }
+            }
+            Event::Preparing => self.live_time = None,
+            Event::RoomChange(change) => {
+                self.values
+                    .insert("title", vec![("title", change.title.clone())]);
+                self.values.insert(
+                    "area",
+                    vec![
+                        ("area", change.area_name.clone()),
+                        ("parent_area", change.parent_area_name.clone()),
+                    ],
+                );
+            }
+            _ => {}
+        }
+        if let (Some(cny), Some(uid), Some(name)) = (event.cny(), event.uid(), event.user_name()) {
+            let gifter = self.gifters.entry(uid).or_insert((String::new(), 0.0));
+            gifter.0 = name.to_string();
+            gifter.1 += cny;
+            if let Some((&uid, (name, cny))) = self
+                .gifters
+                .iter()
+                .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+            {
+                self.values.insert(
+                    "top_gifter",
+                    vec![
+                        ("name", name.clone()),
+                        ("uid", uid.to_string()),
+                        ("cny", format!("{:.1}", cny)),
+                    ],
+                );
+            }
+        }
+    }
+
+    /// Counts gifts anew when a live with another start time begins, `LIVE`
+    /// is sent again on reconnects.
+    fn start_session(&mut self, live_time: i64) {
+        if self.session.is_some_and(|session| session != live_time) {
+            self.gifters.clear();
+            self.values.remove("top_gifter");
+        }
+        self.session = Some(live_time);
+    }
+
+    /// Takes the polled room state, live events win where both give a value.
+    pub fn update_room(&mut self, room: &RoomInfo) {
+        self.live_time = room.live_time;
+        if let Some(live_time) = room.live_time {
+            self.start_session(live_time);
+        }
+        self.values
+            .insert("title", vec![("title", room.title.clone())]);
+        self.values.insert(
+            "area",
+            vec![
+                ("area", room.area_name.clone()),
+                ("parent_area", room.parent_area_name.clone()),
+            ],
+        );
+        if !self.viewers_from_event {
+            self.values
+                .insert("viewers", vec![("count", room.online.to_string())]);
+        }
+    }
+
+    /// Writes the widgets whose text changed, `now` in unix seconds.
+    pub fn write(&mut self, now: i64) -> std::io::Result<()> {
+        let uptime = self
+            .live_time
+            .map(|live_time| cli::format_duration((now - live_time).max(0)))
+            .unwrap_or_default();
+        self.values.insert("uptime", vec![("uptime", uptime)]);
+        std::fs::create_dir_all(&self.dir)?;
+        for (name, template) in &self.templates {
+            let text = match self.values.get(name) {
+                Some(values) => values.iter().fold(template.clone(), |text, (key, value)| {
+                    text.replace(&format!("{{{}}}", key), value)
+                }),
+                None => String::new(),
+            };
+            if self.written.get(name) != Some(&text) {
+                std::fs::write(self.dir.join(format!("{}.txt", name)), &text)?;
+                self.written.insert(name, text);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `tests/fixtures/events/<name>.json`.
+    fn fixture(name: &str) -> Event {
+        let path = format!(
+            "{}/tests/fixtures/events/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        Event::parse(serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap())
+    }
+
+    fn room(live_time: Option<i64>, online: u64) -> RoomInfo {
+        RoomInfo {
+            uid: 67890,
+            title: "标题".to_string(),
+            living: live_time.is_some(),
+            live_time,
+            area_id: 236,
+            area_name: "主机游戏".to_string(),
+            parent_area_name: "单机游戏".to_string(),
+            online,
+            attention: 1000,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bili-live-widgets-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read(dir: &std::path::Path, name: &str) -> String {
+        std::fs::read_to_string(dir.join(format!("{}.txt", name))).unwrap()
+    }
+
+    #[test]
+    fn fills_templates() {
+        let dir = temp_dir("fill");
+        let templates = BTreeMap::from([
+            ("viewers".to_string(), "{count} watching".to_string()),
+            ("latest_follower".to_string(), "{name} ({uid})".to_string()),
+        ]);
+        let mut widgets = Widgets::new(dir.clone(), &templates);
+        widgets.update_room(&room(Some(1700000000), 12));
+        widgets.handle_event(&fixture("INTERACT_WORD"));
+        widgets.handle_event(&fixture("SUPER_CHAT_MESSAGE"));
+        widgets.handle_event(&fixture("SEND_GIFT"));
+        widgets.handle_event(&fixture("GUARD_BUY"));
+        widgets.write(1700003725).unwrap();
+
+        assert_eq!(read(&dir, "viewers"), "12 watching");
+        assert_eq!(read(&dir, "latest_follower"), "观众E (56789)");
+        assert_eq!(read(&dir, "latest_super_chat"), "观众C ¥30: 主播加油");
+        assert_eq!(read(&dir, "top_gifter"), "观众D ¥198.0");
+        assert_eq!(read(&dir, "uptime"), cli::format_duration(3725));
+        assert_eq!(read(&dir, "title"), "标题");
+        assert_eq!(read(&dir, "area"), "单机游戏 - 主机游戏");
+
+        // live events beat polling
+        widgets.handle_event(&fixture("ONLINE_RANK_COUNT"));
+        widgets.handle_event(&fixture("ROOM_CHANGE"));
+        widgets.update_room(&room(Some(1700000000), 13));
+        widgets.handle_event(&fixture("ROOM_CHANGE"));
+        widgets.write(1700003725).unwrap();
+        assert_eq!(read(&dir, "viewers"), "5678 watching");
+        assert_eq!(read(&dir, "title"), "新标题");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_only_changes() {
+        let dir = temp_dir("change");
+        let mut widgets = Widgets::new(dir.clone(), &BTreeMap::new());
+        widgets.write(0).unwrap();
+        // nothing known yet, every widget is empty
+        assert_eq!(read(&dir, "latest_follower"), "");
+        assert_eq!(read(&dir, "uptime"), "");
+
+        std::fs::remove_file(dir.join("title.txt")).unwrap();
+        std::fs::remove_file(dir.join("viewers.txt")).unwrap();
+        widgets.handle_event(&fixture("ONLINE_RANK_COUNT"));
+        widgets.write(0).unwrap();
+        assert!(!dir.join("title.txt").exists());
+        assert_eq!(read(&dir, "viewers"), "5678");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn top_gifter_per_live() {
+        let dir = temp_dir("gifter");
+        let mut widgets = Widgets::new(dir.clone(), &BTreeMap::new());
+        let live = |live_time| Event::Live {
+            live_time: Some(live_time),
+        };
+        widgets.handle_event(&live(1700000000));
+        widgets.handle_event(&fixture("SEND_GIFT"));
+        widgets.handle_event(&fixture("SEND_GIFT"));
+        widgets.write(1700000000).unwrap();
+        assert_eq!(read(&dir, "top_gifter"), "观众B ¥1.0");
+
+        // sent again on a reconnect, or seen by polling
+        widgets.handle_event(&live(1700000000));
+        widgets.update_room(&room(Some(1700000000), 0));
+        widgets.write(1700000000).unwrap();
+        assert_eq!(read(&dir, "top_gifter"), "观众B ¥1.0");
+
+        // the live ends and the next one starts
+        widgets.handle_event(&Event::Preparing);
+        widgets.update_room(&room(None, 0));
+        widgets.handle_event(&live(1700090000));
+        widgets.write(1700090000).unwrap();
+        assert_eq!(read(&dir, "top_gifter"), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}